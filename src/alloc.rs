@@ -1,10 +1,24 @@
+use std::alloc::Layout;
 use std::mem::{align_of, size_of};
+use std::sync::Arc;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use crate::shared::SharedBytes;
 use crate::value::Value;
 
 /// Initial size for a new heap. Kept small so that actors are cheap to
 /// spawn, since each one owns a heap. Heaps grow as needed.
 pub const INIT_SIZE: usize = 4 * 1024 * 1024;
 
+/// Size of the nursery, which every actor allocates its new objects out
+/// of. Collecting it only has to copy what survived since the last cycle,
+/// so it is kept small to make those collections short.
+pub const NURSERY_SIZE: usize = 1024 * 1024;
+
+/// Size from which a bytearray keeps its bytes in an external table, outside
+/// of the heap. The collector leaves those where they are, so that frame
+/// and audio buffers are not copied on every cycle.
+pub const LARGE_OBJECT_SIZE: usize = 64 * 1024;
+
 /// Initial size of a message allocator. These grow on demand, so this
 /// only has to cover ordinary message traffic.
 pub const MSG_INIT_SIZE: usize = 2 * 1024 * 1024;
@@ -12,8 +26,11 @@ pub const MSG_INIT_SIZE: usize = 2 * 1024 * 1024;
 /// Address space reserved for a message allocator. A message allocator
 /// cannot be re-reserved while it holds messages, so it reserves enough
 /// up front to grow into. Reserving costs address space but no memory,
-/// and this is what bounds how large a single message can be.
-pub const MSG_RESERVE_SIZE: usize = 16 * 1024 * 1024 * 1024;
+/// and this is what bounds how large a single message can be. Every actor
+/// has one, so this is kept small enough for tens of thousands of actors
+/// to fit in the address space together. Large bytearrays don't count,
+/// since their bytes are kept outside of the allocator.
+pub const MSG_RESERVE_SIZE: usize = 1024 * 1024 * 1024;
 
 /// Alignment of every allocation
 const ALIGN: usize = 8;
@@ -34,22 +51,45 @@ pub enum Tag
     ByteArray,
     Dict,
 
+    // Bytearray whose bytes are outside of the heap, shared between actors
+    SharedByteArray,
+
     // Captured variable, holds a single value
     Cell,
 
+    // Weak reference, holds a single value that the collector clears once
+    // nothing else references it
+    WeakRef,
+
     // Boxed numbers that don't fit in an immediate value
     Int64,
     Float64,
 
+    // Integer too large for an Int64, with its limbs stored inline
+    BigInt,
+
     // Tables, referenced by the objects above
     ValueTable,
     SlotTable,
     Bytes,
+
+    // Table of bytes allocated outside of the heap
+    ExtBytes,
 }
 
 impl Tag
 {
-    const LAST: u8 = Tag::Bytes as u8;
+    const LAST: u8 = Tag::ExtBytes as u8;
+
+    /// Number of tags, counting the unused zero, so that an array indexed
+    /// by tag has room for all of them
+    pub const COUNT: usize = Tag::LAST as usize + 1;
+
+    /// Every tag, in order
+    pub fn all() -> impl Iterator<Item = Tag>
+    {
+        (1..=Tag::LAST).map(Tag::from_u8)
+    }
 
     fn from_u8(val: u8) -> Tag
     {
@@ -60,10 +100,11 @@ impl Tag
 
 /// Header word preceding every allocation.
 ///
-/// Bit 0 set means the block is live: bits 1..8 hold the tag and bits
-/// 8..64 the payload size. Bit 0 clear means the block has been copied
-/// and the word is the address the payload moved to, which is aligned
-/// and so always has bit 0 clear.
+/// Bit 0 set means the block is live: bits 1..7 hold the tag, bit 7 says
+/// whether the block is in the remembered set, and bits 8..64 hold the
+/// payload size. Bit 0 clear means the block has been copied and the word
+/// is the address the payload moved to, which is aligned and so always
+/// has bit 0 clear.
 #[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
 pub struct Header(u64);
@@ -71,6 +112,9 @@ pub struct Header(u64);
 /// Size of the header preceding every allocation
 pub const HEADER_SIZE: usize = size_of::<Header>();
 
+/// Header bit marking a block of the old space that may reference the nursery
+const REMEMBERED_BIT: u64 = 1 << 7;
+
 impl Header
 {
     fn new(tag: Tag, size: usize) -> Self
@@ -94,7 +138,14 @@ impl Header
     pub fn tag(&self) -> Tag
     {
         debug_assert!(!self.is_forwarded());
-        Tag::from_u8(((self.0 >> 1) & 0x7F) as u8)
+        Tag::from_u8(((self.0 >> 1) & 0x3F) as u8)
+    }
+
+    /// Whether the block is in the remembered set
+    pub fn is_remembered(&self) -> bool
+    {
+        debug_assert!(!self.is_forwarded());
+        self.0 & REMEMBERED_BIT != 0
     }
 
     /// Payload size in bytes, already rounded up to the alignment
@@ -118,6 +169,14 @@ pub fn set_forwarded(payload: *mut u8, new_payload: *mut u8)
     unsafe { *(payload as *mut Header).sub(1) = Header(new_payload as u64) };
 }
 
+/// Mark a block as being in the remembered set, or no longer in it
+pub fn set_remembered(payload: *mut u8, remembered: bool)
+{
+    let hdr = header_of(payload);
+    let word = if remembered { hdr.0 | REMEMBERED_BIT } else { hdr.0 & !REMEMBERED_BIT };
+    unsafe { *(payload as *mut Header).sub(1) = Header(word) };
+}
+
 /// Put back a header that was overwritten with a forwarding address
 pub fn restore_header(payload: *mut u8, header: Header)
 {
@@ -137,7 +196,7 @@ pub struct Alloc
 
     // Size of the reserved address range
     reserve_size: usize,
-
+    // Size of the region that allocations can be made in
     // Size of the committed, accessible region
     mem_size: usize,
 
@@ -150,6 +209,23 @@ pub struct Alloc
     growable: bool,
 
     next_idx: usize,
+
+    // Byte tables allocated outside of the heap, which large byte arrays
+    // and byte arrays moved between actors keep their bytes in. They are
+    // never copied: they belong to the heap that registered them, and are
+    // freed along with it unless the collector hands them over to the
+    // to-space.
+    ext_tables: HashSet<usize>,
+
+    // Total size of the external tables, counting their headers
+    ext_bytes: usize,
+
+    // Shared bytes referenced by shared bytearrays in this heap, which
+    // are kept alive for as long as the heap holds on to them
+    shared: HashMap<usize, Arc<SharedBytes>>,
+
+    // Total size of the shared bytes kept alive
+    shared_bytes: usize,
 }
 
 /// Round a size up to a multiple of the page size
@@ -164,15 +240,18 @@ fn page_round_up(size: usize, page_size: usize) -> usize
     }
 }
 
-/// Reserve a range of address space. PROT_NONE means that none of
-/// it is accessible until it is committed.
+/// Reserve a range of address space. The range is mapped in one piece,
+/// so that an allocator stays a single mapping however much of it is in
+/// use. Every actor has several allocators, and the number of mappings a
+/// process can have is limited. MAP_NORESERVE keeps the range from being
+/// counted as committed memory: pages only take up memory once touched.
 fn reserve_range(size: usize) -> *mut u8
 {
     let p = unsafe { libc::mmap(
         std::ptr::null_mut(),
         size,
-        libc::PROT_NONE,
-        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
         -1,
         0
     )};
@@ -219,6 +298,10 @@ impl Alloc
             page_size,
             growable,
             next_idx: 0,
+            ext_tables: HashSet::default(),
+            ext_bytes: 0,
+            shared: HashMap::default(),
+            shared_bytes: 0,
         };
 
         alloc.grow(mem_size_bytes);
@@ -294,18 +377,9 @@ impl Alloc
             new_size
         );
 
-        // Make the new pages accessible. They are zero-filled by the
-        // kernel on first touch, so there is nothing to clear here.
-        let ret = unsafe { libc::mprotect(
-            self.mem_block.add(self.mem_size) as *mut libc::c_void,
-            new_size - self.mem_size,
-            libc::PROT_READ | libc::PROT_WRITE
-        )};
-
-        if ret != 0 {
-            panic!("could not commit memory for the heap");
-        }
-
+        // The pages are already mapped. Past the old size, they were either
+        // never touched or released by shrink_to, so they are zero-filled
+        // by the kernel on first touch, and there is nothing to clear here.
         self.mem_size = new_size;
     }
 
@@ -323,18 +397,15 @@ impl Alloc
             return;
         }
 
-        // Replacing the mapping releases the physical pages. The range
-        // stays reserved, and reads back as zero if committed again.
-        let p = unsafe { libc::mmap(
+        // Release the physical pages. The range stays mapped, without
+        // splitting the mapping, and reads back as zero if grown again.
+        let ret = unsafe { libc::madvise(
             self.mem_block.add(new_size) as *mut libc::c_void,
             self.mem_size - new_size,
-            libc::PROT_NONE,
-            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
-            -1,
-            0
+            libc::MADV_DONTNEED
         )};
 
-        if p == libc::MAP_FAILED {
+        if ret != 0 {
             panic!("could not release memory from the heap");
         }
 
@@ -351,6 +422,7 @@ impl Alloc
     pub fn reset(&mut self)
     {
         self.next_idx = 0;
+        self.release_shared();
     }
 
     /// Zero the bytes between the allocation point and a given offset.
@@ -384,7 +456,6 @@ impl Alloc
     /// An empty block has its payload one past the last byte allocated,
     /// so it is the header that has to be inside the region, not the
     /// address itself.
-    #[allow(dead_code)] // used by the verify_gc heap walk
     pub fn contains(&self, p: *const u8) -> bool
     {
         let addr = p as usize;
@@ -473,6 +544,101 @@ impl Alloc
         p
     }
 
+    /// Register an external table, which this allocator then owns
+    pub fn adopt_ext_table(&mut self, p: *mut u8)
+    {
+        debug_assert!(header_of(p).tag() == Tag::ExtBytes);
+
+        if self.ext_tables.insert(p as usize) {
+            self.ext_bytes += HEADER_SIZE + header_of(p).size();
+        }
+    }
+
+    /// Unregister an external table, so that it can be handed over to
+    /// another allocator. Returns false if this allocator doesn't own it.
+    pub fn release_ext_table(&mut self, p: *mut u8) -> bool
+    {
+        if !self.ext_tables.remove(&(p as usize)) {
+            return false;
+        }
+
+        self.ext_bytes -= HEADER_SIZE + header_of(p).size();
+        true
+    }
+
+    /// Whether an external table is owned by this allocator
+    #[allow(dead_code)] // used by the verify_gc heap walk
+    pub fn owns_ext_table(&self, p: *const u8) -> bool
+    {
+        self.ext_tables.contains(&(p as usize))
+    }
+
+    /// Total size of the external tables this allocator owns
+    pub fn ext_bytes(&self) -> usize
+    {
+        self.ext_bytes
+    }
+
+    /// Free the external tables that were not handed over to another
+    /// allocator, which the collector does with those that are live
+    pub fn free_ext_tables_except(&mut self, live: &Alloc)
+    {
+        for p in self.ext_tables.drain() {
+            if !live.ext_tables.contains(&p) {
+                free_ext_table(p as *mut u8);
+            }
+        }
+
+        self.ext_bytes = 0;
+    }
+
+    /// Keep shared bytes alive for as long as this heap holds
+    /// a shared bytearray referencing them
+    pub fn retain_shared(&mut self, bytes: Arc<SharedBytes>)
+    {
+        let key = Arc::as_ptr(&bytes) as usize;
+
+        if !self.shared.contains_key(&key) {
+            self.shared_bytes += bytes.num_bytes();
+            self.shared.insert(key, bytes);
+        }
+    }
+
+    /// Keep shared bytes alive, given only a pointer to them, which has
+    /// to come from a shared bytearray in a heap that still retains them
+    pub fn retain_shared_ptr(&mut self, p: *const SharedBytes)
+    {
+        if !self.shared.contains_key(&(p as usize)) {
+            let bytes = unsafe {
+                Arc::increment_strong_count(p);
+                Arc::from_raw(p)
+            };
+            self.retain_shared(bytes);
+        }
+    }
+
+    /// Whether this heap keeps some shared bytes alive
+    #[allow(dead_code)] // used by the verify_gc heap walk
+    pub fn retains_shared(&self, p: *const SharedBytes) -> bool
+    {
+        self.shared.contains_key(&(p as usize))
+    }
+
+    /// Stop keeping shared bytes alive, once the heap no longer
+    /// holds any of the shared bytearrays it had
+    pub fn release_shared(&mut self)
+    {
+        self.shared.clear();
+        self.shared_bytes = 0;
+    }
+
+    /// Size of what the heap keeps alive outside of itself, which its
+    /// own size doesn't account for: external tables and shared bytes
+    pub fn outside_bytes(&self) -> usize
+    {
+        self.ext_bytes + self.shared_bytes
+    }
+
     /// Box an integer that is too large to be a fixnum
     pub fn heap_int64(&mut self, val: i64) -> Value
     {
@@ -493,10 +659,94 @@ impl Alloc
 unsafe impl Send for Alloc {}
 unsafe impl Sync for Alloc {}
 
+/// Memory layout of an external table with a given payload size
+fn ext_layout(size: usize) -> Layout
+{
+    Layout::from_size_align(HEADER_SIZE + size, ALIGN).unwrap()
+}
+
+/// Free an external table, which no allocator owns any more
+fn free_ext_table(p: *mut u8)
+{
+    let size = header_of(p).size();
+    unsafe { std::alloc::dealloc(p.sub(HEADER_SIZE), ext_layout(size)) };
+}
+
+/// Table of bytes allocated outside of any heap, while no allocator owns
+/// it. This is how the bytes of a byte array moved to another actor are
+/// handed over. The table is freed if it is dropped before being adopted.
+pub struct ExtTable(*mut u8);
+
+// The table is only ever accessed by whoever holds the handle
+unsafe impl Send for ExtTable {}
+
+impl ExtTable
+{
+    /// Allocate a zeroed table. It has a header like any block.
+    pub fn new(num_bytes: usize) -> Self
+    {
+        match Self::try_new(num_bytes) {
+            Some(table) => table,
+            None => std::alloc::handle_alloc_error(ext_layout(align_up(num_bytes))),
+        }
+    }
+
+    /// Allocate a zeroed table, or return None if there is no memory for
+    /// it. This is for sizes that come from the program, which may ask for
+    /// more than the system has.
+    pub fn try_new(num_bytes: usize) -> Option<Self>
+    {
+        let size = num_bytes.checked_add(ALIGN - 1)? & !(ALIGN - 1);
+        let layout = Layout::from_size_align(size.checked_add(HEADER_SIZE)?, ALIGN).ok()?;
+
+        unsafe {
+            let block = std::alloc::alloc_zeroed(layout);
+            if block.is_null() {
+                return None;
+            }
+            std::ptr::write(block as *mut Header, Header::new(Tag::ExtBytes, size));
+            Some(ExtTable(block.add(HEADER_SIZE)))
+        }
+    }
+
+    /// Take ownership of a table released by an allocator
+    pub fn from_raw(p: *mut u8) -> Self
+    {
+        debug_assert!(header_of(p).tag() == Tag::ExtBytes);
+        ExtTable(p)
+    }
+
+    /// Give up ownership of the table, once an allocator has adopted it
+    pub fn into_raw(self) -> *mut u8
+    {
+        let p = self.0;
+        std::mem::forget(self);
+        p
+    }
+
+    /// The bytes of the table, including any padding up to its capacity
+    pub fn as_slice_ptr(&self) -> *mut [u8]
+    {
+        std::ptr::slice_from_raw_parts_mut(self.0, header_of(self.0).size())
+    }
+}
+
+impl Drop for ExtTable
+{
+    fn drop(&mut self)
+    {
+        free_ext_table(self.0);
+    }
+}
+
 impl Drop for Alloc
 {
     fn drop(&mut self)
     {
+        for p in self.ext_tables.drain() {
+            free_ext_table(p as *mut u8);
+        }
+
         // Release the whole reserved range
         unsafe { libc::munmap(self.mem_block as *mut libc::c_void, self.reserve_size) };
     }
@@ -708,6 +958,24 @@ mod tests
         assert!(header_of(p).size() == 24);
     }
 
+    /// Remembering a block must not change what its header says about it
+    #[test]
+    fn remembered_bit_round_trips()
+    {
+        let mut alloc = Alloc::with_size(INIT_SIZE);
+        let p = alloc.alloc_bytes(24, Tag::SlotTable);
+        assert!(!header_of(p).is_remembered());
+
+        set_remembered(p, true);
+        assert!(header_of(p).is_remembered());
+        assert!(header_of(p).tag() == Tag::SlotTable);
+        assert!(header_of(p).size() == 24);
+
+        set_remembered(p, false);
+        assert!(!header_of(p).is_remembered());
+        assert!(header_of(p).tag() == Tag::SlotTable);
+    }
+
     /// Tables start out zeroed and the collector scans every slot of the
     /// ones it walks, so zeroed memory has to read back as a value that
     /// holds no reference
@@ -213,6 +213,7 @@ pub fn array_push(actor: &mut Actor, mut array: Value, mut val: Value) -> Result
 
     let arr = array.as_arr();
     arr.push(val, &mut actor.alloc);
+    actor.remember(array);
     Ok(Value::NIL)
 }
 
@@ -241,6 +242,7 @@ pub fn array_insert(actor: &mut Actor, mut array: Value, mut idx: Value, mut val
     let arr = array.as_arr();
     let idx = unwrap_usize!(idx);
     arr.insert(idx, val, &mut actor.alloc);
+    actor.remember(array);
     Ok(Value::NIL)
 }
 
@@ -258,6 +260,7 @@ pub fn array_resize(actor: &mut Actor, mut array: Value, mut new_size: Value, mu
 
     let arr = array.as_arr();
     arr.resize(new_len, fill_val, &mut actor.alloc);
+    actor.remember(array);
     Ok(Value::NIL)
 }
 
@@ -277,5 +280,6 @@ pub fn array_append(actor: &mut Actor, mut self_array: Value, mut other_array: V
     let a0 = self_array.as_arr();
     let a1 = other_array.as_arr();
     a0.append(a1, &mut actor.alloc);
+    actor.remember(self_array);
     Ok(Value::NIL)
 }
@@ -197,10 +197,30 @@ pub enum Stmt
         body_stmt: StmtBox,
     },
 
+    // Loop over the elements of an iterable value
+    // With two variables, the first receives the index or key
+    ForIn {
+        var_names: Vec<String>,
+        iter_expr: ExprBox,
+        body_stmt: StmtBox,
+        decls: Vec<Decl>,
+    },
+
     Assert {
         test_expr: ExprBox,
     },
 
+    // Try block with a catch clause
+    Try {
+        body_stmt: StmtBox,
+        catch_var: String,
+        catch_stmt: StmtBox,
+        decl: Option<Decl>,
+    },
+
+    // Throw a value to the innermost enclosing try block
+    Throw(ExprBox),
+
     /// Local variable declaration
     Let {
         mutable: bool,
@@ -403,9 +423,15 @@ pub const OBJECT_ID: ClassId = ClassId(6);
 pub const ARRAY_ID: ClassId = ClassId(7);
 pub const BYTEARRAY_ID: ClassId = ClassId(8);
 pub const DICT_ID: ClassId = ClassId(9);
+pub const SHARED_BYTEARRAY_ID: ClassId = ClassId(10);
+pub const BIGINT_ID: ClassId = ClassId(11);
+pub const WEAKREF_ID: ClassId = ClassId(12);
 pub const UIEVENT_ID: ClassId = ClassId(100);
 pub const AUDIO_NEEDED_ID: ClassId = ClassId(101);
 pub const AUDIO_DATA_ID: ClassId = ClassId(102);
+pub const ERROR_ID: ClassId = ClassId(103);
+pub const RANGE_ID: ClassId = ClassId(104);
+pub const ACTOR_ERROR_ID: ClassId = ClassId(105);
 pub const LAST_RESERVED_ID: usize = 0xFF;
 
 #[derive(Default, Clone, Debug)]
@@ -6,6 +6,7 @@ use crate::object::Object;
 use crate::alloc::Alloc;
 use crate::ast::{AUDIO_NEEDED_ID, AUDIO_DATA_ID};
 use crate::window::with_sdl_context;
+use crate::permissions::Permission;
 use crate::*;
 
 // --- Audio Output ---
@@ -110,6 +111,8 @@ static AUDIO_OUT_PAIR: (Mutex<Option<OutputState>>, Condvar) = (Mutex::new(None)
 /// Open an audio output device
 pub fn audio_open_output(actor: &mut Actor, sample_rate: Value, num_channels: Value) -> Result<Value, String>
 {
+    actor.check_permission(Permission::Audio)?;
+
     {
         let (lock, _) = &AUDIO_OUT_PAIR;
         let audio_state = lock.lock().unwrap();
@@ -305,6 +308,8 @@ static AUDIO_IN_PAIR: (Mutex<Option<InputState>>, Condvar) = (Mutex::new(None),
 /// Open an audio input device
 pub fn audio_open_input(actor: &mut Actor, sample_rate: Value, num_channels: Value) -> Result<Value, String>
 {
+    actor.check_permission(Permission::Audio)?;
+
     {
         let (lock, _) = &AUDIO_IN_PAIR;
         let audio_state = lock.lock().unwrap();
@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+use std::mem::size_of;
+use crate::alloc::{Alloc, Tag, HEADER_SIZE};
+use crate::value::Value;
+
+/// Number of bits a shift may produce. Integers this large take a
+/// megabyte, and a shift is the one operation that can make one in a
+/// single step from small operands.
+pub const MAX_SHIFT_BITS: usize = 1 << 23;
+
+/// Integer too large for an Int64. The magnitude is stored inline, right
+/// after it, as 32-bit limbs with the least significant limb first.
+///
+/// Integer results that fit in an Int64 are never stored as a BigInt, so
+/// a BigInt is never equal to an Int64.
+#[repr(C, align(8))]
+pub struct BigInt
+{
+    neg: bool,
+    len: usize,
+}
+
+impl BigInt
+{
+    /// Bytes a bigint with a given number of limbs occupies, header included
+    pub fn alloc_size(num_limbs: usize) -> usize
+    {
+        HEADER_SIZE + size_of::<BigInt>() + num_limbs * size_of::<u32>()
+    }
+
+    /// Allocate a bigint holding a number too large for an Int64
+    pub fn from_num(num: &BigNum, alloc: &mut Alloc) -> Value
+    {
+        debug_assert!(num.to_i64().is_none());
+
+        let len = num.mag.len();
+        let p = alloc.alloc_var(BigInt { neg: num.neg, len }, len * size_of::<u32>(), Tag::BigInt);
+
+        unsafe {
+            let limbs = p.add(1) as *mut u32;
+            std::ptr::copy_nonoverlapping(num.mag.as_ptr(), limbs, len);
+        }
+
+        Value::bigint(p)
+    }
+
+    fn limbs(&self) -> &[u32]
+    {
+        unsafe {
+            let limbs = (self as *const BigInt).add(1) as *const u32;
+            std::slice::from_raw_parts(limbs, self.len)
+        }
+    }
+
+    /// Copy the number out of the heap, to compute with it
+    pub fn to_num(&self) -> BigNum
+    {
+        BigNum { neg: self.neg, mag: self.limbs().to_vec() }
+    }
+}
+
+/// Arbitrary-precision integer that arithmetic is done on, outside of the
+/// heap. The magnitude has no leading zero limbs, and zero is never negative,
+/// so that equal numbers are represented the same way.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigNum
+{
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+impl BigNum
+{
+    pub fn from_i64(val: i64) -> BigNum
+    {
+        let abs = val.unsigned_abs();
+        BigNum::new(val < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    fn new(neg: bool, mut mag: Vec<u32>) -> BigNum
+    {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+
+        let neg = neg && !mag.is_empty();
+        BigNum { neg, mag }
+    }
+
+    pub fn to_i64(&self) -> Option<i64>
+    {
+        if self.mag.len() > 2 {
+            return None;
+        }
+
+        let abs = self.mag.iter().rev().fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+
+        if self.neg {
+            0i64.checked_sub_unsigned(abs)
+        } else {
+            i64::try_from(abs).ok()
+        }
+    }
+
+    /// Nearest double, or an infinity past the double range
+    pub fn to_f64(&self) -> f64
+    {
+        let abs = self.mag.iter().rev().fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.neg { -abs } else { abs }
+    }
+
+    /// Number of bits needed to write the magnitude
+    pub fn num_bits(&self) -> usize
+    {
+        match self.mag.last() {
+            Some(top) => self.mag.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool
+    {
+        self.mag.is_empty()
+    }
+
+    pub fn neg(&self) -> BigNum
+    {
+        BigNum::new(!self.neg, self.mag.clone())
+    }
+
+    pub fn abs(&self) -> BigNum
+    {
+        BigNum::new(false, self.mag.clone())
+    }
+
+    pub fn add(&self, other: &BigNum) -> BigNum
+    {
+        if self.neg == other.neg {
+            return BigNum::new(self.neg, mag_add(&self.mag, &other.mag));
+        }
+
+        // Signs differ, so the smaller magnitude comes off the larger one
+        match mag_cmp(&self.mag, &other.mag) {
+            Ordering::Less => BigNum::new(other.neg, mag_sub(&other.mag, &self.mag)),
+            _ => BigNum::new(self.neg, mag_sub(&self.mag, &other.mag)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigNum) -> BigNum
+    {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigNum) -> BigNum
+    {
+        BigNum::new(self.neg != other.neg, mag_mul(&self.mag, &other.mag))
+    }
+
+    /// Quotient rounded toward zero, and the remainder, which has the sign
+    /// of the dividend, as for Int64. None when dividing by zero.
+    pub fn div_rem(&self, other: &BigNum) -> Option<(BigNum, BigNum)>
+    {
+        if other.is_zero() {
+            return None;
+        }
+
+        let (quot, rem) = mag_div_rem(&self.mag, &other.mag);
+        Some((
+            BigNum::new(self.neg != other.neg, quot),
+            BigNum::new(self.neg, rem),
+        ))
+    }
+
+    pub fn shl(&self, shift: usize) -> BigNum
+    {
+        let limb_shift = shift / 32;
+        let mut mag = vec![0; limb_shift];
+        mag.extend(shl_bits(&self.mag, (shift % 32) as u32, true));
+        BigNum::new(self.neg, mag)
+    }
+
+    /// Shift right, rounding toward negative infinity like an Int64 shift
+    pub fn shr(&self, shift: usize) -> BigNum
+    {
+        if !self.neg {
+            return BigNum::new(false, mag_shr(&self.mag, shift));
+        }
+
+        // -a >> s == -((a - 1) >> s) - 1
+        let one = [1];
+        let shifted = mag_shr(&mag_sub(&self.mag, &one), shift);
+        BigNum::new(true, mag_add(&shifted, &one))
+    }
+
+    pub fn bit_and(&self, other: &BigNum) -> BigNum
+    {
+        self.bitwise(other, |a, b| a & b)
+    }
+
+    pub fn bit_or(&self, other: &BigNum) -> BigNum
+    {
+        self.bitwise(other, |a, b| a | b)
+    }
+
+    pub fn bit_xor(&self, other: &BigNum) -> BigNum
+    {
+        self.bitwise(other, |a, b| a ^ b)
+    }
+
+    /// Apply a bitwise operation to the two's complement representations
+    fn bitwise(&self, other: &BigNum, op: fn(u32, u32) -> u32) -> BigNum
+    {
+        // One limb more than either magnitude leaves room for the sign
+        let len = std::cmp::max(self.mag.len(), other.mag.len()) + 1;
+        let a = self.twos_complement(len);
+        let b = other.twos_complement(len);
+
+        let mut limbs: Vec<u32> = a.iter().zip(&b).map(|(&a, &b)| op(a, b)).collect();
+
+        // The top bit of the result is its sign
+        let neg = limbs[len - 1] >> 31 == 1;
+        if neg {
+            for limb in limbs.iter_mut() {
+                *limb = !*limb;
+            }
+            limbs = mag_add(&limbs, &[1]);
+        }
+
+        BigNum::new(neg, limbs)
+    }
+
+    fn twos_complement(&self, len: usize) -> Vec<u32>
+    {
+        let mut limbs = self.mag.clone();
+        limbs.resize(len, 0);
+
+        if self.neg {
+            for limb in limbs.iter_mut() {
+                *limb = !*limb;
+            }
+            limbs = mag_add(&limbs, &[1]);
+            limbs.truncate(len);
+        }
+
+        limbs
+    }
+
+    /// Parse digits in a given radix, with an optional sign, like
+    /// i64::from_str_radix does
+    pub fn parse(s: &str, radix: u32) -> Option<BigNum>
+    {
+        let (neg, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+
+        if digits.is_empty() {
+            return None;
+        }
+
+        let mut mag = Vec::new();
+        for ch in digits.chars() {
+            let digit = ch.to_digit(radix)?;
+            mag = mag_mul_small(&mag, radix, digit);
+        }
+
+        Some(BigNum::new(neg, mag))
+    }
+
+    /// Format in a given radix, with lowercase digits past 9
+    pub fn to_string_radix(&self, radix: u32) -> String
+    {
+        debug_assert!((2..=36).contains(&radix));
+
+        if self.is_zero() {
+            return "0".to_string();
+        }
+
+        let mut digits = Vec::new();
+        let mut mag = self.mag.clone();
+
+        while !mag.is_empty() {
+            let (quot, rem) = mag_div_small(&mag, radix);
+            digits.push(std::char::from_digit(rem, radix).unwrap());
+            mag = quot;
+        }
+
+        if self.neg {
+            digits.push('-');
+        }
+
+        digits.iter().rev().collect()
+    }
+}
+
+impl PartialOrd for BigNum
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigNum
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => mag_cmp(&self.mag, &other.mag),
+            (true, true) => mag_cmp(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl std::fmt::Display for BigNum
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        write!(f, "{}", self.to_string_radix(10))
+    }
+}
+
+fn mag_cmp(a: &[u32], b: &[u32]) -> Ordering
+{
+    // Magnitudes have no leading zeros, so the longer one is larger
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn mag_add(a: &[u32], b: &[u32]) -> Vec<u32>
+{
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+
+    for (i, &limb) in a.iter().enumerate() {
+        let sum = limb as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+
+    if carry != 0 {
+        out.push(carry as u32);
+    }
+
+    out
+}
+
+/// Subtract a magnitude from one at least as large
+fn mag_sub(a: &[u32], b: &[u32]) -> Vec<u32>
+{
+    debug_assert!(mag_cmp(a, b) != Ordering::Less);
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, &limb) in a.iter().enumerate() {
+        let diff = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        out.push(diff as u32);
+        borrow = if diff < 0 { 1 } else { 0 };
+    }
+
+    out
+}
+
+fn mag_mul(a: &[u32], b: &[u32]) -> Vec<u32>
+{
+    let mut out = vec![0u32; a.len() + b.len()];
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+
+        for (j, &y) in b.iter().enumerate() {
+            let prod = x as u64 * y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = prod as u32;
+            carry = prod >> 32;
+        }
+
+        out[i + b.len()] = carry as u32;
+    }
+
+    out
+}
+
+/// Compute `a * mul + add` for single limb operands
+fn mag_mul_small(a: &[u32], mul: u32, add: u32) -> Vec<u32>
+{
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = add as u64;
+
+    for &limb in a {
+        let prod = limb as u64 * mul as u64 + carry;
+        out.push(prod as u32);
+        carry = prod >> 32;
+    }
+
+    if carry != 0 {
+        out.push(carry as u32);
+    }
+
+    out
+}
+
+/// Divide by a single limb, returning the quotient and the remainder
+fn mag_div_small(a: &[u32], div: u32) -> (Vec<u32>, u32)
+{
+    let mut quot = vec![0u32; a.len()];
+    let mut rem = 0u64;
+
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        quot[i] = (cur / div as u64) as u32;
+        rem = cur % div as u64;
+    }
+
+    while quot.last() == Some(&0) {
+        quot.pop();
+    }
+
+    (quot, rem as u32)
+}
+
+/// Shift left by less than a limb. The limb shifted out at the top is
+/// kept if `grow` is set, and dropped otherwise.
+fn shl_bits(a: &[u32], shift: u32, grow: bool) -> Vec<u32>
+{
+    if shift == 0 {
+        return a.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+
+    for &limb in a {
+        out.push((limb << shift) | carry);
+        carry = limb >> (32 - shift);
+    }
+
+    if grow && carry != 0 {
+        out.push(carry);
+    }
+
+    out
+}
+
+fn mag_shr(a: &[u32], shift: usize) -> Vec<u32>
+{
+    let limb_shift = shift / 32;
+    let bit_shift = (shift % 32) as u32;
+
+    if limb_shift >= a.len() {
+        return Vec::new();
+    }
+
+    let a = &a[limb_shift..];
+    if bit_shift == 0 {
+        return a.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(a.len());
+    for i in 0..a.len() {
+        let hi = a.get(i + 1).map_or(0, |&limb| limb << (32 - bit_shift));
+        out.push((a[i] >> bit_shift) | hi);
+    }
+
+    out
+}
+
+/// Long division of magnitudes, with algorithm D from Knuth's TAOCP
+/// volume 2, section 4.3.1
+fn mag_div_rem(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>)
+{
+    debug_assert!(!b.is_empty());
+
+    if mag_cmp(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    if b.len() == 1 {
+        let (quot, rem) = mag_div_small(a, b[0]);
+        return (quot, vec![rem]);
+    }
+
+    // Normalize so that the top limb of the divisor has its top bit set,
+    // which keeps the estimate of each quotient limb off by at most 2
+    let shift = b[b.len() - 1].leading_zeros();
+    let v = shl_bits(b, shift, false);
+    let mut u = shl_bits(a, shift, false);
+    u.push(if shift == 0 { 0 } else { a[a.len() - 1] >> (32 - shift) });
+
+    let n = v.len();
+    let m = a.len() - n;
+    let mut quot = vec![0u32; m + 1];
+    const BASE: u64 = 1 << 32;
+
+    for j in (0..=m).rev() {
+        // Estimate the quotient limb from the top limbs
+        let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+        let mut qhat = num / v[n - 1] as u64;
+        let mut rhat = num % v[n - 1] as u64;
+
+        while qhat >= BASE || qhat * v[n - 2] as u64 > ((rhat << 32) | u[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += v[n - 1] as u64;
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        // Multiply and subtract
+        let mut borrow = 0i64;
+        for i in 0..n {
+            let prod = qhat * v[i] as u64;
+            let diff = u[i + j] as i64 - borrow - (prod & 0xFFFF_FFFF) as i64;
+            u[i + j] = diff as u32;
+            borrow = (prod >> 32) as i64 - (diff >> 32);
+        }
+        let diff = u[j + n] as i64 - borrow;
+        u[j + n] = diff as u32;
+
+        // The estimate was one too large, so add the divisor back
+        if diff < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+
+        quot[j] = qhat as u32;
+    }
+
+    // Unnormalize the remainder
+    u.truncate(n);
+    let rem = mag_shr(&u, shift as usize);
+
+    (quot, rem)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn num(s: &str) -> BigNum
+    {
+        BigNum::parse(s, 10).unwrap()
+    }
+
+    #[test]
+    fn i64_round_trip()
+    {
+        for val in [0, 1, -1, i64::MAX, i64::MIN, 1 << 32, -(1 << 32)] {
+            assert_eq!(BigNum::from_i64(val).to_i64(), Some(val));
+        }
+
+        let past_max = BigNum::from_i64(i64::MAX).add(&BigNum::from_i64(1));
+        assert_eq!(past_max.to_i64(), None);
+        assert_eq!(past_max.neg().to_i64(), Some(i64::MIN));
+        assert_eq!(past_max.neg().sub(&BigNum::from_i64(1)).to_i64(), None);
+    }
+
+    #[test]
+    fn arith_against_i128()
+    {
+        let vals: [i128; 10] = [
+            0, 1, -1, 7, -12345,
+            i64::MAX as i128, i64::MIN as i128,
+            (1 << 70) + 3, -(1 << 90) - 12345678901234567,
+            170141183460469231731687303715884105,
+        ];
+
+        let big = |v: i128| BigNum::parse(&v.to_string(), 10).unwrap();
+
+        for &a in &vals {
+            for &b in &vals {
+                assert_eq!(big(a).add(&big(b)), big(a + b));
+                assert_eq!(big(a).sub(&big(b)), big(a - b));
+                assert_eq!(big(a).cmp(&big(b)), a.cmp(&b));
+                assert_eq!(big(a).bit_and(&big(b)), big(a & b));
+                assert_eq!(big(a).bit_or(&big(b)), big(a | b));
+                assert_eq!(big(a).bit_xor(&big(b)), big(a ^ b));
+
+                if let Some(prod) = a.checked_mul(b) {
+                    assert_eq!(big(a).mul(&big(b)), big(prod));
+                }
+
+                if b != 0 {
+                    let (quot, rem) = big(a).div_rem(&big(b)).unwrap();
+                    assert_eq!(quot, big(a / b));
+                    assert_eq!(rem, big(a % b));
+                }
+            }
+
+            for shift in [0, 1, 31, 32, 33, 64, 100] {
+                assert_eq!(big(a).shr(shift), big(a >> shift.min(127)));
+            }
+            assert_eq!(big(a).shl(5).shr(5), big(a));
+        }
+    }
+
+    #[test]
+    fn long_division()
+    {
+        let a = num("123456789012345678901234567890123456789012345678901234567890");
+        let b = num("987654321098765432109876543210");
+        let (quot, rem) = a.div_rem(&b).unwrap();
+        assert_eq!(quot.to_string(), "124999998860937500014238281249");
+        assert_eq!(quot.mul(&b).add(&rem), a);
+        assert!(rem < b);
+
+        // Quotient limbs whose first estimate is too large
+        let a = num("340282366920938463463374607431768211455");
+        let b = num("18446744073709551617");
+        let (quot, rem) = a.div_rem(&b).unwrap();
+        assert_eq!(quot.mul(&b).add(&rem), a);
+        assert!(rem < b);
+
+        assert!(a.div_rem(&BigNum::from_i64(0)).is_none());
+    }
+
+    #[test]
+    fn format_and_parse()
+    {
+        let a = num("-98765432109876543210987654321");
+        assert_eq!(a.to_string(), "-98765432109876543210987654321");
+        assert_eq!(BigNum::parse(&a.to_string_radix(16), 16), Some(a.clone()));
+        assert_eq!(BigNum::parse(&a.to_string_radix(2), 2), Some(a));
+
+        assert_eq!(BigNum::from_i64(255).to_string_radix(16), "ff");
+        assert_eq!(BigNum::from_i64(0).to_string(), "0");
+        assert_eq!(BigNum::parse("", 10), None);
+        assert_eq!(BigNum::parse("-", 10), None);
+        assert_eq!(BigNum::parse("12a", 10), None);
+    }
+}
@@ -1,9 +1,13 @@
 use std::mem::{transmute, size_of};
 use crate::vm::Actor;
 use crate::value::*;
-use crate::alloc::{Alloc, Tag, HEADER_SIZE};
+use crate::alloc::{Alloc, ExtTable, Tag, HEADER_SIZE, LARGE_OBJECT_SIZE};
 use crate::*;
 
+/// Table pointer of a bytearray that has none: one being allocated,
+/// or one whose bytes were moved to another actor
+const NO_TABLE: *mut [u8] = std::ptr::slice_from_raw_parts_mut(std::ptr::null_mut(), 0);
+
 pub struct ByteArray
 {
     // Relocated by the collector, which walks the table on its own
@@ -13,27 +17,78 @@ pub struct ByteArray
 
 impl ByteArray
 {
-    /// Bytes a bytearray of a given size occupies, counting the headers
-    /// of both the bytearray and its byte table
+    /// Bytes a bytearray of a given size occupies in the heap, counting
+    /// the headers of both the bytearray and its byte table
     pub fn alloc_size(num_bytes: usize) -> usize
     {
-        HEADER_SIZE + size_of::<ByteArray>() + HEADER_SIZE + num_bytes
+        HEADER_SIZE + size_of::<ByteArray>() + Self::table_size(num_bytes)
+    }
+
+    /// Bytes a byte table of a given size occupies in the heap. A large
+    /// one is kept outside of it, and takes up no space there.
+    fn table_size(num_bytes: usize) -> usize
+    {
+        if num_bytes >= LARGE_OBJECT_SIZE {
+            0
+        } else {
+            HEADER_SIZE + num_bytes
+        }
     }
 
-    /// Allocate a zeroed bytearray of a given size.
+    /// Bytes a byte table of a given size keeps outside of the heap,
+    /// which count against its limit all the same
+    pub fn outside_size(num_bytes: usize) -> usize
+    {
+        if num_bytes >= LARGE_OBJECT_SIZE {
+            HEADER_SIZE + num_bytes
+        } else {
+            0
+        }
+    }
+
+    /// Allocate a zeroed byte table. A large one gets an external table
+    /// of its own, which the collector leaves where it is instead of
+    /// copying it on every cycle. Returns None if the system has no
+    /// memory for such a table.
+    fn alloc_table(num_bytes: usize, alloc: &mut Alloc) -> Option<*mut [u8]>
+    {
+        if num_bytes < LARGE_OBJECT_SIZE {
+            return Some(alloc.alloc_table(num_bytes, Tag::Bytes));
+        }
+
+        let table = ExtTable::try_new(num_bytes)?;
+        let bytes = table.as_slice_ptr();
+        alloc.adopt_ext_table(table.into_raw());
+        Some(bytes)
+    }
+
+    /// Allocate a zeroed bytearray of a given size. Sizes that come from
+    /// the program go through try_with_size instead, which can fail.
+    pub fn with_size(num_bytes: usize, alloc: &mut Alloc) -> Value
+    {
+        match Self::try_with_size(num_bytes, alloc) {
+            Some(ba) => ba,
+            None => panic!("could not allocate a bytearray of {} bytes", num_bytes),
+        }
+    }
+
+    /// Allocate a zeroed bytearray of a given size, or return None if its
+    /// table could not be allocated. What is left in the heap then is an
+    /// empty bytearray nothing references.
     ///
     /// The bytearray is allocated before its table, so that the two end
     /// up in the same order the collector puts them in, with the bytes
     /// following the bytearray they belong to. No collection can happen
     /// between the two allocations: callers reserve the space up front.
-    pub fn with_size(num_bytes: usize, alloc: &mut Alloc) -> Value
+    pub fn try_with_size(num_bytes: usize, alloc: &mut Alloc) -> Option<Value>
     {
-        // Placeholder standing in until the table below is allocated
-        const NO_TABLE: *mut [u8] = std::ptr::slice_from_raw_parts_mut(std::ptr::null_mut(), 0);
-
-        let ba = alloc.alloc(ByteArray { bytes: NO_TABLE, len: num_bytes }, Tag::ByteArray);
-        let bytes = alloc.alloc_table(num_bytes, Tag::Bytes);
-        unsafe { (*ba).bytes = bytes };
+        // The placeholder stands in until the table below is allocated
+        let ba = alloc.alloc(ByteArray { bytes: NO_TABLE, len: 0 }, Tag::ByteArray);
+        let bytes = Self::alloc_table(num_bytes, alloc)?;
+        unsafe {
+            (*ba).bytes = bytes;
+            (*ba).len = num_bytes;
+        }
 
         // A new bytearray reads as zeroed. Stale bytes here would be
         // silently wrong data rather than anything that fails.
@@ -43,9 +98,33 @@ impl ByteArray
             "bytearray allocated over memory that was not zeroed"
         );
 
+        Some(Value::bytearray(ba))
+    }
+
+    /// Allocate a bytearray whose bytes are kept in an external table
+    pub fn with_ext_table(table: &ExtTable, num_bytes: usize, alloc: &mut Alloc) -> Value
+    {
+        let bytes = table.as_slice_ptr();
+        debug_assert!(num_bytes <= bytes.len());
+
+        let ba = alloc.alloc(ByteArray { bytes, len: num_bytes }, Tag::ByteArray);
         Value::bytearray(ba)
     }
 
+    /// Whether the bytes of this bytearray were moved to another actor
+    pub fn is_detached(&self) -> bool
+    {
+        self.bytes.is_null()
+    }
+
+    /// Leave the bytearray empty, after its bytes were moved elsewhere.
+    /// Whoever took them now owns the table.
+    pub fn detach(&mut self)
+    {
+        self.bytes = NO_TABLE;
+        self.len = 0;
+    }
+
     pub fn clone(&self, alloc: &mut Alloc) -> Value
     {
         let new_ba = Self::with_size(self.len, alloc);
@@ -229,12 +308,16 @@ pub fn ba_with_size(actor: &mut Actor, _self: Value, num_bytes: Value) -> Result
 {
     let num_bytes = unwrap_usize!(num_bytes);
 
+    actor.outside_check(ByteArray::outside_size(num_bytes), &mut []);
     actor.gc_check(
         ByteArray::alloc_size(num_bytes),
         &mut []
     );
 
-    Ok(ByteArray::with_size(num_bytes, &mut actor.alloc))
+    match ByteArray::try_with_size(num_bytes, &mut actor.alloc) {
+        Some(ba) => Ok(ba),
+        None => Err(format!("could not allocate a bytearray of {} bytes", num_bytes)),
+    }
 }
 
 pub fn ba_resize(actor: &mut Actor, mut ba: Value, new_size: Value) -> Result<Value, String>
@@ -245,14 +328,18 @@ pub fn ba_resize(actor: &mut Actor, mut ba: Value, new_size: Value) -> Result<Va
     let capacity = unwrap_ba!(ba).capacity();
 
     if new_size > capacity {
+        actor.outside_check(ByteArray::outside_size(new_size), &mut [&mut ba]);
         actor.gc_check(
-            HEADER_SIZE + new_size,
+            ByteArray::table_size(new_size),
             &mut [&mut ba]
         );
         let ba_mut = ba.as_ba();
 
         let old_len = ba_mut.len;
-        let new_bytes = actor.alloc.alloc_table(new_size, Tag::Bytes);
+        let new_bytes = match ByteArray::alloc_table(new_size, &mut actor.alloc) {
+            Some(bytes) => bytes,
+            None => return Err(format!("could not resize bytearray to {} bytes", new_size)),
+        };
         let copy_len = std::cmp::min(old_len, new_size);
 
         unsafe {
@@ -265,6 +352,7 @@ pub fn ba_resize(actor: &mut Actor, mut ba: Value, new_size: Value) -> Result<Va
 
         ba_mut.bytes = new_bytes;
         ba_mut.len = new_size;
+        actor.remember(ba);
     }
     else {
         let ba_mut = ba.as_ba();
@@ -375,11 +463,24 @@ pub fn ba_memcpy(_actor: &mut Actor, dst: Value, dst_idx: Value, src: Value, src
 {
     let dst = unwrap_ba!(dst);
 
-    let src = unwrap_ba!(src);
-
     let src_idx = unwrap_usize!(src_idx);
     let dst_idx = unwrap_usize!(dst_idx);
     let num_bytes = unwrap_usize!(num_bytes);
+
+    // Copying out of a shared bytearray is how its contents get
+    // into a bytearray, e.g. to draw them to a window
+    if let Some(src) = src.to_sba() {
+        if src_idx + num_bytes > src.num_bytes() {
+            return Err("source range out of bounds for shared bytearray".into());
+        }
+        if dst_idx + num_bytes > dst.num_bytes() {
+            return Err("destination range out of bounds for bytearray".into());
+        }
+        src.copy_to(src_idx, unsafe { dst.get_slice_mut::<u8>(dst_idx, num_bytes) });
+        return Ok(Value::NIL);
+    }
+
+    let src = unwrap_ba!(src);
     dst.memcpy(dst_idx, src, src_idx, num_bytes);
     Ok(Value::NIL)
 }
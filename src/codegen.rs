@@ -25,7 +25,9 @@ fn patch_jump(code: &mut Vec<Insn>, jmp_idx: usize, dst_idx: usize)
     match &mut code[jmp_idx] {
         Insn::if_true { target_ofs } |
         Insn::if_false { target_ofs } |
-        Insn::jump { target_ofs } => {
+        Insn::jump { target_ofs } |
+        Insn::try_begin { target_ofs } |
+        Insn::iter_next { target_ofs, .. } => {
             *target_ofs = jump_ofs;
         }
 
@@ -84,6 +86,9 @@ impl Function
             actor.insns.push(Insn::ret);
         }
 
+        // Map the final return to the function's position
+        actor.insn_pos.resize(actor.insns.len(), self.pos);
+
         Ok(CompiledFun {
             entry_pc,
             num_params: self.params.len(),
@@ -129,7 +134,18 @@ impl StmtBox
             }
 
             Stmt::Return(expr) => {
-                expr.gen_code(fun, actor)?;
+                // A call whose value is returned is in tail position
+                match expr.expr.as_ref() {
+                    Expr::Call { callee, args } => {
+                        gen_call(callee, args, true, fun, actor)?;
+
+                        // Map the call to its own position, as
+                        // gen_code does for a call expression
+                        actor.insn_pos.resize(actor.insns.len(), expr.pos);
+                    }
+                    _ => expr.gen_code(fun, actor)?,
+                }
+
                 actor.insns.push(Insn::ret);
             }
 
@@ -245,6 +261,85 @@ impl StmtBox
                 }
             }
 
+            Stmt::ForIn { iter_expr, body_stmt, decls, .. } => {
+                // The iterable value and a cursor into it stay
+                // on the stack for the duration of the loop
+                iter_expr.gen_code(fun, actor)?;
+                actor.insns.push(Insn::push { val: Value::fixnum(0) });
+
+                let mut break_idxs = Vec::new();
+                let mut cont_idxs = Vec::new();
+
+                // Produce the next value(s), or jump out of the loop
+                // if the iterable is a built-in value that is exhausted
+                let next_idx = actor.insns.len();
+                break_idxs.push(next_idx);
+                actor.insns.push(Insn::iter_next {
+                    num_vals: decls.len() as u8,
+                    target_ofs: 0,
+                });
+
+                // Built-in iterables also push true, objects push false
+                let if_idx = actor.insns.len();
+                actor.insns.push(Insn::if_true { target_ofs: 0 });
+
+                // Objects are iterated by calling their next method
+                // until it returns nil
+                let mut end_idx = None;
+                if decls.len() == 1 {
+                    actor.insns.push(Insn::getn { idx: 1 });
+                    actor.gc_check(Str::alloc_size("next".len()), &mut []);
+                    let name = Str::new("next", &mut actor.alloc);
+                    actor.insns.push(Insn::call_method { name, argc: 0 });
+                    actor.insns.push(Insn::dup);
+                    actor.insns.push(Insn::push { val: Value::NIL });
+                    actor.insns.push(Insn::eq);
+                    end_idx = Some(actor.insns.len());
+                    actor.insns.push(Insn::if_true { target_ofs: 0 });
+                }
+
+                // Assign the loop variables, the last value is on top
+                let body_idx = actor.insns.len();
+                patch_jump(&mut actor.insns, if_idx, body_idx);
+                for decl in decls.iter().rev() {
+                    gen_var_write(decl, fun, &mut actor.insns);
+                }
+
+                body_stmt.gen_code(
+                    fun,
+                    &mut break_idxs,
+                    &mut cont_idxs,
+                    actor,
+                )?;
+
+                // Continue will jump here
+                let cont_idx = actor.insns.len();
+                actor.insns.push(Insn::jump { target_ofs: 0 });
+                patch_jump(&mut actor.insns, cont_idx, next_idx);
+
+                // Pop the nil returned by the next method
+                if let Some(end_idx) = end_idx {
+                    let dst_idx = actor.insns.len();
+                    patch_jump(&mut actor.insns, end_idx, dst_idx);
+                    actor.insns.push(Insn::pop);
+                }
+
+                // Break will jump here
+                let break_idx = actor.insns.len();
+                actor.insns.push(Insn::pop);
+                actor.insns.push(Insn::pop);
+
+                // Patch continue jumps
+                for branch_idx in cont_idxs.iter() {
+                    patch_jump(&mut actor.insns, *branch_idx, cont_idx);
+                }
+
+                // Patch break jumps
+                for branch_idx in break_idxs.iter() {
+                    patch_jump(&mut actor.insns, *branch_idx, break_idx);
+                }
+            }
+
             Stmt::Assert { test_expr } => {
                 test_expr.gen_code(fun, actor)?;
 
@@ -255,6 +350,55 @@ impl StmtBox
                 patch_jump(&mut actor.insns, if_idx, dst_idx);
             }
 
+            Stmt::Try { body_stmt, catch_stmt, decl, .. } => {
+                // Install the handler, which jumps to the catch clause
+                let try_idx = actor.insns.len();
+                actor.insns.push(Insn::try_begin { target_ofs: 0 });
+
+                let mut inner_breaks = Vec::new();
+                let mut inner_conts = Vec::new();
+                body_stmt.gen_code(
+                    fun,
+                    &mut inner_breaks,
+                    &mut inner_conts,
+                    actor,
+                )?;
+
+                // Uninstall the handler when the body completes normally
+                actor.insns.push(Insn::try_end);
+                let jump_idx = actor.insns.len();
+                actor.insns.push(Insn::jump { target_ofs: 0 });
+
+                // Break and continue statements leaving the try block
+                // must also uninstall the handler before jumping out
+                for (inner_idxs, outer_idxs) in [(inner_breaks, &mut *break_idxs), (inner_conts, &mut *cont_idxs)] {
+                    if !inner_idxs.is_empty() {
+                        let dst_idx = actor.insns.len();
+                        for branch_idx in inner_idxs {
+                            patch_jump(&mut actor.insns, branch_idx, dst_idx);
+                        }
+
+                        actor.insns.push(Insn::try_end);
+                        outer_idxs.push(actor.insns.len());
+                        actor.insns.push(Insn::jump { target_ofs: 0 });
+                    }
+                }
+
+                // The thrown value is on top of the stack in the catch clause
+                let catch_idx = actor.insns.len();
+                patch_jump(&mut actor.insns, try_idx, catch_idx);
+                gen_var_write(decl.as_ref().unwrap(), fun, &mut actor.insns);
+                catch_stmt.gen_code(fun, break_idxs, cont_idxs, actor)?;
+
+                let dst_idx = actor.insns.len();
+                patch_jump(&mut actor.insns, jump_idx, dst_idx);
+            }
+
+            Stmt::Throw(expr) => {
+                expr.gen_code(fun, actor)?;
+                actor.insns.push(Insn::throw);
+            }
+
             // Variable declaration
             Stmt::Let { mutable: _, var_name: _, init_expr, decl } => {
                 // Nothing to do for top-level functions
@@ -307,6 +451,10 @@ impl StmtBox
             //_ => todo!("{:?}", self.stmt)
         }
 
+        // Map the instructions generated for this statement
+        // (but not its substatements) to its source position
+        actor.insn_pos.resize(actor.insns.len(), self.pos);
+
         Ok(())
     }
 }
@@ -365,19 +513,19 @@ impl ExprBox
             }
 
             Expr::Array { exprs } => {
-                return gen_arr_expr(
+                gen_arr_expr(
                     exprs,
                     fun,
                     actor,
-                );
+                )?;
             }
 
             Expr::Dict { pairs } => {
-                return gen_dict_expr(
+                gen_dict_expr(
                     pairs,
                     fun,
                     actor,
-                );
+                )?;
             }
 
             Expr::Ref {decl, .. } => {
@@ -452,87 +600,136 @@ impl ExprBox
             }
 
             Expr::Call { callee, args } => {
-                let argc = args.len().try_into().unwrap();
-
-                match callee.expr.as_ref() {
-                    // New class instance
-                    Expr::Ref { decl: Decl::Class { id }, .. } => {
-                        // Evaluate the arguments
-                        for arg in args {
-                            arg.gen_code(fun, actor)?;
-                        }
+                gen_call(callee, args, false, fun, actor)?;
+            }
 
-                        actor.insns.push(Insn::new { class_id: *id, argc });
+            // Function expression
+            Expr::Fun { fun_id, captured } => {
+                // If this is not a closure
+                if captured.len() == 0 {
+                    actor.insns.push(Insn::push { val: Value::fun(*fun_id) });
+                }
+                else
+                {
+                    actor.insns.push(Insn::clos_new {
+                        fun_id: *fun_id,
+                        num_slots: captured.len() as u32,
+                    });
+
+                    // For each variable captured by the closure
+                    for (idx, decl) in captured.iter().enumerate() {
+                        actor.insns.push(Insn::dup);
+
+                        // Copy variables and cells captured by the closure
+                        match decl {
+                            Decl::Local { idx, mutable: true, .. } => {
+                                actor.insns.push(Insn::get_local { idx: *idx });
+                            }
+                            _ => gen_var_read(decl, fun, &mut actor.insns)
+                        }
+                        actor.insns.push(Insn::clos_set { idx: idx as u32 });
                     }
+                }
+            }
 
-                    // Callee has form a.b
-                    Expr::Member { base, field } => {
-                        // Evaluate the self argument
-                        base.gen_code(fun, actor)?;
-
-                        for arg in args {
-                            arg.gen_code(fun, actor)?;
-                        }
+            _ => todo!("{:?}", self)
+        }
 
-                        actor.gc_check(Str::alloc_size(field.len()), &mut []);
-                        let name = Str::new(&field, &mut actor.alloc);
-                        actor.insns.push(Insn::call_method { name, argc });
-                    }
+        // Map the instructions generated for this expression
+        // (but not its subexpressions) to its source position
+        actor.insn_pos.resize(actor.insns.len(), self.pos);
 
-                    // Call to a known function
-                    Expr::Ref { decl: Decl::Fun { id }, .. } => {
-                        for arg in args {
-                            arg.gen_code(fun, actor)?;
-                        }
+        Ok(())
+    }
+}
 
-                        actor.insns.push(Insn::call_direct { fun_id: *id, argc });
-                    }
+// Generate code for a call expression. Calls in tail position reuse
+// the frame of the function making them, except constructor calls,
+// which need the frame to return the new object.
+fn gen_call(
+    callee: &ExprBox,
+    args: &Vec<ExprBox>,
+    tail: bool,
+    fun: &Function,
+    actor: &mut Actor,
+) -> Result<(), ParseError>
+{
+    let argc = args.len().try_into().unwrap();
+
+    match callee.expr.as_ref() {
+        // New class instance
+        Expr::Ref { decl: Decl::Class { id }, .. } => {
+            // Evaluate the arguments
+            for arg in args {
+                arg.gen_code(fun, actor)?;
+            }
 
-                    // Plain regular call
-                    _ => {
-                        for arg in args {
-                            arg.gen_code(fun, actor)?;
-                        }
+            // Core classes whose instances are not objects are created
+            // by a host function, which is called like any other
+            match crate::runtime::get_core_ctor(*id) {
+                Some(ctor) => {
+                    actor.insns.push(Insn::push { val: Value::host_fn(ctor) });
 
-                        callee.gen_code(fun, actor)?;
+                    if tail {
+                        actor.insns.push(Insn::tail_call { argc });
+                    } else {
                         actor.insns.push(Insn::call { argc });
                     }
                 }
+
+                None => actor.insns.push(Insn::new { class_id: *id, argc }),
             }
+        }
 
-            // Function expression
-            Expr::Fun { fun_id, captured } => {
-                // If this is not a closure
-                if captured.len() == 0 {
-                    actor.insns.push(Insn::push { val: Value::fun(*fun_id) });
-                    return Ok(())
-                }
+        // Callee has form a.b
+        Expr::Member { base, field } => {
+            // Evaluate the self argument
+            base.gen_code(fun, actor)?;
 
-                actor.insns.push(Insn::clos_new {
-                    fun_id: *fun_id,
-                    num_slots: captured.len() as u32,
-                });
+            for arg in args {
+                arg.gen_code(fun, actor)?;
+            }
 
-                // For each variable captured by the closure
-                for (idx, decl) in captured.iter().enumerate() {
-                    actor.insns.push(Insn::dup);
+            actor.gc_check(Str::alloc_size(field.len()), &mut []);
+            let name = Str::new(&field, &mut actor.alloc);
 
-                    // Copy variables and cells captured by the closure
-                    match decl {
-                        Decl::Local { idx, mutable: true, .. } => {
-                            actor.insns.push(Insn::get_local { idx: *idx });
-                        }
-                        _ => gen_var_read(decl, fun, &mut actor.insns)
-                    }
-                    actor.insns.push(Insn::clos_set { idx: idx as u32 });
-                }
+            if tail {
+                actor.insns.push(Insn::tail_call_method { name, argc });
+            } else {
+                actor.insns.push(Insn::call_method { name, argc });
             }
+        }
 
-            _ => todo!("{:?}", self)
+        // Call to a known function
+        Expr::Ref { decl: Decl::Fun { id }, .. } => {
+            for arg in args {
+                arg.gen_code(fun, actor)?;
+            }
+
+            if tail {
+                actor.insns.push(Insn::tail_call_direct { fun_id: *id, argc });
+            } else {
+                actor.insns.push(Insn::call_direct { fun_id: *id, argc });
+            }
         }
 
-        Ok(())
+        // Plain regular call
+        _ => {
+            for arg in args {
+                arg.gen_code(fun, actor)?;
+            }
+
+            callee.gen_code(fun, actor)?;
+
+            if tail {
+                actor.insns.push(Insn::tail_call { argc });
+            } else {
+                actor.insns.push(Insn::call { argc });
+            }
+        }
     }
+
+    Ok(())
 }
 
 // Generate code for an array literal expression
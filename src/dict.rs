@@ -10,6 +10,13 @@ pub(crate) struct TableSlot {
 }
 
 impl TableSlot {
+    // A slot that never held an entry. Tables are allocated zeroed.
+    const EMPTY: TableSlot = TableSlot { key: std::ptr::null(), val: Value::from_raw(0) };
+
+    // A slot whose entry was removed. Lookups have to probe past it, since
+    // the key they are looking for may have been placed further along.
+    const TOMBSTONE: TableSlot = TableSlot { key: std::ptr::null(), val: Value::UNDEF };
+
     fn new(key: *const Str, val: Value) -> Self {
         Self{ key, val }
     }
@@ -41,12 +48,21 @@ impl TableSlot {
     fn is_occupied(&self) -> bool {
         !self.key.is_null()
     }
+
+    fn is_tombstone(&self) -> bool {
+        self.key.is_null() && self.val.is_undef()
+    }
 }
 
 pub struct Dict {
     // Relocated by the collector, which walks the table on its own
     pub(crate) table: *mut [TableSlot],
-    len: usize
+
+    // Number of entries
+    len: usize,
+
+    // Number of slots that are either occupied or tombstones
+    used: usize,
 }
 
 const THRESHOLD: usize = 75;
@@ -86,7 +102,7 @@ impl Dict {
         const NO_TABLE: *mut [TableSlot] = std::ptr::slice_from_raw_parts_mut(std::ptr::null_mut(), 0);
 
         let capacity = std::cmp::max(capacity, 2);
-        let dict = alloc.alloc(Dict { table: NO_TABLE, len: 0 }, Tag::Dict);
+        let dict = alloc.alloc(Dict { table: NO_TABLE, len: 0, used: 0 }, Tag::Dict);
         unsafe { (*dict).table = Self::empty_zeroed_table(capacity, alloc) };
         Value::dict(dict)
     }
@@ -94,37 +110,61 @@ impl Dict {
     // get slot is the heart of the dict implementation, as it's used for both
     // getting and setting values. it hashes the key and tries to find the slot where the key
     // should go. The hashing algorithm we use is the default one that rust stdlib ships with.
-    // We then use linear probing to deal with collisions.
+    // We then use linear probing to deal with collisions. If the key is absent, the slot
+    // returned is the first tombstone probed, so that removed slots get reused.
     fn get_slot<'a>(&'a mut self, key: &str) -> &'a mut TableSlot {
         let table = unsafe { &mut *self.table };
         let len = table.len();
         let mut hasher = DefaultHasher::new();
         key.hash(&mut hasher);
         let hash = hasher.finish();
-        let mut pos = usize::try_from(hash).unwrap_or(usize::MAX);
+        let mut pos = usize::try_from(hash).unwrap_or(usize::MAX) % len;
+        let mut tombstone = None;
+
+        loop {
+            let slot = &table[pos];
+
+            match slot.key_as_str() {
+                // we found an occupied slot for the given key (the key already existed in the dict)
+                Some(slot_key) if slot_key == key => break,
 
-        // Have to modulo by len so that it's always inside the table
-        while let Some(slot_key) = table[pos % len].key_as_str() {
-            // we found an occupied slot for the given key (the key already existed in the dict)
-            if slot_key == key {
-                break;
+                Some(_) => {}
+
+                // keep probing past removed entries
+                None if slot.is_tombstone() => {
+                    tombstone = tombstone.or(Some(pos));
+                }
+
+                // the key is not in the dict
+                None => {
+                    pos = tombstone.unwrap_or(pos);
+                    break;
+                }
             }
 
             // linear probing on occupied slot
-            pos += 1;
+            pos = (pos + 1) % len;
         }
 
-        &mut table[pos % len]
+        &mut table[pos]
     }
 
-    // Double the size of the internal backing table. This allocates a whole new backing table
-    // and rehashes all entries into it
-    fn double_size(&mut self, alloc: &mut Alloc) {
+    // Allocate a new backing table and rehash all entries into it, which drops the tombstones.
+    // The table doubles in size, unless removals have left it mostly tombstones
+    fn rehash(&mut self, alloc: &mut Alloc) {
         let old_table = unsafe { &* self.table };
 
-        let new_table = Self::empty_zeroed_table((old_table.len() + 1) * 2, alloc);
+        let new_capacity = if 2 * (self.len + 1) <= old_table.len() {
+            old_table.len()
+        } else {
+            (old_table.len() + 1) * 2
+        };
+
+        let new_table = Self::empty_zeroed_table(new_capacity, alloc);
 
         self.table = new_table;
+        self.len = 0;
+        self.used = 0;
 
         for entry in old_table {
             if let Some((key, val)) = entry.key_value() {
@@ -154,22 +194,33 @@ impl Dict {
         let table = unsafe { &*self.table };
 
         // Note: we must never end up in a situation where there are no
-        // free slots after an element is added, because the get_slot method
-        // relies on there always being at least one free slot.
-        (self.len + 1 == table.len()) ||
-        (100 * self.len / table.len() > THRESHOLD)
+        // empty slots after an element is added, because the get_slot method
+        // relies on there always being at least one empty slot. Tombstones
+        // don't count as empty, since probing continues past them.
+        (self.used + 1 == table.len()) ||
+        (100 * self.used / table.len() > THRESHOLD)
     }
 
     // Set the value associated with a given key
     pub fn set(&mut self, field_name: *const Str, new_val: Value, alloc: &mut Alloc) {
-        if self.will_allocate_on_set() {
-            self.double_size(alloc);
+        let key = unsafe { &*field_name }.as_str();
+
+        if !self.has(key) && self.will_allocate_on_set() {
+            self.rehash(alloc);
         }
 
-        let key = unsafe { &*field_name }.as_str();
         let slot = self.get_slot(key);
+        let is_new = !slot.is_occupied();
+        let was_tombstone = slot.is_tombstone();
         *slot = TableSlot::new(field_name, new_val);
-        self.len += 1;
+
+        if is_new {
+            self.len += 1;
+
+            if !was_tombstone {
+                self.used += 1;
+            }
+        }
     }
 
     // Get the value associated with a given field
@@ -180,4 +231,36 @@ impl Dict {
     pub fn has(&mut self, field_name: &str) -> bool {
         self.get_slot(field_name).is_occupied()
     }
+
+    // Remove the entry for a given key, returning its value if it was present
+    pub fn remove(&mut self, field_name: &str) -> Option<Value> {
+        let slot = self.get_slot(field_name);
+        let val = slot.value().copied()?;
+        *slot = TableSlot::TOMBSTONE;
+        self.len -= 1;
+        Some(val)
+    }
+
+    // Remove all entries, keeping the current capacity
+    pub fn clear(&mut self) {
+        let table = unsafe { &mut *self.table };
+        table.fill(TableSlot::EMPTY);
+        self.len = 0;
+        self.used = 0;
+    }
+
+    // Number of entries in the dict
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Find the first entry at or after a given slot index, in table order.
+    /// Returns the index of its slot along with its key and value.
+    pub fn next_entry(&self, slot_idx: usize) -> Option<(usize, Value, Value)> {
+        let table = unsafe { &*self.table };
+
+        table.iter().enumerate().skip(slot_idx).find_map(|(idx, slot)| {
+            slot.key_value().map(|(key, val)| (idx, Value::string(*key), *val))
+        })
+    }
 }
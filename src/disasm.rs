@@ -0,0 +1,253 @@
+use std::fmt::Write;
+use rustc_hash::FxHashSet as HashSet;
+use crate::ast::{Program, FunId, ClassId};
+use crate::vm::{Actor, Insn};
+use crate::value::*;
+
+/// Name of a function, qualified with the class name for methods
+fn fun_name(prog: &Program, fun_id: FunId) -> String
+{
+    match prog.funs.get(&fun_id) {
+        Some(fun) if fun.class_id != ClassId::default() => {
+            format!("{}.{}", class_name(prog, fun.class_id), fun.name)
+        }
+        Some(fun) => fun.name.clone(),
+        None => format!("fun#{}", usize::from(fun_id)),
+    }
+}
+
+fn class_name(prog: &Program, class_id: ClassId) -> String
+{
+    match prog.classes.get(&class_id) {
+        Some(class) => class.name.clone(),
+        None => format!("class#{}", usize::from(class_id)),
+    }
+}
+
+/// Text form of a constant operand
+fn format_val(prog: &Program, val: Value) -> String
+{
+    match val.type_of() {
+        Type::Undef => "undef".into(),
+        Type::Nil => "nil".into(),
+        Type::Bool => val.as_bool().to_string(),
+        Type::Int64 => val.to_i64().unwrap().to_string(),
+        Type::Float64 => format!("{:?}", val.to_f64().unwrap()),
+        Type::String => format!("{:?}", val.as_str()),
+        Type::Fun => format!("fun {}", fun_name(prog, val.as_fun())),
+        Type::Class => format!("class {}", class_name(prog, val.as_class())),
+        Type::HostFn => format!("${}", val.as_host_fn().name),
+        _ => format!("{:?}", val),
+    }
+}
+
+/// Address a jump instruction at a given pc goes to
+fn jump_target(pc: usize, target_ofs: i32) -> usize
+{
+    (pc as i64 + 1 + target_ofs as i64) as usize
+}
+
+/// Text form of one instruction, with jump targets given as labels
+fn format_insn(prog: &Program, insn: &Insn, pc: usize) -> String
+{
+    use Insn::*;
+
+    let label = |target_ofs: i32| format!("L{}", jump_target(pc, target_ofs));
+
+    match *insn {
+        panic { pos } => format!("panic {}", pos),
+        push { val } => format!("push {}", format_val(prog, val)),
+        getn { idx } => format!("getn {}", idx),
+        get_arg { idx } => format!("get_arg {}", idx),
+        get_local { idx } => format!("get_local {}", idx),
+        set_local { idx } => format!("set_local {}", idx),
+        get_global { idx } => format!("get_global {}", idx),
+        set_global { idx } => format!("set_global {}", idx),
+        add_i64 { val } => format!("add_i64 {}", val),
+
+        clos_new { fun_id, num_slots } => {
+            format!("clos_new {}, slots={}", fun_name(prog, fun_id), num_slots)
+        }
+        clos_set { idx } => format!("clos_set {}", idx),
+        clos_get { idx } => format!("clos_get {}", idx),
+
+        new { class_id, argc } => format!("new {}, argc={}", class_name(prog, class_id), argc),
+        new_known_ctor { class_id, argc, num_slots, ctor_pc, fun_id, num_locals } => {
+            format!(
+                "new_known_ctor {}, argc={}, slots={}, ctor={} @{}, locals={}",
+                class_name(prog, class_id), argc, num_slots, fun_name(prog, fun_id), ctor_pc, num_locals
+            )
+        }
+        instanceof { class_id } => format!("instanceof {}", class_name(prog, class_id)),
+
+        get_field { field, class_id, slot_idx } | set_field { field, class_id, slot_idx } => {
+            let name = if matches!(insn, get_field { .. }) { "get_field" } else { "set_field" };
+
+            // The class and slot are filled in once the field has been accessed
+            if class_id == ClassId::default() {
+                format!("{} {}", name, field.as_str())
+            } else {
+                format!("{} {}, class={}, slot={}", name, field.as_str(), class_name(prog, class_id), slot_idx)
+            }
+        }
+
+        arr_new { capacity } => format!("arr_new capacity={}", capacity),
+
+        if_true { target_ofs } => format!("if_true {}", label(target_ofs)),
+        if_false { target_ofs } => format!("if_false {}", label(target_ofs)),
+        jump { target_ofs } => format!("jump {}", label(target_ofs)),
+        try_begin { target_ofs } => format!("try_begin {}", label(target_ofs)),
+        iter_next { num_vals, target_ofs } => format!("iter_next {}, vals={}", label(target_ofs), num_vals),
+
+        call { argc } => format!("call argc={}", argc),
+        call_direct { fun_id, argc } => format!("call_direct {}, argc={}", fun_name(prog, fun_id), argc),
+        call_pc { entry_pc, fun_id, num_locals, argc } => {
+            format!("call_pc {} @{}, argc={}, locals={}", fun_name(prog, fun_id), entry_pc, argc, num_locals)
+        }
+
+        call_method { name, argc } => format!("call_method {}, argc={}", name.as_str(), argc),
+        call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals } => {
+            format!(
+                "call_method_pc {}, argc={}, class={}, fun={} @{}, locals={}",
+                name.as_str(), argc, class_name(prog, class_id), fun_name(prog, fun_id), entry_pc, num_locals
+            )
+        }
+        call_method_host { name, argc, type_tag, host_fn } => {
+            format!("call_method_host {}, argc={}, type={:?}, host_fn={}", name.as_str(), argc, type_tag, host_fn.name)
+        }
+
+        tail_call { argc } => format!("tail_call argc={}", argc),
+        tail_call_direct { fun_id, argc } => format!("tail_call_direct {}, argc={}", fun_name(prog, fun_id), argc),
+        tail_call_pc { entry_pc, fun_id, num_locals, argc } => {
+            format!("tail_call_pc {} @{}, argc={}, locals={}", fun_name(prog, fun_id), entry_pc, argc, num_locals)
+        }
+
+        tail_call_method { name, argc } => format!("tail_call_method {}, argc={}", name.as_str(), argc),
+        tail_call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals } => {
+            format!(
+                "tail_call_method_pc {}, argc={}, class={}, fun={} @{}, locals={}",
+                name.as_str(), argc, class_name(prog, class_id), fun_name(prog, fun_id), entry_pc, num_locals
+            )
+        }
+
+        // Instructions without operands print as their name
+        _ => format!("{:?}", insn),
+    }
+}
+
+/// Disassemble every function an actor has compiled so far, in the order
+/// they were compiled. Instructions rewritten by inline caches appear in
+/// their specialized form.
+pub fn dump_bytecode(actor: &Actor) -> String
+{
+    let vm = actor.vm.clone();
+    let vm = vm.lock().unwrap();
+    let prog = vm.prog();
+
+    // Functions are compiled one after the other, so each one
+    // ends where the next one in address order starts
+    let mut funs: Vec<_> = actor.funs.iter().map(|(id, entry)| (*id, *entry)).collect();
+    funs.sort_by_key(|(_, entry)| entry.entry_pc);
+
+    let mut out = String::new();
+
+    for (idx, (fun_id, entry)) in funs.iter().enumerate() {
+        let start_pc = entry.entry_pc;
+        let end_pc = funs.get(idx + 1).map(|(_, next)| next.entry_pc).unwrap_or(actor.insns.len());
+        let insns = &actor.insns[start_pc..end_pc];
+
+        writeln!(
+            out,
+            "fun {} ({}), params={}, locals={}:",
+            fun_name(prog, *fun_id), prog.funs[fun_id].pos, entry.num_params, entry.num_locals
+        ).unwrap();
+
+        // Collect the jump targets so they can be labelled
+        let targets: HashSet<usize> = insns.iter().enumerate().filter_map(|(ofs, insn)| {
+            match *insn {
+                Insn::if_true { target_ofs } |
+                Insn::if_false { target_ofs } |
+                Insn::jump { target_ofs } |
+                Insn::try_begin { target_ofs } |
+                Insn::iter_next { target_ofs, .. } => Some(jump_target(start_pc + ofs, target_ofs)),
+                _ => None,
+            }
+        }).collect();
+
+        // Source positions are only shown where they change
+        let mut last_pos = None;
+
+        for (ofs, insn) in insns.iter().enumerate() {
+            let pc = start_pc + ofs;
+
+            if targets.contains(&pc) {
+                writeln!(out, "L{}:", pc).unwrap();
+            }
+
+            let text = format_insn(prog, insn, pc);
+            let pos = actor.insn_pos[pc];
+
+            if last_pos == Some(pos) {
+                writeln!(out, "  {:>6}  {}", pc, text).unwrap();
+            } else {
+                writeln!(out, "  {:>6}  {:<48} # {}", pc, text, pos).unwrap();
+                last_pos = Some(pos);
+            }
+        }
+
+        writeln!(out).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::parser::parse_str;
+    use crate::vm::VM;
+
+    fn compile(src: &str) -> Program
+    {
+        let mut prog = parse_str(src).unwrap();
+        prog.resolve_syms().unwrap();
+        prog
+    }
+
+    #[test]
+    fn labels_and_names()
+    {
+        let prog = compile("fun f(n) { if (n < 2) return n; return f(n - 1); } $println(f(3));");
+        let out = dump_bytecode(&VM::compile_all(prog));
+        dbg!(&out);
+
+        assert!(out.contains("fun f ("));
+        assert!(out.contains("if_false L"));
+        assert!(out.contains("call_direct f, argc=1"));
+        assert!(out.contains("tail_call_direct f, argc=1"));
+        assert!(out.contains("push $println"));
+    }
+
+    #[test]
+    fn inline_caches()
+    {
+        let prog = compile("
+            class C { init(self) { self.x = 1; } get(self) { return self.x; } }
+            let c = C();
+            c.get();
+        ");
+        let main_fn = prog.main_fn;
+        let vm = VM::new(prog);
+        let mut actor = VM::main_actor(&vm);
+        actor.call(Value::fun(main_fn), &[]);
+
+        let out = dump_bytecode(&actor);
+        dbg!(&out);
+
+        assert!(out.contains("fun C.get ("));
+        assert!(out.contains("new_known_ctor C, argc=0"));
+        assert!(out.contains("call_method_pc get, argc=0, class=C, fun=C.get"));
+        assert!(out.contains("get_field x, class=C, slot=0"));
+    }
+}
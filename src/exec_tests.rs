@@ -50,6 +50,8 @@ fn test_file(file_path: &str, no_exec: bool)
     command.current_dir(".");
     if no_exec {
         command.arg("--no-exec");
+    } else {
+        command.arg("--allow-all");
     }
     command.arg(file_path);
 
@@ -105,3 +107,73 @@ fn benchmarks()
         test_file(&file_path, true);
     }
 }
+
+#[test]
+fn permissions()
+{
+    let run = |args: &[&str]| {
+        let output = Command::new(verify_gc_binary())
+            .args(args)
+            .arg("tests/read_file.psh")
+            .output()
+            .unwrap();
+        (output.status.success(), String::from_utf8_lossy(&output.stderr).to_string())
+    };
+
+    // Nothing is allowed unless it is granted
+    let (success, stderr) = run(&[]);
+    assert!(!success);
+    assert!(stderr.contains("missing permission `fs-read`"), "{}", stderr);
+
+    // The script reads a file outside of the tests directory
+    let (success, stderr) = run(&["--allow", "fs-read:tests"]);
+    assert!(!success);
+    assert!(stderr.contains("missing permission `fs-read` for path \"README.md\""), "{}", stderr);
+
+    let (success, stderr) = run(&["--allow", "fs-read:."]);
+    assert!(success, "{}", stderr);
+
+    // Denying takes precedence over allowing
+    let (success, stderr) = run(&["--allow-all", "--deny", "fs-read"]);
+    assert!(!success);
+    assert!(stderr.contains("permission `fs-read` was denied"), "{}", stderr);
+}
+
+#[test]
+fn single_thread()
+{
+    // Actors have to take turns, and park while they wait
+    let output = Command::new(verify_gc_binary())
+        .args(["--allow-all", "--threads", "1", "tests/actor_sched.psh"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn stack_trace()
+{
+    let output = Command::new(verify_gc_binary())
+        .arg("tests/errors/nested_call.psh")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // Each frame gives the position it is executing at, from the
+    // innermost one out. The outer function calls middle in tail
+    // position, so its frame is gone.
+    let trace = concat!(
+        "inner\n",
+        "  defined at tests/errors/nested_call.psh@2:1\n",
+        "  at tests/errors/nested_call.psh@4:12\n",
+        "middle\n",
+        "  defined at tests/errors/nested_call.psh@7:1\n",
+        "  at tests/errors/nested_call.psh@9:18\n",
+    );
+    assert!(stderr.contains(trace), "{}", stderr);
+    assert!(stderr.contains("  at tests/errors/nested_call.psh@18:6\n"), "{}", stderr);
+    assert!(!stderr.contains("outer\n"), "{}", stderr);
+}
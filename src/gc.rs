@@ -1,14 +1,17 @@
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::hash::{Hash, Hasher};
 use std::mem::size_of;
+use std::time::Duration;
 
 use crate::alloc::{header_of, restore_header, set_forwarded};
-use crate::alloc::{Alloc, Header, Tag, HEADER_SIZE};
+use crate::alloc::{Alloc, ExtTable, Header, Tag, HEADER_SIZE, LARGE_OBJECT_SIZE};
 use crate::array::Array;
+use crate::ast::ClassId;
 use crate::bytearray::ByteArray;
 use crate::closure::Closure;
 use crate::dict::{Dict, TableSlot};
 use crate::object::Object;
+use crate::shared::SharedByteArray;
 use crate::str::Str;
 use crate::value::Value;
 
@@ -66,11 +69,19 @@ pub type UndoLog = Vec<UndoEntry>;
 ///
 /// Walking that region block by block is what the size and tag in each
 /// block header are there for.
+///
+/// A minor collection only moves the blocks of the nursery, promoting
+/// them into the old space, which is then the destination. Blocks of the
+/// old space stay where they are, and only the ones in the remembered set
+/// are scanned for references into the nursery.
 pub struct Copier<'a>
 {
     // Allocator to copy into
     dst: &'a mut Alloc,
 
+    // The nursery, when it is the only space being collected
+    nursery: Option<&'a Alloc>,
+
     // Offset of the next block to scan in the destination
     scan: usize,
 
@@ -81,8 +92,17 @@ pub struct Copier<'a>
     // stay usable after the copy
     undo: Option<&'a mut UndoLog>,
 
-    // Number of blocks copied, for reporting
-    num_blocks: usize,
+    // Whether the destination is the heap of an actor rather than a holder
+    // for a message, in which case large bytearrays keep their bytes
+    // outside of it
+    dst_is_heap: bool,
+
+    // Number of blocks copied of each kind, for reporting
+    blocks_by_tag: [usize; Tag::COUNT],
+
+    // Weak references copied so far, whose referents are only known to
+    // be live once everything reachable has been copied
+    weak_refs: Vec<*mut Value>,
 }
 
 impl<'a> Copier<'a>
@@ -96,12 +116,24 @@ impl<'a> Copier<'a>
         Self {
             scan: dst.bytes_used(),
             dst,
+            nursery: None,
             strs,
             undo: None,
-            num_blocks: 0,
+            dst_is_heap: true,
+            blocks_by_tag: [0; Tag::COUNT],
+            weak_refs: Vec::new(),
         }
     }
 
+    /// Copier that promotes the live blocks of the nursery into the old
+    /// space, leaving forwarding addresses behind in the nursery
+    pub fn for_nursery(old: &'a mut Alloc, strs: &'a mut StrTable, nursery: &'a Alloc) -> Self
+    {
+        let mut copier = Self::new(old, strs);
+        copier.nursery = Some(nursery);
+        copier
+    }
+
     /// Copier that records the headers it overwrites, so that they can
     /// be put back with undo_forwarding once the copy is done
     pub fn with_undo(
@@ -114,13 +146,38 @@ impl<'a> Copier<'a>
 
         let mut copier = Self::new(dst, strs);
         copier.undo = Some(undo);
+        copier.dst_is_heap = false;
         copier
     }
 
+    /// Have a copier that records its undo log copy into the heap of an
+    /// actor, rather than into a message
+    pub fn into_heap(mut self) -> Self
+    {
+        self.dst_is_heap = true;
+        self
+    }
+
     #[allow(dead_code)] // used by the log_gc cycle report
     pub fn num_blocks(&self) -> usize
     {
-        self.num_blocks
+        self.blocks_by_tag.iter().sum()
+    }
+
+    /// Number of blocks copied of each kind, indexed by tag
+    pub fn blocks_by_tag(&self) -> &[usize; Tag::COUNT]
+    {
+        &self.blocks_by_tag
+    }
+
+    /// Whether a block is in the space being collected. Anything else
+    /// stays where it is.
+    fn is_collected(&self, p: *const u8) -> bool
+    {
+        match self.nursery {
+            Some(nursery) => nursery.contains(p),
+            None => true,
+        }
     }
 
     /// Copy the value's referent into the destination and return the
@@ -137,6 +194,10 @@ impl<'a> Copier<'a>
         }
 
         let p = val.heap_ptr();
+        if !self.is_collected(p) {
+            return val;
+        }
+
         let hdr = header_of(p);
 
         let new_p = if hdr.is_forwarded() {
@@ -153,6 +214,10 @@ impl<'a> Copier<'a>
     /// Copy a string, sharing one that was already copied if it is equal
     fn forward_str(&mut self, p: *const Str) -> *const Str
     {
+        if !self.is_collected(p as *const u8) {
+            return p;
+        }
+
         let hdr = header_of(p as *const u8);
 
         if hdr.is_forwarded() {
@@ -187,13 +252,17 @@ impl<'a> Copier<'a>
         self.record_undo(p, hdr);
         set_forwarded(p, new_p);
 
-        self.num_blocks += 1;
+        self.blocks_by_tag[hdr.tag() as usize] += 1;
         new_p
     }
 
     /// Relocate a table, keeping its length
     fn copy_table<T>(&mut self, table: *mut [T]) -> *mut [T]
     {
+        if self.keep_table(table as *mut T as *mut u8) {
+            return table;
+        }
+
         let len = table.len();
         let new_p = self.copy(table as *mut T as *mut u8);
 
@@ -209,6 +278,10 @@ impl<'a> Copier<'a>
         debug_assert!(len <= table.len());
 
         let p = table as *mut T as *mut u8;
+        if self.keep_table(p) {
+            return table;
+        }
+
         let hdr = header_of(p);
         debug_assert!(!hdr.is_forwarded(), "table reachable from two owners");
 
@@ -222,10 +295,23 @@ impl<'a> Copier<'a>
         self.record_undo(p, hdr);
         set_forwarded(p, new_table as *mut T as *mut u8);
 
-        self.num_blocks += 1;
+        self.blocks_by_tag[tag as usize] += 1;
         new_table
     }
 
+    /// A table outside of the space being collected stays where it is,
+    /// but may still reference blocks that move, so it is scanned where
+    /// it is instead. Returns true if the table stays.
+    fn keep_table(&mut self, p: *mut u8) -> bool
+    {
+        if self.is_collected(p) {
+            return false;
+        }
+
+        self.scan_block(header_of(p), p);
+        true
+    }
+
     fn record_undo(&mut self, p: *mut u8, hdr: Header)
     {
         if let Some(undo) = self.undo.as_mut() {
@@ -243,14 +329,45 @@ impl<'a> Copier<'a>
 
             self.scan_block(hdr, p);
         }
+
+        self.update_weak_refs();
+    }
+
+    /// Point the weak references that were copied at the copies of their
+    /// referents, or clear them if nothing else kept the referent alive.
+    /// A referent outside of the space being collected is left alone.
+    fn update_weak_refs(&mut self)
+    {
+        for p in std::mem::take(&mut self.weak_refs) {
+            let weak_ref = unsafe { &mut *p };
+
+            if !weak_ref.is_heap() || !self.is_collected(weak_ref.heap_ptr()) {
+                continue;
+            }
+
+            let hdr = header_of(weak_ref.heap_ptr());
+            *weak_ref = if hdr.is_forwarded() {
+                weak_ref.with_heap_ptr(hdr.forward_addr())
+            } else {
+                Value::NIL
+            };
+        }
+    }
+
+    /// Update the references held by a block of the old space, which
+    /// stays where it is, after it was written to since the last cycle
+    pub fn scan_remembered(&mut self, block: Value)
+    {
+        let p = block.heap_ptr();
+        self.scan_block(header_of(p), p);
     }
 
     /// Update the references held by a block that has been copied
     fn scan_block(&mut self, hdr: Header, p: *mut u8)
     {
         match hdr.tag() {
-            // Strings and raw bytes hold no references
-            Tag::Str | Tag::Bytes | Tag::Int64 | Tag::Float64 => {}
+            // Strings, numbers and raw bytes hold no references
+            Tag::Str | Tag::Bytes | Tag::ExtBytes | Tag::Int64 | Tag::Float64 | Tag::BigInt => {}
 
             Tag::Object => {
                 let obj = unsafe { &mut *(p as *mut Object) };
@@ -275,6 +392,12 @@ impl<'a> Copier<'a>
                 *cell = self.forward(*cell);
             }
 
+            // The referent is not copied from here, only updated once the
+            // copy is done if something else copied it
+            Tag::WeakRef => {
+                self.weak_refs.push(p as *mut Value);
+            }
+
             // Arrays and bytearrays hold spare capacity, which the copy
             // drops. A dict keeps its capacity: its table has to stay
             // bigger than its entry count for lookups to terminate.
@@ -293,11 +416,43 @@ impl<'a> Copier<'a>
 
             Tag::ByteArray => {
                 let ba = unsafe { &mut *(p as *mut ByteArray) };
+
+                // A bytearray moved to another actor has no table left
+                if ba.is_detached() {
+                    return;
+                }
+
+                // Bytes kept outside of the heap stay where they are when
+                // the source is discarded, and the destination takes them
+                // over. Otherwise the source keeps them, and the copy gets
+                // bytes of its own in the heap like any other bytearray.
+                let table = ba.bytes as *mut u8;
+                if self.undo.is_none() && header_of(table).tag() == Tag::ExtBytes {
+                    self.dst.adopt_ext_table(table);
+                    return;
+                }
+
+                // A large bytearray copied into a heap gets its bytes moved
+                // out of it, like one allocated there
                 let num_bytes = ba.num_bytes();
+                if self.dst_is_heap && num_bytes >= LARGE_OBJECT_SIZE {
+                    let ext = ExtTable::new(num_bytes);
+                    unsafe { std::ptr::copy_nonoverlapping(table, ext.as_slice_ptr() as *mut u8, num_bytes) };
+                    ba.bytes = ext.as_slice_ptr();
+                    self.dst.adopt_ext_table(ext.into_raw());
+                    return;
+                }
+
                 let bytes = self.copy_table_prefix(ba.bytes, num_bytes, Tag::Bytes);
                 ba.bytes = bytes;
             }
 
+            // The bytes stay where they are, and are shared with the source
+            Tag::SharedByteArray => {
+                let sba = unsafe { &*(p as *const SharedByteArray) };
+                self.dst.retain_shared_ptr(sba.bytes);
+            }
+
             Tag::Dict => {
                 let dict = unsafe { &mut *(p as *mut Dict) };
                 let table = self.copy_table(dict.table);
@@ -343,19 +498,188 @@ pub fn undo_forwarding(undo: &mut UndoLog)
     }
 }
 
-/// Walk a heap and check that every block is well formed and every
-/// reference points at a live block of the expected kind in the same
-/// heap. This is a full heap walk, so it is only meant for testing.
+/// What the collections of an actor have done so far, which the program
+/// can query with $vm_gc_stats()
+#[derive(Default)]
+pub struct GcStats
+{
+    pub num_minor: usize,
+    pub num_full: usize,
+
+    // Time spent collecting, over every cycle and in the last one
+    pub total_pause: Duration,
+    pub last_pause: Duration,
+
+    // Bytes copied over every cycle
+    pub bytes_copied: usize,
+
+    // Blocks of each kind in the old space, indexed by tag. A full cycle
+    // counts what it copies and a minor one adds what it promotes, so
+    // this counts blocks that were live as of the cycle that moved them.
+    pub live_blocks: [usize; Tag::COUNT],
+}
+
+impl GcStats
+{
+    /// Account for a minor cycle, given the blocks it promoted
+    pub fn record_minor(&mut self, pause: Duration, bytes_copied: usize, promoted: &[usize; Tag::COUNT])
+    {
+        self.num_minor += 1;
+        self.record_cycle(pause, bytes_copied);
+
+        for (live, num) in self.live_blocks.iter_mut().zip(promoted) {
+            *live += num;
+        }
+    }
+
+    /// Account for a full cycle, given the blocks it copied
+    pub fn record_full(&mut self, pause: Duration, bytes_copied: usize, copied: &[usize; Tag::COUNT])
+    {
+        self.num_full += 1;
+        self.record_cycle(pause, bytes_copied);
+        self.live_blocks = *copied;
+    }
+
+    fn record_cycle(&mut self, pause: Duration, bytes_copied: usize)
+    {
+        self.total_pause += pause;
+        self.last_pause = pause;
+        self.bytes_copied += bytes_copied;
+    }
+}
+
+/// Number of blocks and the bytes they take up
+#[derive(Default, Clone, Copy)]
+pub struct HeapCount
+{
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl HeapCount
+{
+    fn add(&mut self, bytes: usize)
+    {
+        self.count += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Blocks of a space counted by kind, and objects counted by class
+pub struct HeapHistogram
+{
+    // Indexed by tag. Tables are counted with the block they belong to.
+    pub by_tag: [HeapCount; Tag::COUNT],
+    pub by_class: FxHashMap<ClassId, HeapCount>,
+}
+
+/// Bytes a block takes up, counting its header. The bytes of an array,
+/// dict or bytearray include those of its table, even when the table is
+/// kept outside of the heap. Tables are counted with the block they
+/// belong to, and so have no size of their own here.
+pub fn block_size(p: *const u8) -> Option<usize>
+{
+    // Size of a block on its own
+    let own_bytes = |p: *const u8| HEADER_SIZE + header_of(p).size();
+
+    let table = match header_of(p).tag() {
+        Tag::ValueTable | Tag::SlotTable | Tag::Bytes | Tag::ExtBytes => return None,
+
+        Tag::Array => own_bytes(unsafe { &*(p as *const Array) }.elems as *const u8),
+        Tag::Dict => own_bytes(unsafe { &*(p as *const Dict) }.table as *const u8),
+
+        Tag::ByteArray => {
+            let ba = unsafe { &*(p as *const ByteArray) };
+            if ba.is_detached() { 0 } else { own_bytes(ba.bytes as *const u8) }
+        }
+
+        _ => 0,
+    };
+
+    Some(own_bytes(p) + table)
+}
+
+/// Walk a space and count its blocks by kind and its objects by class,
+/// along with the bytes they take up as block_size counts them. Every
+/// block in the space is counted, so this is only meaningful right after
+/// a full collection, when nothing in it is garbage.
+pub fn heap_histogram(space: &Alloc) -> HeapHistogram
+{
+    let mut histogram = HeapHistogram {
+        by_tag: [HeapCount::default(); Tag::COUNT],
+        by_class: FxHashMap::default(),
+    };
+
+    let mut offset = 0;
+    while offset < space.bytes_used() {
+        let p = space.block_at(offset);
+        let hdr = header_of(p);
+        offset += HEADER_SIZE + hdr.size();
+
+        // Tables are counted with the block they belong to
+        let bytes = match block_size(p) {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+
+        if hdr.tag() == Tag::Object {
+            let obj = unsafe { &*(p as *const Object) };
+            histogram.by_class.entry(obj.class_id).or_default().add(bytes);
+        }
+
+        histogram.by_tag[hdr.tag() as usize].add(bytes);
+    }
+
+    histogram
+}
+
+/// Walk the old space and the nursery and check that every block is well
+/// formed, and that every reference points at a live block of the expected
+/// kind in one of the two. A block of the old space that references the
+/// nursery has to be in the remembered set, or a minor collection would
+/// miss the reference. This is a full heap walk, so it is only meant for
+/// testing.
 #[cfg(feature = "verify_gc")]
-pub fn verify_heap(alloc: &Alloc)
+pub fn verify_heap(old: &Alloc, nursery: &Alloc)
 {
-    fn check(alloc: &Alloc, p: *const u8, expected: Tag)
+    let verifier = Verifier { old, nursery };
+    verifier.walk(old, true);
+    verifier.walk(nursery, false);
+}
+
+#[cfg(feature = "verify_gc")]
+struct Verifier<'a>
+{
+    old: &'a Alloc,
+    nursery: &'a Alloc,
+}
+
+#[cfg(feature = "verify_gc")]
+impl Verifier<'_>
+{
+    /// Check a reference to a block. One held by a block of the old space
+    /// that is not remembered must not point into the nursery.
+    fn check_ptr(&self, p: *const u8, from_old: bool)
     {
-        assert!(alloc.contains(p), "reference {:p} outside the heap", p);
+        assert!(
+            self.old.contains(p) || self.nursery.contains(p),
+            "reference {:p} outside the heap", p
+        );
         assert!(p as usize % 8 == 0, "misaligned reference {:p}", p);
+        assert!(
+            !from_old || !self.nursery.contains(p),
+            "reference {:p} into the nursery from an old block that is not remembered", p
+        );
 
         let hdr = header_of(p);
         assert!(!hdr.is_forwarded(), "reference {:p} to a forwarded block", p);
+    }
+
+    fn check(&self, p: *const u8, expected: Tag, from_old: bool)
+    {
+        self.check_ptr(p, from_old);
+
+        let hdr = header_of(p);
         assert!(
             hdr.tag() == expected,
             "reference {:p} has tag {:?}, expected {:?}",
@@ -363,7 +687,7 @@ pub fn verify_heap(alloc: &Alloc)
         );
     }
 
-    fn check_val(alloc: &Alloc, val: Value)
+    fn check_val(&self, val: Value, from_old: bool)
     {
         if !val.is_heap() {
             // Anything that is not a pointer still has to name a type
@@ -372,88 +696,136 @@ pub fn verify_heap(alloc: &Alloc)
         }
 
         let p = val.heap_ptr();
-        assert!(alloc.contains(p), "reference {:p} outside the heap", p);
-        assert!(p as usize % 8 == 0, "misaligned reference {:p}", p);
-
-        let hdr = header_of(p);
-        assert!(!hdr.is_forwarded(), "reference {:p} to a forwarded block", p);
+        self.check_ptr(p, from_old);
 
         // The pointer tag says how the value compares and the header says
         // what it points at, so the two have to agree
+        let hdr = header_of(p);
         let agrees = match hdr.tag() {
             Tag::Str => val.is_string(),
             Tag::Object => val.is_object(),
             Tag::Closure => val.is_closure(),
             Tag::Cell => val.is_cell(),
+            Tag::WeakRef => val.is_weakref(),
             Tag::Array => val.is_array(),
             Tag::ByteArray => val.is_bytearray(),
+            Tag::SharedByteArray => val.is_shared_bytearray(),
             Tag::Dict => val.is_dict(),
             Tag::Int64 => val.is_int64_box(),
             Tag::Float64 => val.is_float64_box(),
+            Tag::BigInt => val.is_bigint(),
             _ => false,
         };
 
         assert!(agrees, "value points at a {:?} block", hdr.tag());
     }
 
-    let mut offset = 0;
+    fn check_vals(&self, p: *const u8, from_old: bool)
+    {
+        let vals = p as *const Value;
+        for i in 0..header_of(p).size() / size_of::<Value>() {
+            self.check_val(unsafe { *vals.add(i) }, from_old);
+        }
+    }
 
-    while offset < alloc.bytes_used() {
-        let p = alloc.block_at(offset);
-        let hdr = header_of(p);
-        assert!(!hdr.is_forwarded(), "forwarded block left in the heap");
-        offset += HEADER_SIZE + hdr.size();
+    fn check_slots(&self, p: *const u8, from_old: bool)
+    {
+        let slots = p as *const TableSlot;
+        for i in 0..header_of(p).size() / size_of::<TableSlot>() {
+            let slot = unsafe { &*slots.add(i) };
+            if slot.key.is_null() {
+                continue;
+            }
+            self.check(slot.key as *const u8, Tag::Str, from_old);
+            self.check_val(slot.val, from_old);
+        }
+    }
 
-        match hdr.tag() {
-            Tag::Str | Tag::Bytes | Tag::Int64 | Tag::Float64 => {}
+    /// Walk the blocks of one space. Tables are checked along with the
+    /// block they belong to, since that is the one the remembered set
+    /// would hold.
+    fn walk(&self, space: &Alloc, is_old: bool)
+    {
+        let mut offset = 0;
 
-            Tag::Object => {
-                let obj = unsafe { &*(p as *const Object) };
-                for i in 0..obj.num_slots() {
-                    check_val(alloc, obj.get(i));
-                }
-            }
+        while offset < space.bytes_used() {
+            let p = space.block_at(offset);
+            let hdr = header_of(p);
+            assert!(!hdr.is_forwarded(), "forwarded block left in the heap");
+            assert!(is_old || !hdr.is_remembered(), "remembered block in the nursery");
+            offset += HEADER_SIZE + hdr.size();
 
-            Tag::Closure => {
-                let clos = unsafe { &*(p as *const Closure) };
-                for i in 0..clos.num_slots() {
-                    check_val(alloc, clos.get(i));
-                }
-            }
+            let from_old = is_old && !hdr.is_remembered();
 
-            Tag::Cell => check_val(alloc, unsafe { *(p as *const Value) }),
+            match hdr.tag() {
+                Tag::Str | Tag::Bytes | Tag::ExtBytes | Tag::Int64 | Tag::Float64 | Tag::BigInt => {}
 
-            Tag::Array => {
-                let arr = unsafe { &*(p as *const Array) };
-                check(alloc, arr.elems as *const u8, Tag::ValueTable);
-            }
+                // Checked through the array or dict they belong to
+                Tag::ValueTable | Tag::SlotTable => {}
 
-            Tag::ByteArray => {
-                let ba = unsafe { &*(p as *const ByteArray) };
-                check(alloc, ba.bytes as *const u8, Tag::Bytes);
-            }
+                Tag::Object => {
+                    let obj = unsafe { &*(p as *const Object) };
+                    for i in 0..obj.num_slots() {
+                        self.check_val(obj.get(i), from_old);
+                    }
+                }
 
-            Tag::Dict => {
-                let dict = unsafe { &*(p as *const Dict) };
-                check(alloc, dict.table as *const u8, Tag::SlotTable);
-            }
+                Tag::Closure => {
+                    let clos = unsafe { &*(p as *const Closure) };
+                    for i in 0..clos.num_slots() {
+                        self.check_val(clos.get(i), from_old);
+                    }
+                }
 
-            Tag::ValueTable => {
-                let vals = p as *const Value;
-                for i in 0..hdr.size() / size_of::<Value>() {
-                    check_val(alloc, unsafe { *vals.add(i) });
+                Tag::Cell | Tag::WeakRef => self.check_val(unsafe { *(p as *const Value) }, from_old),
+
+                Tag::Array => {
+                    let arr = unsafe { &*(p as *const Array) };
+                    let table = arr.elems as *const u8;
+                    self.check(table, Tag::ValueTable, from_old);
+                    self.check_vals(table, from_old);
                 }
-            }
 
-            Tag::SlotTable => {
-                let slots = p as *const TableSlot;
-                for i in 0..hdr.size() / size_of::<TableSlot>() {
-                    let slot = unsafe { &*slots.add(i) };
-                    if slot.key.is_null() {
-                        continue;
+                Tag::ByteArray => {
+                    let ba = unsafe { &*(p as *const ByteArray) };
+                    let table = ba.bytes as *const u8;
+
+                    if ba.is_detached() {
+                        assert!(ba.num_bytes() == 0, "detached bytearray with a length");
+                    } else if !self.old.contains(table) && !self.nursery.contains(table) && header_of(table).tag() == Tag::ExtBytes {
+                        assert!(
+                            self.old.owns_ext_table(table) || self.nursery.owns_ext_table(table),
+                            "bytearray table {:p} not owned by the heap", table
+                        );
+
+                        // A minor collection only hands over the tables of
+                        // the bytearrays it sees, like any other reference
+                        assert!(
+                            !from_old || self.old.owns_ext_table(table),
+                            "bytearray table {:p} owned by the nursery from an old block that is not remembered", table
+                        );
+                    } else {
+                        self.check(table, Tag::Bytes, from_old);
+                        assert!(
+                            ba.capacity() < LARGE_OBJECT_SIZE,
+                            "large bytearray table {:p} in the heap", table
+                        );
                     }
-                    check(alloc, slot.key as *const u8, Tag::Str);
-                    check_val(alloc, slot.val);
+                }
+
+                Tag::SharedByteArray => {
+                    let sba = unsafe { &*(p as *const SharedByteArray) };
+                    assert!(
+                        self.old.retains_shared(sba.bytes) || self.nursery.retains_shared(sba.bytes),
+                        "shared bytes {:p} not retained by the heap", sba.bytes
+                    );
+                }
+
+                Tag::Dict => {
+                    let dict = unsafe { &*(p as *const Dict) };
+                    let table = dict.table as *const u8;
+                    self.check(table, Tag::SlotTable, from_old);
+                    self.check_slots(table, from_old);
                 }
             }
         }
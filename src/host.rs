@@ -1,9 +1,16 @@
-use std::thread;
 use std::time::Duration;
-use crate::vm::{VM, Actor};
+use crate::vm::{VM, Actor, ActorLimits};
 use crate::value::*;
-use crate::ast::{Expr, Function, Program};
+use crate::ast::{Expr, Function, Program, RANGE_ID};
+use crate::object::Object;
+use crate::array::Array;
 use crate::str::Str;
+use crate::json::{json_parse, json_stringify, Json};
+use crate::alloc::Tag;
+use crate::gc::HeapCount;
+use crate::snapshot::heap_snapshot;
+use crate::timer::{timer_after, timer_every, timer_cancel};
+use crate::permissions::Permission;
 use crate::*;
 
 /// Host function signature
@@ -79,14 +86,32 @@ pub fn get_host_const(name: &str, fun: &Function, prog: &Program) -> Expr
     static MAKE_DIR: HostFn = HostFn { name: "make_dir", f: Fn1(make_dir) };
     static VM_SHRINK_HEAP: HostFn = HostFn { name: "vm_shrink_heap", f: Fn1(vm_shrink_heap) };
     static VM_GC_COLLECT: HostFn = HostFn { name: "vm_gc_collect", f: Fn0(vm_gc_collect) };
+    static VM_GC_STATS: HostFn = HostFn { name: "vm_gc_stats", f: Fn0(vm_gc_stats) };
+    static VM_HEAP_HISTOGRAM: HostFn = HostFn { name: "vm_heap_histogram", f: Fn0(vm_heap_histogram) };
+    static VM_HEAP_SNAPSHOT: HostFn = HostFn { name: "vm_heap_snapshot", f: Fn1(vm_heap_snapshot) };
     static ACTOR_ID: HostFn = HostFn { name: "actor_id", f: Fn0(actor_id) };
     static ACTOR_PARENT: HostFn = HostFn { name: "actor_parent", f: Fn0(actor_parent) };
     static ACTOR_SLEEP: HostFn = HostFn { name: "actor_sleep", f: Fn1(actor_sleep) };
     static ACTOR_SPAWN: HostFn = HostFn { name: "actor_spawn", f: Fn1(actor_spawn) };
+    static ACTOR_SPAWN_WITH: HostFn = HostFn { name: "actor_spawn_with", f: Fn2(actor_spawn_with) };
     static ACTOR_JOIN: HostFn = HostFn { name: "actor_join", f: Fn1(actor_join) };
+    static ACTOR_MONITOR: HostFn = HostFn { name: "actor_monitor", f: Fn1(actor_monitor) };
+    static ACTOR_KILL: HostFn = HostFn { name: "actor_kill", f: Fn1(actor_kill) };
+    static ACTOR_ALIVE: HostFn = HostFn { name: "actor_alive", f: Fn1(actor_alive) };
+    static ACTOR_LIST: HostFn = HostFn { name: "actor_list", f: Fn0(actor_list) };
+    static ACTOR_MAILBOX_LEN: HostFn = HostFn { name: "actor_mailbox_len", f: Fn0(actor_mailbox_len) };
+    static ACTOR_REGISTER: HostFn = HostFn { name: "actor_register", f: Fn1(actor_register) };
+    static ACTOR_LOOKUP: HostFn = HostFn { name: "actor_lookup", f: Fn1(actor_lookup) };
+    static ACTOR_UNREGISTER: HostFn = HostFn { name: "actor_unregister", f: Fn1(actor_unregister) };
     static ACTOR_SEND: HostFn = HostFn { name: "actor_send", f: Fn2(actor_send) };
+    static ACTOR_SEND_MOVE: HostFn = HostFn { name: "actor_send_move", f: Fn2(actor_send_move) };
     static ACTOR_RECV: HostFn = HostFn { name: "actor_recv", f: Fn0(actor_recv) };
+    static ACTOR_RECV_TIMEOUT: HostFn = HostFn { name: "actor_recv_timeout", f: Fn1(actor_recv_timeout) };
+    static ACTOR_RECV_MATCH: HostFn = HostFn { name: "actor_recv_match", f: Fn1(actor_recv_match) };
     static ACTOR_POLL: HostFn = HostFn { name: "actor_poll", f: Fn0(actor_poll) };
+    static TIMER_AFTER: HostFn = HostFn { name: "timer_after", f: Fn2(timer_after) };
+    static TIMER_EVERY: HostFn = HostFn { name: "timer_every", f: Fn2(timer_every) };
+    static TIMER_CANCEL: HostFn = HostFn { name: "timer_cancel", f: Fn1(timer_cancel) };
     static WINDOW_CREATE: HostFn = HostFn { name: "window_create", f: Fn4(window_create) };
     static WINDOW_DRAW_FRAME: HostFn = HostFn { name: "window_draw_frame", f: Fn2(window_draw_frame) };
     static AUDIO_OPEN_OUTPUT: HostFn = HostFn { name: "audio_open_output", f: Fn2(audio_open_output) };
@@ -94,6 +119,9 @@ pub fn get_host_const(name: &str, fun: &Function, prog: &Program) -> Expr
     static AUDIO_OPEN_INPUT: HostFn = HostFn { name: "audio_open_input", f: Fn2(audio_open_input) };
     static AUDIO_READ_SAMPLES: HostFn = HostFn { name: "audio_read_samples", f: Fn4(audio_read_samples) };
     static EXIT: HostFn = HostFn { name: "exit", f: Fn1(exit) };
+    static RANGE: HostFn = HostFn { name: "range", f: Fn3(range) };
+    static JSON_PARSE: HostFn = HostFn { name: "json_parse", f: Fn1(json_parse) };
+    static JSON_STRINGIFY: HostFn = HostFn { name: "json_stringify", f: Fn2(json_stringify) };
 
     let fn_ref = match name
     {
@@ -113,15 +141,34 @@ pub fn get_host_const(name: &str, fun: &Function, prog: &Program) -> Expr
 
         "vm_shrink_heap" => &VM_SHRINK_HEAP,
         "vm_gc_collect" => &VM_GC_COLLECT,
+        "vm_gc_stats" => &VM_GC_STATS,
+        "vm_heap_histogram" => &VM_HEAP_HISTOGRAM,
+        "vm_heap_snapshot" => &VM_HEAP_SNAPSHOT,
         "actor_id" => &ACTOR_ID,
         "actor_parent" => &ACTOR_PARENT,
         "actor_sleep" => &ACTOR_SLEEP,
         "actor_spawn" => &ACTOR_SPAWN,
+        "actor_spawn_with" => &ACTOR_SPAWN_WITH,
         "actor_join" => &ACTOR_JOIN,
+        "actor_monitor" => &ACTOR_MONITOR,
+        "actor_kill" => &ACTOR_KILL,
+        "actor_alive" => &ACTOR_ALIVE,
+        "actor_list" => &ACTOR_LIST,
+        "actor_mailbox_len" => &ACTOR_MAILBOX_LEN,
+        "actor_register" => &ACTOR_REGISTER,
+        "actor_lookup" => &ACTOR_LOOKUP,
+        "actor_unregister" => &ACTOR_UNREGISTER,
         "actor_send" => &ACTOR_SEND,
+        "actor_send_move" => &ACTOR_SEND_MOVE,
         "actor_recv" => &ACTOR_RECV,
+        "actor_recv_timeout" => &ACTOR_RECV_TIMEOUT,
+        "actor_recv_match" => &ACTOR_RECV_MATCH,
         "actor_poll" => &ACTOR_POLL,
 
+        "timer_after" => &TIMER_AFTER,
+        "timer_every" => &TIMER_EVERY,
+        "timer_cancel" => &TIMER_CANCEL,
+
         "window_create" => &WINDOW_CREATE,
         "window_draw_frame" => &WINDOW_DRAW_FRAME,
 
@@ -132,6 +179,10 @@ pub fn get_host_const(name: &str, fun: &Function, prog: &Program) -> Expr
         "audio_read_samples" => &AUDIO_READ_SAMPLES,
 
         "exit" => &EXIT,
+        "range" => &RANGE,
+
+        "json_parse" => &JSON_PARSE,
+        "json_stringify" => &JSON_STRINGIFY,
 
         _ => panic!("unknown host constant `{name}`")
     };
@@ -423,6 +474,7 @@ fn read_file(actor: &mut Actor, file_path: Value) -> Result<Value, String>
     use crate::bytearray::ByteArray;
 
     let file_path = unwrap_str!(file_path);
+    actor.check_permission(Permission::FsRead(file_path))?;
 
     if !is_safe_path(&file_path) {
         return Err(format!("requested file path breaks sandboxing rules: {}", file_path));
@@ -447,6 +499,7 @@ fn read_file(actor: &mut Actor, file_path: Value) -> Result<Value, String>
 fn read_file_utf8(actor: &mut Actor, file_path: Value) -> Result<Value, String>
 {
     let file_path = unwrap_str!(file_path);
+    actor.check_permission(Permission::FsRead(file_path))?;
 
     if !is_safe_path(&file_path) {
         return Err(format!("requested file path breaks sandboxing rules: {}", file_path));
@@ -466,9 +519,10 @@ fn read_file_utf8(actor: &mut Actor, file_path: Value) -> Result<Value, String>
 }
 
 /// Writes the contents of a ByteArray to a file
-fn write_file(_actor: &mut Actor, file_path: Value, bytes: Value) -> Result<Value, String>
+fn write_file(actor: &mut Actor, file_path: Value, bytes: Value) -> Result<Value, String>
 {
     let file_path = unwrap_str!(file_path);
+    actor.check_permission(Permission::FsWrite(file_path))?;
     let bytes = unwrap_ba!(bytes);
     let bytes = unsafe { bytes.get_slice(0, bytes.num_bytes()) };
 
@@ -484,9 +538,10 @@ fn write_file(_actor: &mut Actor, file_path: Value, bytes: Value) -> Result<Valu
 
 /// Create a directory, along with any missing parent directories.
 /// Succeeds if the directory already exists.
-fn make_dir(_actor: &mut Actor, dir_path: Value) -> Result<Value, String>
+fn make_dir(actor: &mut Actor, dir_path: Value) -> Result<Value, String>
 {
     let dir_path = unwrap_str!(dir_path);
+    actor.check_permission(Permission::FsWrite(dir_path))?;
 
     if !is_safe_path(&dir_path) {
         return Err(format!("requested file path breaks sandboxing rules: {}", dir_path));
@@ -498,7 +553,8 @@ fn make_dir(_actor: &mut Actor, dir_path: Value) -> Result<Value, String>
     }
 }
 
-/// Shrink the heap to a smaller size
+/// Shrink the nursery the actor allocates out of to a smaller size, so
+/// that it gets collected sooner. This is primarily used to test the GC.
 fn vm_shrink_heap(actor: &mut Actor, new_size: Value) -> Result<Value, String>
 {
     let new_size = unwrap_usize!(new_size);
@@ -519,10 +575,104 @@ fn vm_shrink_heap(actor: &mut Actor, new_size: Value) -> Result<Value, String>
 /// Manually trigger garbage collection in the current actor
 fn vm_gc_collect(actor: &mut Actor) -> Result<Value, String>
 {
-    actor.gc_collect(0, &mut []);
+    actor.gc_collect_full(&mut []);
     Ok(Value::NIL)
 }
 
+/// Get a dict of statistics about the collections of the current actor
+fn vm_gc_stats(actor: &mut Actor) -> Result<Value, String>
+{
+    let stats = actor.gc_stats();
+    let int = |n: usize| Json::Int(n as i64);
+    let ms = |d: Duration| Json::Float(d.as_secs_f64() * 1000.0);
+
+    // Blocks of each kind that were live as of the last collection
+    let live_counts = Tag::all()
+        .map(|tag| (format!("{:?}", tag), stats.live_blocks[tag as usize]))
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| (name, int(count)))
+        .collect();
+
+    let json = Json::Object(vec![
+        ("collections".into(), int(stats.num_minor + stats.num_full)),
+        ("minor_collections".into(), int(stats.num_minor)),
+        ("full_collections".into(), int(stats.num_full)),
+        ("total_pause_ms".into(), ms(stats.total_pause)),
+        ("last_pause_ms".into(), ms(stats.last_pause)),
+        ("bytes_copied".into(), int(stats.bytes_copied)),
+        ("heap_size".into(), int(actor.heap_size())),
+        ("bytes_free".into(), int(actor.heap_free())),
+        ("large_object_bytes".into(), int(actor.large_object_bytes())),
+        ("live_counts".into(), Json::Object(live_counts)),
+    ]);
+
+    Ok(json.to_heap(actor))
+}
+
+/// Collect the heap of the current actor, then get a dict counting what
+/// is live in it by type and by class name, along with the bytes it uses
+fn vm_heap_histogram(actor: &mut Actor) -> Result<Value, String>
+{
+    let histogram = actor.heap_histogram();
+
+    let entry = |counts: &HeapCount| Json::Object(vec![
+        ("count".into(), Json::Int(counts.count as i64)),
+        ("bytes".into(), Json::Int(counts.bytes as i64)),
+    ]);
+
+    let types = Tag::all()
+        .filter(|tag| histogram.by_tag[*tag as usize].count > 0)
+        .map(|tag| {
+            let name = match tag {
+                Tag::Str => "String".to_string(),
+                tag => format!("{:?}", tag),
+            };
+            (name, entry(&histogram.by_tag[tag as usize]))
+        })
+        .collect();
+
+    // Separate classes can share a name, and are counted together
+    let mut by_name: Vec<(String, HeapCount)> = Vec::new();
+    for (class_id, counts) in &histogram.by_class {
+        let name = actor.get_class_name(*class_id);
+        match by_name.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => {
+                total.count += counts.count;
+                total.bytes += counts.bytes;
+            }
+            None => by_name.push((name, *counts)),
+        }
+    }
+    let classes = by_name.iter().map(|(name, counts)| (name.clone(), entry(counts))).collect();
+
+    let json = Json::Object(vec![
+        ("types".into(), Json::Object(types)),
+        ("classes".into(), Json::Object(classes)),
+    ]);
+
+    Ok(json.to_heap(actor))
+}
+
+/// Write a snapshot of the heap of the current actor to a file, in the
+/// V8 .heapsnapshot format. Returns false if the file can't be written.
+fn vm_heap_snapshot(actor: &mut Actor, file_path: Value) -> Result<Value, String>
+{
+    // Taking the snapshot collects the heap, which moves the string
+    let file_path = unwrap_str!(file_path).to_string();
+    actor.check_permission(Permission::FsWrite(&file_path))?;
+
+    if !is_safe_path(&file_path) {
+        return Err(format!("requested file path breaks sandboxing rules: {}", file_path));
+    }
+
+    let snapshot = heap_snapshot(actor);
+
+    match std::fs::write(file_path, snapshot) {
+        Err(_) => Ok(Value::FALSE),
+        Ok(_) => Ok(Value::TRUE)
+    }
+}
+
 /// Get the id of the current actor
 fn actor_id(actor: &mut Actor) -> Result<Value, String>
 {
@@ -539,10 +689,10 @@ fn actor_parent(actor: &mut Actor) -> Result<Value, String>
 }
 
 /// Make the current actor sleep
-fn actor_sleep(_actor: &mut Actor, msecs: Value) -> Result<Value, String>
+fn actor_sleep(actor: &mut Actor, msecs: Value) -> Result<Value, String>
 {
     let msecs = unwrap_u64!(msecs);
-    thread::sleep(Duration::from_millis(msecs));
+    actor.sleep(Duration::from_millis(msecs));
     Ok(Value::NIL)
 }
 
@@ -551,6 +701,45 @@ fn actor_sleep(_actor: &mut Actor, msecs: Value) -> Result<Value, String>
 /// Returns an actor id
 fn actor_spawn(actor: &mut Actor, fun: Value) -> Result<Value, String>
 {
+    spawn(actor, fun, ActorLimits::default())
+}
+
+/// Spawn a new actor, with a dictionary of options setting limits
+/// that only apply to it
+fn actor_spawn_with(actor: &mut Actor, fun: Value, options: Value) -> Result<Value, String>
+{
+    let options = unwrap_dict!(options);
+    let mut limits = ActorLimits::default();
+
+    let mut slot_idx = 0;
+    while let Some((idx, key, val)) = options.next_entry(slot_idx) {
+        match key.as_str() {
+            "max_heap" => {
+                let max_heap = unwrap_usize!(val);
+                if max_heap == 0 {
+                    return Err("max_heap must be greater than zero".into());
+                }
+                limits.max_heap = Some(max_heap);
+            }
+            "max_stack_depth" => {
+                let max_stack_depth = unwrap_usize!(val);
+                if max_stack_depth == 0 {
+                    return Err("max_stack_depth must be greater than zero".into());
+                }
+                limits.max_stack_depth = Some(max_stack_depth);
+            }
+            name => return Err(format!("unknown actor_spawn_with option `{}`", name)),
+        }
+        slot_idx = idx + 1;
+    }
+
+    spawn(actor, fun, limits)
+}
+
+fn spawn(actor: &mut Actor, fun: Value, limits: ActorLimits) -> Result<Value, String>
+{
+    actor.check_permission(Permission::Actors)?;
+
     if fun.to_clos().is_none() && !fun.is_fun() {
         return Err("actor_spawn received non-function value".into());
     }
@@ -566,15 +755,95 @@ fn actor_spawn(actor: &mut Actor, fun: Value) -> Result<Value, String>
         ));
     }
 
-    let actor_id = VM::new_actor(actor, fun, vec![]);
+    let actor_id = VM::new_actor(actor, fun, vec![], limits);
     Ok(actor.int64(actor_id as i64))
 }
 
-/// Wait for a thread to terminate, produce the return value
+/// Wait for a thread to terminate, produce the return value,
+/// or an ActorError if the actor failed
 fn actor_join(actor: &mut Actor, actor_id: Value) -> Result<Value, String>
 {
     let id = unwrap_u64!(actor_id);
-    Ok(VM::join_actor(&actor.vm, id))
+
+    match VM::join_actor(actor, id)? {
+        Ok(val) => Ok(val),
+        Err(failure) => {
+            actor.gc_check(failure.alloc_size(), &mut []);
+            Ok(failure.to_value(&mut actor.alloc))
+        }
+    }
+}
+
+/// Be sent an ActorError message if another actor fails
+fn actor_monitor(actor: &mut Actor, actor_id: Value) -> Result<Value, String>
+{
+    let id = unwrap_u64!(actor_id);
+    VM::monitor_actor(&actor.vm, actor.actor_id, id)?;
+    Ok(Value::NIL)
+}
+
+/// Have an actor end the next time it makes a call or loops around
+/// Returns false if the actor isn't running
+fn actor_kill(actor: &mut Actor, actor_id: Value) -> Result<Value, String>
+{
+    let id = unwrap_u64!(actor_id);
+    Ok(Value::from(VM::kill_actor(&actor.vm, id)?))
+}
+
+/// Check whether an actor is still running
+fn actor_alive(actor: &mut Actor, actor_id: Value) -> Result<Value, String>
+{
+    let id = unwrap_u64!(actor_id);
+    Ok(Value::from(VM::actor_alive(&actor.vm, id)))
+}
+
+/// Get an array of the ids of the running actors
+fn actor_list(actor: &mut Actor) -> Result<Value, String>
+{
+    let ids = VM::actor_list(&actor.vm);
+
+    actor.gc_check(Array::alloc_size(ids.len()), &mut []);
+    let arr = Array::with_capacity(ids.len(), &mut actor.alloc);
+    for id in ids {
+        arr.as_arr().push(Value::fixnum(id as i64), &mut actor.alloc);
+    }
+
+    Ok(arr)
+}
+
+/// Get the number of messages waiting in the current actor's mailbox
+fn actor_mailbox_len(actor: &mut Actor) -> Result<Value, String>
+{
+    Ok(Value::fixnum(actor.mailbox_len() as i64))
+}
+
+/// Register a name for the current actor, which other actors
+/// can use to look up its id
+fn actor_register(actor: &mut Actor, name: Value) -> Result<Value, String>
+{
+    let name = unwrap_str!(name);
+    VM::register_actor(&actor.vm, actor.actor_id, name)?;
+    Ok(Value::NIL)
+}
+
+/// Get the id of the actor registered under a name
+/// Returns nil if no actor is
+fn actor_lookup(actor: &mut Actor, name: Value) -> Result<Value, String>
+{
+    let name = unwrap_str!(name);
+
+    match VM::lookup_actor(&actor.vm, name) {
+        Some(id) => Ok(actor.int64(id as i64)),
+        None => Ok(Value::NIL),
+    }
+}
+
+/// Release a registered actor name
+/// Returns false if the name wasn't registered
+fn actor_unregister(actor: &mut Actor, name: Value) -> Result<Value, String>
+{
+    let name = unwrap_str!(name);
+    Ok(Value::from(VM::unregister_actor(&actor.vm, name)))
 }
 
 /// Send a message to an actor
@@ -591,6 +860,21 @@ fn actor_send(actor: &mut Actor, actor_id: Value, msg: Value) -> Result<Value, S
     }
 }
 
+/// Send a byte array to an actor without copying its bytes, which
+/// leaves the byte array detached. This will return false in case of failure
+fn actor_send_move(actor: &mut Actor, actor_id: Value, ba: Value) -> Result<Value, String>
+{
+    let actor_id = unwrap_u64!(actor_id);
+    unwrap_ba!(ba);
+    let res = actor.send_move(actor_id, ba);
+
+    if res.is_ok() {
+        Ok(Value::TRUE)
+    } else {
+        Ok(Value::FALSE)
+    }
+}
+
 /// Receive a message from the current actor's queue
 /// This will block until a message is available
 fn actor_recv(actor: &mut Actor) -> Result<Value, String>
@@ -598,6 +882,40 @@ fn actor_recv(actor: &mut Actor) -> Result<Value, String>
     Ok(actor.recv())
 }
 
+/// Receive a message from the current actor's queue, waiting
+/// for at most a given number of milliseconds
+/// Returns nil if no message arrived in time
+fn actor_recv_timeout(actor: &mut Actor, msecs: Value) -> Result<Value, String>
+{
+    let msecs = unwrap_u64!(msecs);
+
+    Ok(match actor.recv_timeout(Duration::from_millis(msecs)) {
+        Some(msg_val) => msg_val,
+        None => Value::NIL,
+    })
+}
+
+/// Receive the first message for which a predicate function returns
+/// true, leaving the others queued for later receives
+/// This will block until such a message is available
+fn actor_recv_match(actor: &mut Actor, pred: Value) -> Result<Value, String>
+{
+    let fun_id = match pred.to_fun_id() {
+        Some(fun_id) => fun_id,
+        None => return Err(format!("expected function value but got {:?}", pred)),
+    };
+
+    let num_params = actor.get_num_params(fun_id);
+    if num_params != 1 {
+        return Err(format!(
+            "predicate passed to actor_recv_match should take one argument, but takes {}",
+            num_params
+        ));
+    }
+
+    actor.recv_match(pred)
+}
+
 /// Receive a message from the current actor's queue
 /// This will block until a message is available
 fn actor_poll(actor: &mut Actor) -> Result<Value, String>
@@ -608,6 +926,30 @@ fn actor_poll(actor: &mut Actor) -> Result<Value, String>
     })
 }
 
+/// Create a lazily evaluated range of integers, from start
+/// up to but not including end, for use in for-in loops
+fn range(actor: &mut Actor, mut start: Value, mut end: Value, mut step: Value) -> Result<Value, String>
+{
+    unwrap_i64!(start);
+    unwrap_i64!(end);
+
+    if unwrap_i64!(step) == 0 {
+        return Err("range step cannot be zero".into());
+    }
+
+    let num_slots = actor.get_num_slots(RANGE_ID);
+    actor.gc_check(
+        Object::alloc_size(num_slots),
+        &mut [&mut start, &mut end, &mut step]
+    );
+
+    let obj = Object::new(RANGE_ID, num_slots, &mut actor.alloc);
+    actor.set_field(obj, "start", start);
+    actor.set_field(obj, "end", end);
+    actor.set_field(obj, "step", step);
+    Ok(obj)
+}
+
 /// End program execution
 fn exit(_actor: &mut Actor, val: Value) -> Result<Value, String>
 {
@@ -0,0 +1,527 @@
+use crate::vm::Actor;
+use crate::value::*;
+use crate::alloc::{Alloc, HEADER_SIZE};
+use crate::array::Array;
+use crate::dict::Dict;
+use crate::str::Str;
+use crate::bigint::{BigInt, BigNum};
+use crate::*;
+
+/// How deeply arrays and objects can be nested. This bounds the recursion
+/// when parsing, and catches cyclic values when serializing.
+const MAX_DEPTH: usize = 512;
+
+/// Parsed JSON value. The whole input is parsed before anything is
+/// allocated in the actor heap, so that the space needed can be reserved
+/// up front and no collection can happen while the values are created.
+/// Host functions that return nested data build one of these for the same
+/// reason.
+pub enum Json
+{
+    Null,
+    Bool(bool),
+    Int(i64),
+    BigInt(BigNum),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Capacity to give a dict so that inserting a number of
+/// entries never needs to grow its table
+fn dict_capacity(num_entries: usize) -> usize
+{
+    num_entries * 4 / 3 + 2
+}
+
+/// Bytes needed to allocate a string, counting alignment padding
+fn str_size(s: &str) -> usize
+{
+    Str::alloc_size(s.len()) + size_of::<Value>()
+}
+
+impl Json
+{
+    /// Upper bound on the bytes needed to allocate this value
+    fn alloc_size(&self) -> usize
+    {
+        match self {
+            Json::Null | Json::Bool(_) => 0,
+            Json::Int(v) if Value::fits_fixnum(*v) => 0,
+            Json::Float(v) if Value::try_flonum(*v).is_some() => 0,
+            Json::Int(_) | Json::Float(_) => HEADER_SIZE + size_of::<Value>(),
+            Json::BigInt(num) => BigInt::alloc_size(num.num_bits().div_ceil(32)),
+            Json::Str(s) => str_size(s),
+
+            Json::Array(elems) => {
+                Array::alloc_size(elems.len()) +
+                elems.iter().map(|e| e.alloc_size()).sum::<usize>()
+            }
+
+            Json::Object(entries) => {
+                Dict::alloc_size(dict_capacity(entries.len())) +
+                entries.iter().map(|(k, v)| str_size(k) + v.alloc_size()).sum::<usize>()
+            }
+        }
+    }
+
+    /// Create the value in the actor heap, collecting first to make room
+    pub fn to_heap(&self, actor: &mut Actor) -> Value
+    {
+        actor.gc_check(self.alloc_size(), &mut []);
+        self.to_value(&mut actor.alloc)
+    }
+
+    /// Create the value in a heap where room was already reserved for it
+    fn to_value(&self, alloc: &mut Alloc) -> Value
+    {
+        match self {
+            Json::Null => Value::NIL,
+            Json::Bool(b) => Value::from(*b),
+
+            Json::Int(v) => match Value::try_fixnum(*v) {
+                Some(v) => v,
+                None => alloc.heap_int64(*v),
+            },
+
+            Json::BigInt(num) => BigInt::from_num(num, alloc),
+
+            Json::Float(v) => match Value::try_flonum(*v) {
+                Some(v) => v,
+                None => alloc.heap_float64(*v),
+            },
+
+            Json::Str(s) => Str::new(s, alloc),
+
+            Json::Array(elems) => {
+                let arr = Array::with_capacity(elems.len(), alloc);
+                for elem in elems {
+                    let val = elem.to_value(alloc);
+                    arr.as_arr().push(val, alloc);
+                }
+                arr
+            }
+
+            Json::Object(entries) => {
+                let dict = Dict::with_capacity(dict_capacity(entries.len()), alloc);
+                for (key, val) in entries {
+                    let key = Str::new(key, alloc);
+                    let val = val.to_value(alloc);
+                    dict.as_dict().set(key.as_string(), val, alloc);
+                }
+                dict
+            }
+        }
+    }
+}
+
+/// Recursive descent JSON parser
+struct Parser<'a>
+{
+    input: &'a str,
+
+    // Current byte index in the input
+    idx: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    /// Produce an error with the line and column of the current position
+    fn error<T>(&self, msg: &str) -> Result<T, String>
+    {
+        let before = &self.input[..self.idx];
+        let line_no = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let col_no = before[line_start..].chars().count() + 1;
+        Err(format!("invalid JSON at line {}, column {}: {}", line_no, col_no, msg))
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        self.input.as_bytes().get(self.idx).copied()
+    }
+
+    fn eat_ws(&mut self)
+    {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.idx += 1;
+        }
+    }
+
+    /// Consume a given string if the input continues with it
+    fn match_str(&mut self, s: &str) -> bool
+    {
+        if self.input[self.idx..].starts_with(s) {
+            self.idx += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Json, String>
+    {
+        if depth > MAX_DEPTH {
+            return self.error("arrays and objects are nested too deeply");
+        }
+
+        self.eat_ws();
+
+        match self.peek() {
+            None => self.error("unexpected end of input"),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'"') => Ok(Json::Str(self.parse_string()?)),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) if self.match_str("true") => Ok(Json::Bool(true)),
+            Some(_) if self.match_str("false") => Ok(Json::Bool(false)),
+            Some(_) if self.match_str("null") => Ok(Json::Null),
+            Some(_) => self.error("expected a value"),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Json, String>
+    {
+        // Skip the opening bracket
+        self.idx += 1;
+        let mut elems = Vec::new();
+
+        self.eat_ws();
+        if self.match_str("]") {
+            return Ok(Json::Array(elems));
+        }
+
+        loop {
+            elems.push(self.parse_value(depth + 1)?);
+
+            self.eat_ws();
+            if self.match_str("]") {
+                return Ok(Json::Array(elems));
+            }
+            if !self.match_str(",") {
+                return self.error("expected `,` or `]` in array");
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Json, String>
+    {
+        // Skip the opening brace
+        self.idx += 1;
+        let mut entries = Vec::new();
+
+        self.eat_ws();
+        if self.match_str("}") {
+            return Ok(Json::Object(entries));
+        }
+
+        loop {
+            self.eat_ws();
+            if self.peek() != Some(b'"') {
+                return self.error("expected string key in object");
+            }
+            let key = self.parse_string()?;
+
+            self.eat_ws();
+            if !self.match_str(":") {
+                return self.error("expected `:` after object key");
+            }
+
+            let val = self.parse_value(depth + 1)?;
+            entries.push((key, val));
+
+            self.eat_ws();
+            if self.match_str("}") {
+                return Ok(Json::Object(entries));
+            }
+            if !self.match_str(",") {
+                return self.error("expected `,` or `}` in object");
+            }
+        }
+    }
+
+    /// Parse the four hex digits of a \u escape
+    fn parse_hex4(&mut self) -> Result<u32, String>
+    {
+        let digits = match self.input.get(self.idx..self.idx + 4) {
+            Some(digits) if digits.bytes().all(|b| b.is_ascii_hexdigit()) => digits,
+            _ => return self.error("expected four hex digits in \\u escape"),
+        };
+
+        self.idx += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn parse_string(&mut self) -> Result<String, String>
+    {
+        // Skip the opening quote
+        self.idx += 1;
+        let mut out = String::new();
+
+        loop {
+            let ch = match self.input[self.idx..].chars().next() {
+                Some(ch) => ch,
+                None => return self.error("unterminated string"),
+            };
+
+            match ch {
+                '"' => {
+                    self.idx += 1;
+                    return Ok(out);
+                }
+
+                '\\' => {
+                    self.idx += 1;
+                    let esc = match self.peek() {
+                        Some(esc) => esc,
+                        None => return self.error("unterminated string"),
+                    };
+                    self.idx += 1;
+
+                    match esc {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+
+                            // Characters outside the basic multilingual
+                            // plane are written as a surrogate pair
+                            if (0xD800..0xDC00).contains(&code) && self.match_str("\\u") {
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return self.error("invalid low surrogate in \\u escape");
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+
+                            match char::from_u32(code) {
+                                Some(ch) => out.push(ch),
+                                None => return self.error("invalid code point in \\u escape"),
+                            }
+                        }
+
+                        _ => {
+                            self.idx -= 1;
+                            return self.error("invalid escape sequence in string");
+                        }
+                    }
+                }
+
+                '\0'..='\x1F' => return self.error("control character in string"),
+
+                _ => {
+                    out.push(ch);
+                    self.idx += ch.len_utf8();
+                }
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String>
+    {
+        let start = self.idx;
+        let mut is_float = false;
+
+        let digits = |p: &mut Self| {
+            let start = p.idx;
+            while let Some(b'0'..=b'9') = p.peek() {
+                p.idx += 1;
+            }
+            p.idx - start
+        };
+
+        self.match_str("-");
+
+        // Leading zeros are not allowed
+        if self.match_str("0") {
+            if let Some(b'0'..=b'9') = self.peek() {
+                return self.error("leading zeros are not allowed in numbers");
+            }
+        } else if digits(self) == 0 {
+            return self.error("expected digits in number");
+        }
+
+        if self.match_str(".") {
+            is_float = true;
+            if digits(self) == 0 {
+                return self.error("expected digits after decimal point");
+            }
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.idx += 1;
+            is_float = true;
+            if !self.match_str("+") {
+                self.match_str("-");
+            }
+            if digits(self) == 0 {
+                return self.error("expected digits in exponent");
+            }
+        }
+
+        let num_str = &self.input[start..self.idx];
+
+        // Integers too large for an Int64 become BigInts,
+        // as they are written out by $json_stringify
+        if !is_float {
+            if let Ok(v) = num_str.parse::<i64>() {
+                return Ok(Json::Int(v));
+            }
+            if let Some(num) = BigNum::parse(num_str, 10) {
+                return Ok(Json::BigInt(num));
+            }
+        }
+
+        match num_str.parse::<f64>() {
+            Ok(v) => Ok(Json::Float(v)),
+            Err(_) => {
+                self.idx = start;
+                self.error("invalid number")
+            }
+        }
+    }
+}
+
+/// Parse a JSON string into nested dicts, arrays,
+/// strings, integers, floats, booleans and nil
+pub fn json_parse(actor: &mut Actor, s: Value) -> Result<Value, String>
+{
+    let s = unwrap_str!(s);
+    let mut parser = Parser { input: s, idx: 0 };
+
+    let json = parser.parse_value(0)?;
+
+    parser.eat_ws();
+    if parser.idx < s.len() {
+        return parser.error("unexpected characters after value");
+    }
+
+    Ok(json.to_heap(actor))
+}
+
+/// Append a JSON string literal, with escapes
+pub fn write_str(out: &mut String, s: &str)
+{
+    out.push('"');
+
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\0'..='\x1F' => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            _ => out.push(ch),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Start a new line at a given nesting depth, if indenting
+fn write_newline(out: &mut String, indent: usize, depth: usize)
+{
+    if indent > 0 {
+        out.push('\n');
+        out.extend(std::iter::repeat_n(' ', indent * depth));
+    }
+}
+
+fn write_value(actor: &mut Actor, out: &mut String, val: Value, indent: usize, depth: usize) -> Result<(), String>
+{
+    if depth > MAX_DEPTH {
+        return Err("value is nested too deeply, or contains a cycle".into());
+    }
+
+    match val.type_of() {
+        Type::Nil => out.push_str("null"),
+        Type::Bool => out.push_str(if val.as_bool() { "true" } else { "false" }),
+        Type::Int64 => out.push_str(&val.to_i64().unwrap().to_string()),
+        Type::BigInt => out.push_str(&val.as_bigint().to_num().to_string()),
+
+        Type::Float64 => {
+            let v = val.to_f64().unwrap();
+            if !v.is_finite() {
+                return Err(format!("cannot serialize non-finite float {} to JSON", v));
+            }
+
+            // Debug formatting keeps a decimal point on integral
+            // values, so that they parse back as floats
+            out.push_str(&format!("{:?}", v));
+        }
+
+        Type::String => write_str(out, val.as_str()),
+
+        Type::Array => {
+            let len = val.as_arr().len();
+            out.push('[');
+
+            for idx in 0..len {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write_newline(out, indent, depth + 1);
+                write_value(actor, out, val.as_arr().get(idx), indent, depth + 1)?;
+            }
+
+            if len > 0 {
+                write_newline(out, indent, depth);
+            }
+            out.push(']');
+        }
+
+        Type::Dict => {
+            out.push('{');
+
+            let mut slot_idx = 0;
+            while let Some((idx, key, elem)) = val.as_dict().next_entry(slot_idx) {
+                if slot_idx > 0 {
+                    out.push(',');
+                }
+                write_newline(out, indent, depth + 1);
+                write_str(out, key.as_str());
+                out.push_str(if indent > 0 { ": " } else { ":" });
+                write_value(actor, out, elem, indent, depth + 1)?;
+                slot_idx = idx + 1;
+            }
+
+            if slot_idx > 0 {
+                write_newline(out, indent, depth);
+            }
+            out.push('}');
+        }
+
+        Type::Object => {
+            let class_name = actor.get_class_name(val.as_obj().class_id);
+            return Err(format!("cannot serialize instance of class `{}` to JSON", class_name));
+        }
+
+        t => return Err(format!("cannot serialize value of type {:?} to JSON", t)),
+    }
+
+    Ok(())
+}
+
+/// Serialize a value to a JSON string. The indent is the number of spaces
+/// per nesting level, or zero or nil for output on a single line.
+pub fn json_stringify(actor: &mut Actor, val: Value, indent: Value) -> Result<Value, String>
+{
+    let indent = if indent.is_nil() { 0 } else { unwrap_usize!(indent) };
+
+    let mut out = String::new();
+    write_value(actor, &mut out, val, indent, 0)?;
+
+    actor.gc_check(Str::alloc_size(out.len()), &mut []);
+    Ok(Str::new(&out, &mut actor.alloc))
+}
@@ -13,6 +13,8 @@ mod object;
 mod closure;
 mod array;
 mod bytearray;
+mod shared;
+mod bigint;
 mod runtime;
 mod host;
 mod gc;
@@ -21,12 +23,21 @@ mod audio;
 mod exec_tests;
 mod str;
 mod dict;
+mod json;
+mod repl;
+mod disasm;
+mod permissions;
+mod timer;
+mod snapshot;
+mod sched;
 
 extern crate sdl2;
 use std::env;
 use std::process::exit;
 use std::sync::Mutex;
-use crate::vm::VM;
+use crate::vm::{VM, ActorLimits};
+use crate::value::Value;
+use crate::permissions::Permissions;
 use crate::ast::Program;
 use crate::parser::{parse_file, parse_str};
 
@@ -43,18 +54,50 @@ pub struct Options
     // String of code to be evaluated
     eval_str: Option<String>,
 
+    // Start an interactive session
+    repl: bool,
+
+    // Print the compiled bytecode instead of executing the program
+    dump_bytecode: bool,
+
+    // Print the bytecode of the main actor once the program has run,
+    // showing the call sites rewritten by inline caches
+    dump_bytecode_after_run: bool,
+
     // Input script file to parse/execute
     input_file: Option<String>,
 
+    // Permissions granted to the program
+    permissions: Permissions,
+
+    // Limits every actor runs under
+    limits: ActorLimits,
+
+    // Number of worker threads to run actors on
+    threads: Option<usize>,
+
     // Unnamed rest arguments
     rest: Vec<String>,
 }
 
+// Parse a size in bytes, which can be given in kilobytes, megabytes
+// or gigabytes with a K, M or G suffix, e.g. 64M
+fn parse_size(size: &str) -> Option<usize>
+{
+    let (digits, unit) = match size.char_indices().last()? {
+        (idx, 'K' | 'k') => (&size[..idx], 1024),
+        (idx, 'M' | 'm') => (&size[..idx], 1024 * 1024),
+        (idx, 'G' | 'g') => (&size[..idx], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n.checked_mul(unit),
+        _ => None,
+    }
+}
+
 // Parse the command-line arguments
-// TODO: parse permissions
-// --allow <permissions>
-// --deny <permissions>
-// --allow-all
 pub fn parse_args(args: Vec<String>) -> Options
 {
     let mut opts = Options::default();
@@ -100,6 +143,73 @@ pub fn parse_args(args: Vec<String>) -> Options
                 opts.eval_str = Some(read_arg!(arg));
             }
 
+            "--repl" => {
+                opts.repl = true;
+            }
+
+            "--allow-all" => {
+                opts.permissions.set_allow_all();
+            }
+
+            "--allow" | "--deny" => {
+                let specs = read_arg!(arg);
+
+                let result = if arg == "--allow" {
+                    opts.permissions.allow(&specs)
+                } else {
+                    opts.permissions.deny(&specs)
+                };
+
+                if let Err(msg) = result {
+                    println!("Error in {} command-line option: {}", arg, msg);
+                    exit(-1);
+                }
+            }
+
+            "--max-heap" => {
+                let size = read_arg!(arg);
+
+                match parse_size(&size) {
+                    Some(max_heap) => opts.limits.max_heap = Some(max_heap),
+                    None => {
+                        println!("Invalid size for {} command-line option: {}", arg, size);
+                        exit(-1);
+                    }
+                }
+            }
+
+            "--max-stack-depth" => {
+                let depth = read_arg!(arg);
+
+                match depth.parse::<usize>() {
+                    Ok(depth) if depth > 0 => opts.limits.max_stack_depth = Some(depth),
+                    _ => {
+                        println!("Invalid depth for {} command-line option: {}", arg, depth);
+                        exit(-1);
+                    }
+                }
+            }
+
+            "--threads" => {
+                let num = read_arg!(arg);
+
+                match num.parse::<usize>() {
+                    Ok(num) if num > 0 => opts.threads = Some(num),
+                    _ => {
+                        println!("Invalid thread count for {} command-line option: {}", arg, num);
+                        exit(-1);
+                    }
+                }
+            }
+
+            "--dump-bytecode" => {
+                opts.dump_bytecode = true;
+            }
+
+            "--dump-bytecode-after-run" => {
+                opts.dump_bytecode_after_run = true;
+            }
+
             _ => panic!("unknown option {}", arg)
         }
     }
@@ -141,6 +251,12 @@ fn main()
     let opts = parse_args(env::args().collect());
     //println!("{:?}", opts);
 
+    // With no input to run, start an interactive session
+    if opts.repl || (opts.input_file.is_none() && opts.eval_str.is_none()) {
+        repl::run_repl(opts.permissions, opts.limits, opts.threads);
+        return;
+    }
+
     let mut prog = parse_input(&opts);
 
     // Store the rest arguments in a global variable
@@ -168,9 +284,28 @@ fn main()
         return;
     }
 
+    if opts.dump_bytecode {
+        let actor = VM::compile_all(prog);
+        print!("{}", disasm::dump_bytecode(&actor));
+        return;
+    }
+
     let main_fn = prog.main_fn;
     let mut vm = VM::new(prog);
-    let ret = VM::call(&mut vm, main_fn, vec![]);
+    vm.lock().unwrap().set_permissions(opts.permissions);
+    vm.lock().unwrap().set_limits(opts.limits);
+    if let Some(num_threads) = opts.threads {
+        vm.lock().unwrap().set_threads(num_threads);
+    }
+
+    let ret = if opts.dump_bytecode_after_run {
+        let mut actor = VM::main_actor(&vm);
+        let ret = actor.call(Value::fun(main_fn), &[]);
+        print!("{}", disasm::dump_bytecode(&actor));
+        ret
+    } else {
+        VM::call(&mut vm, main_fn, vec![])
+    };
 
     // This is the value returned by the main unit
     if ret.is_nil() {
@@ -903,6 +903,48 @@ fn parse_block_stmt(input: &mut Lexer, prog: &mut Program) -> Result<StmtBox, Pa
     );
 }
 
+/// Parse the rest of a for-in loop, after its first variable name and
+/// either the `in` keyword or the comma preceding a second variable
+fn parse_for_in(
+    input: &mut Lexer,
+    prog: &mut Program,
+    var_name: String,
+    two_vars: bool,
+    pos: SrcPos
+) -> Result<StmtBox, ParseError>
+{
+    let mut var_names = vec![var_name];
+
+    if two_vars {
+        input.eat_ws()?;
+        var_names.push(input.parse_ident()?);
+
+        if !input.match_keyword("in")? {
+            return input.parse_error("expected `in` keyword in for-in loop");
+        }
+    }
+
+    if var_names.len() == 2 && var_names[0] == var_names[1] {
+        return input.parse_error("for-in loop variables must have distinct names");
+    }
+
+    let iter_expr = parse_expr(input, prog)?;
+    input.expect_token(")")?;
+
+    // Parse the loop body
+    let body_stmt = parse_stmt(input, prog)?;
+
+    StmtBox::new_ok(
+        Stmt::ForIn {
+            var_names,
+            iter_expr,
+            body_stmt,
+            decls: Vec::default(),
+        },
+        pos
+    )
+}
+
 /// Parse a statement
 fn parse_stmt(input: &mut Lexer, prog: &mut Program) -> Result<StmtBox, ParseError>
 {
@@ -1017,8 +1059,38 @@ fn parse_stmt(input: &mut Lexer, prog: &mut Program) -> Result<StmtBox, ParseErr
         input.expect_token("(")?;
 
         // Initialization statement
+        input.eat_ws()?;
+        let init_pos = input.get_pos();
         let init_stmt = if input.match_token(";")? {
             StmtBox::default()
+        } else if input.match_keyword("let")? {
+            let mutable = input.match_keyword("var")?;
+            input.eat_ws()?;
+            let var_name = input.parse_ident()?;
+
+            // For-in loop, with one or two loop variables
+            let two_vars = input.match_token(",")?;
+            if two_vars || input.match_keyword("in")? {
+                if mutable {
+                    return input.parse_error("for-in loop variables cannot be mutable");
+                }
+
+                return parse_for_in(input, prog, var_name, two_vars, pos);
+            }
+
+            input.expect_token("=")?;
+            let init_expr = parse_expr(input, prog)?;
+            input.expect_token(";")?;
+
+            StmtBox::new(
+                Stmt::Let {
+                    mutable,
+                    var_name,
+                    init_expr,
+                    decl: None,
+                },
+                init_pos,
+            )
         } else {
             parse_stmt(input, prog)?
         };
@@ -1074,6 +1146,44 @@ fn parse_stmt(input: &mut Lexer, prog: &mut Program) -> Result<StmtBox, ParseErr
         );
     }
 
+    // Try-catch statement
+    if input.match_keyword("try")? {
+        let body_stmt = parse_block_stmt(input, prog)?;
+
+        if !input.match_keyword("catch")? {
+            return input.parse_error("expected `catch` clause after try block");
+        }
+
+        // Parse the name of the caught value
+        input.expect_token("(")?;
+        input.eat_ws()?;
+        let catch_var = input.parse_ident()?;
+        input.expect_token(")")?;
+
+        let catch_stmt = parse_block_stmt(input, prog)?;
+
+        return StmtBox::new_ok(
+            Stmt::Try {
+                body_stmt,
+                catch_var,
+                catch_stmt,
+                decl: None,
+            },
+            pos
+        );
+    }
+
+    // Throw statement
+    if input.match_keyword("throw")? {
+        let expr = parse_expr(input, prog)?;
+        input.expect_token(";")?;
+
+        return StmtBox::new_ok(
+            Stmt::Throw(expr),
+            pos
+        );
+    }
+
     // Block statement
     if input.peek_ch() == '{' {
         return parse_block_stmt(input, prog);
@@ -1323,6 +1433,66 @@ fn parse_class(input: &mut Lexer, prog: &mut Program, pos: SrcPos) -> Result<(St
     Ok((class_name, class_id))
 }
 
+/// Parse the statements and class declarations making up the body of a
+/// unit, recording the classes, functions and constants it declares
+fn parse_unit_body(
+    input: &mut Lexer,
+    prog: &mut Program,
+    classes: &mut HashMap<String, ClassId>,
+    funs: &mut HashMap<String, FunId>,
+    consts: &mut HashMap<String, u32>,
+) -> Result<Vec<StmtBox>, ParseError>
+{
+    let mut stmts = Vec::default();
+
+    loop
+    {
+        input.eat_ws()?;
+        let pos = input.get_pos();
+
+        if input.eof() {
+            break;
+        }
+
+        if input.match_keyword("class")? {
+            let (name, id) = parse_class(input, prog, pos)?;
+            classes.insert(name, id);
+            stmts.push(StmtBox::new(
+                Stmt::ClassDecl { class_id: id },
+                pos
+            ));
+            continue;
+        }
+
+        let mut stmt = parse_stmt(input, prog)?;
+
+        // If this is a top-level declaration
+        if let Stmt::Let { init_expr, var_name, mutable, decl } = stmt.stmt.as_mut() {
+            // If this is a function declaration, add it to the
+            // list of functions declared in this unit
+            if let Expr::Fun { fun_id, .. } = init_expr.expr.as_ref() {
+                funs.insert(var_name.clone(), *fun_id);
+            }
+            // If this is an immutable global (potentially exportable)
+            else if *mutable == false {
+                // Assign a global index for the global variable
+                // We do this now so this is accessible during symbol resolution
+                let global_idx = prog.num_globals;
+                prog.num_globals += 1;
+                consts.insert(var_name.clone(), global_idx);
+                *decl = Some(crate::symbols::Decl::Global {
+                    idx: global_idx,
+                    mutable: false,
+                });
+            }
+        }
+
+        stmts.push(stmt);
+    }
+
+    Ok(stmts)
+}
+
 /// Parse a single unit of source code (e.g. one source file)
 pub fn parse_unit(input: &mut Lexer, prog: &mut Program) -> Result<FunId, ParseError>
 {
@@ -1336,7 +1506,6 @@ pub fn parse_unit(input: &mut Lexer, prog: &mut Program) -> Result<FunId, ParseE
     let mut classes = HashMap::default();
     let mut funs = HashMap::default();
     let mut consts = HashMap::default();
-    let mut stmts = Vec::default();
 
     // Parse imports, which must be at the top of the unit
     loop
@@ -1421,50 +1590,7 @@ pub fn parse_unit(input: &mut Lexer, prog: &mut Program) -> Result<FunId, ParseE
     }
 
     // Parse the unit body
-    loop
-    {
-        input.eat_ws()?;
-        let pos = input.get_pos();
-
-        if input.eof() {
-            break;
-        }
-
-        if input.match_keyword("class")? {
-            let (name, id) = parse_class(input, prog, pos)?;
-            classes.insert(name, id);
-            stmts.push(StmtBox::new(
-                Stmt::ClassDecl { class_id: id },
-                pos
-            ));
-            continue;
-        }
-
-        let mut stmt = parse_stmt(input, prog)?;
-
-        // If this is a top-level declaration
-        if let Stmt::Let { init_expr, var_name, mutable, decl } = stmt.stmt.as_mut() {
-            // If this is a function declaration, add it to the
-            // list of functions declared in this unit
-            if let Expr::Fun { fun_id, .. } = init_expr.expr.as_ref() {
-                funs.insert(var_name.clone(), *fun_id);
-            }
-            // If this is an immutable global (potentially exportable)
-            else if *mutable == false {
-                // Assign a global index for the global variable
-                // We do this now so this is accessible during symbol resolution
-                let global_idx = prog.num_globals;
-                prog.num_globals += 1;
-                consts.insert(var_name.clone(), global_idx);
-                *decl = Some(crate::symbols::Decl::Global {
-                    idx: global_idx,
-                    mutable: false,
-                });
-            }
-        }
-
-        stmts.push(stmt);
-    }
+    let stmts = parse_unit_body(input, prog, &mut classes, &mut funs, &mut consts)?;
 
     let body = StmtBox::new(
         Stmt::Block(stmts),
@@ -1573,6 +1699,43 @@ pub fn parse_file(file_name: &str) -> Result<Program, ParseError>
     parse_program(&mut input)
 }
 
+/// Parse one input typed into the REPL into a unit function added to an
+/// existing program. If the input ends with an expression statement, the
+/// unit function returns its value so that the REPL can print it.
+pub fn parse_repl_input(src: &str, prog: &mut Program) -> Result<Unit, ParseError>
+{
+    let mut input = Lexer::new(src, "repl");
+    input.eat_ws()?;
+    let unit_pos = input.get_pos();
+
+    let mut classes = HashMap::default();
+    let mut funs = HashMap::default();
+    let mut consts = HashMap::default();
+    let mut stmts = parse_unit_body(&mut input, prog, &mut classes, &mut funs, &mut consts)?;
+
+    if let Some(last_stmt) = stmts.last_mut() {
+        if let Stmt::Expr(expr) = last_stmt.stmt.as_ref() {
+            *last_stmt.stmt = Stmt::Return(expr.clone());
+        }
+    }
+
+    let unit_fn = Function {
+        name: "repl".into(),
+        body: StmtBox::new(Stmt::Block(stmts), unit_pos),
+        is_unit: true,
+        pos: unit_pos,
+        ..Default::default()
+    };
+
+    Ok(Unit {
+        classes,
+        funs,
+        consts,
+        unit_fn: prog.reg_fun(unit_fn),
+        ..Default::default()
+    })
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -1917,6 +2080,42 @@ mod tests
         parse_fails("for (;;);");
     }
 
+    #[test]
+    fn for_in()
+    {
+        parse_ok("for (let x in a) {}");
+        parse_ok("for (let x in [1, 2, 3]) { $println(x); }");
+        parse_ok("for (let k, v in d) {}");
+        parse_ok("for (let k,v in {a: 1}) $println(k);");
+        parse_ok("for (let i in $range(0, 10, 1)) {}");
+        parse_ok("for (let input in a) {}");
+
+        // C-style loops still parse
+        parse_ok("for (let var index = 0; index < 10; ++index) {}");
+
+        parse_fails("for (let var x in a) {}");
+        parse_fails("for (let k, v a) {}");
+        parse_fails("for (let k, k in a) {}");
+        parse_fails("for (let x in a;) {}");
+        parse_fails("for (let x in) {}");
+    }
+
+    #[test]
+    fn try_stmt()
+    {
+        parse_ok("try {} catch (e) {}");
+        parse_ok("try { foo(); } catch (e) { $println(e); }");
+        parse_ok("try { throw 1; } catch (e) { throw e; }");
+        parse_ok("fun f() { try { return 1; } catch (err) { return 2; } }");
+
+        // The catch clause is mandatory, and both bodies must be blocks
+        parse_fails("try {}");
+        parse_fails("try foo(); catch (e) {}");
+        parse_fails("try {} catch {}");
+        parse_fails("try {} catch (e) foo();");
+        parse_fails("throw;");
+    }
+
     #[test]
     fn regress_prefix_postfix()
     {
@@ -0,0 +1,232 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Capability a host function needs in order to run
+#[derive(Copy, Clone, Debug)]
+pub enum Permission<'a>
+{
+    FsRead(&'a str),
+    FsWrite(&'a str),
+    Window,
+    Audio,
+    Actors,
+}
+
+impl Permission<'_>
+{
+    fn name(&self) -> &'static str
+    {
+        match self {
+            Permission::FsRead(_) => "fs-read",
+            Permission::FsWrite(_) => "fs-write",
+            Permission::Window => "window",
+            Permission::Audio => "audio",
+            Permission::Actors => "actors",
+        }
+    }
+}
+
+/// Permission named on the command line. File system permissions
+/// can be limited to a directory and everything below it.
+#[derive(Clone, Debug, PartialEq)]
+enum Grant
+{
+    FsRead(Option<PathBuf>),
+    FsWrite(Option<PathBuf>),
+    Window,
+    Audio,
+    Actors,
+}
+
+impl Grant
+{
+    fn parse(spec: &str) -> Result<Grant, String>
+    {
+        let (name, dir) = match spec.split_once(':') {
+            Some((name, dir)) => (name, Some(resolve_path(dir))),
+            None => (spec, None),
+        };
+
+        match (name, dir) {
+            ("fs-read", dir) => Ok(Grant::FsRead(dir)),
+            ("fs-write", dir) => Ok(Grant::FsWrite(dir)),
+            ("window", None) => Ok(Grant::Window),
+            ("audio", None) => Ok(Grant::Audio),
+            ("actors", None) => Ok(Grant::Actors),
+            (_, Some(_)) if ["window", "audio", "actors"].contains(&name) => {
+                Err(format!("permission `{}` does not take a directory", name))
+            }
+            _ => Err(format!("unknown permission `{}`", name)),
+        }
+    }
+
+    fn covers(&self, perm: Permission) -> bool
+    {
+        fn covers_path(dir: &Option<PathBuf>, path: &str) -> bool
+        {
+            match dir {
+                None => true,
+                Some(dir) => resolve_path(path).starts_with(dir),
+            }
+        }
+
+        match (self, perm) {
+            (Grant::FsRead(dir), Permission::FsRead(path)) => covers_path(dir, path),
+            (Grant::FsWrite(dir), Permission::FsWrite(path)) => covers_path(dir, path),
+            (Grant::Window, Permission::Window) => true,
+            (Grant::Audio, Permission::Audio) => true,
+            (Grant::Actors, Permission::Actors) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Make a path absolute, with `.` and `..` taken out. Symbolic links are
+/// resolved for the part of the path that exists, so that neither can
+/// be used to reach outside of a directory that was granted.
+fn resolve_path(path: &str) -> PathBuf
+{
+    let path = std::path::absolute(path).unwrap_or_else(|_| PathBuf::from(path));
+
+    let mut normal = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => { normal.pop(); }
+            comp => normal.push(comp),
+        }
+    }
+
+    // Find the longest prefix that exists
+    let mut existing: &Path = &normal;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => return normal,
+        }
+    }
+
+    match existing.canonicalize() {
+        Ok(canonical) => canonical.join(normal.strip_prefix(existing).unwrap()),
+        Err(_) => normal,
+    }
+}
+
+/// Permissions granted to a program. Nothing is allowed by default,
+/// and what is denied takes precedence over what is allowed.
+#[derive(Default, Clone, Debug)]
+pub struct Permissions
+{
+    allow_all: bool,
+    allowed: Vec<Grant>,
+    denied: Vec<Grant>,
+}
+
+impl Permissions
+{
+    pub fn set_allow_all(&mut self)
+    {
+        self.allow_all = true;
+    }
+
+    /// Allow a comma-separated list of permissions
+    pub fn allow(&mut self, specs: &str) -> Result<(), String>
+    {
+        for spec in specs.split(',') {
+            self.allowed.push(Grant::parse(spec.trim())?);
+        }
+        Ok(())
+    }
+
+    /// Deny a comma-separated list of permissions
+    pub fn deny(&mut self, specs: &str) -> Result<(), String>
+    {
+        for spec in specs.split(',') {
+            self.denied.push(Grant::parse(spec.trim())?);
+        }
+        Ok(())
+    }
+
+    /// Check that a permission was granted, producing an error naming it otherwise
+    pub fn check(&self, perm: Permission) -> Result<(), String>
+    {
+        let target = match perm {
+            Permission::FsRead(path) | Permission::FsWrite(path) => format!(" for path \"{}\"", path),
+            _ => String::new(),
+        };
+
+        if self.denied.iter().any(|grant| grant.covers(perm)) {
+            return Err(format!("permission `{}` was denied{}", perm.name(), target));
+        }
+
+        if self.allow_all || self.allowed.iter().any(|grant| grant.covers(perm)) {
+            return Ok(());
+        }
+
+        let hint = match perm {
+            Permission::FsRead(_) | Permission::FsWrite(_) => format!("{}:<dir>", perm.name()),
+            _ => perm.name().to_string(),
+        };
+
+        Err(format!(
+            "missing permission `{}`{}, which can be granted with `--allow {}`",
+            perm.name(), target, hint
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn deny_by_default()
+    {
+        let perms = Permissions::default();
+        assert!(perms.check(Permission::Window).is_err());
+        assert!(perms.check(Permission::FsRead("foo.txt")).is_err());
+
+        let mut perms = Permissions::default();
+        perms.set_allow_all();
+        assert!(perms.check(Permission::Actors).is_ok());
+        assert!(perms.check(Permission::FsWrite("foo.txt")).is_ok());
+    }
+
+    #[test]
+    fn grants()
+    {
+        let mut perms = Permissions::default();
+        perms.allow("window, fs-read:examples").unwrap();
+        assert!(perms.check(Permission::Window).is_ok());
+        assert!(perms.check(Permission::Audio).is_err());
+        assert!(perms.check(Permission::FsRead("examples/fib.psh")).is_ok());
+        assert!(perms.check(Permission::FsRead("./examples/../examples/new_file.txt")).is_ok());
+        assert!(perms.check(Permission::FsRead("tests/fact.psh")).is_err());
+        assert!(perms.check(Permission::FsRead("examples/../tests/fact.psh")).is_err());
+        assert!(perms.check(Permission::FsRead("examples_other/foo.txt")).is_err());
+        assert!(perms.check(Permission::FsWrite("examples/fib.psh")).is_err());
+
+        assert!(perms.allow("network").is_err());
+        assert!(perms.allow("window:foo").is_err());
+    }
+
+    #[test]
+    fn deny_overrides_allow()
+    {
+        let mut perms = Permissions::default();
+        perms.set_allow_all();
+        perms.deny("actors,fs-write").unwrap();
+        assert!(perms.check(Permission::Actors).is_err());
+        assert!(perms.check(Permission::FsWrite("out.txt")).is_err());
+        assert!(perms.check(Permission::FsRead("out.txt")).is_ok());
+
+        let mut perms = Permissions::default();
+        perms.allow("fs-read").unwrap();
+        perms.deny("fs-read:src").unwrap();
+        assert!(perms.check(Permission::FsRead("docs/language.md")).is_ok());
+        assert_eq!(
+            perms.check(Permission::FsRead("src/main.rs")),
+            Err("permission `fs-read` was denied for path \"src/main.rs\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use crate::vm::{VM, ActorLimits};
+use crate::ast::Program;
+use crate::parser::parse_repl_input;
+use crate::symbols::ReplEnv;
+use crate::permissions::Permissions;
+use crate::value::*;
+
+/// Depth of unclosed brackets at the end of some source code. Brackets
+/// inside of string literals and comments are not counted.
+fn bracket_depth(src: &str) -> i64
+{
+    let mut depth = 0;
+    let mut chars = src.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+
+            '\'' | '"' => {
+                while let Some(next) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == ch {
+                        break;
+                    }
+                }
+            }
+
+            '/' if chars.peek() == Some(&'/') => {
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for next in chars.by_ref() {
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    depth
+}
+
+/// Read one input from stdin, continuing on more lines for as long as
+/// brackets are left open. Returns None at the end of the input.
+fn read_input() -> Option<String>
+{
+    let mut src = String::new();
+
+    loop {
+        print!("{}", if src.is_empty() { "> " } else { "... " });
+        let _ = io::stdout().flush();
+
+        match io::stdin().read_line(&mut src) {
+            Ok(0) | Err(_) => {
+                println!();
+                return None;
+            }
+            Ok(_) => {}
+        }
+
+        if bracket_depth(&src) <= 0 {
+            return Some(src);
+        }
+    }
+}
+
+/// Print the value an expression statement produced
+fn print_value(val: Value)
+{
+    match val.type_of() {
+        Type::Nil => {}
+        Type::String => println!("{:?}", val.as_str()),
+        Type::Int64 => println!("{}", val.to_i64().unwrap()),
+        Type::Float64 => println!("{}", val.to_f64().unwrap()),
+        Type::Bool => println!("{}", val.as_bool()),
+        _ => println!("{:?}", val),
+    }
+}
+
+/// Run an interactive session, in which each input is compiled and run
+/// in the same actor, so that what it declares stays available
+pub fn run_repl(permissions: Permissions, limits: ActorLimits, threads: Option<usize>)
+{
+    let vm = VM::new(Program::new());
+    vm.lock().unwrap().set_permissions(permissions);
+    vm.lock().unwrap().set_limits(limits);
+    if let Some(num_threads) = threads {
+        vm.lock().unwrap().set_threads(num_threads);
+    }
+    let mut actor = VM::main_actor(&vm);
+    let mut env = ReplEnv::new();
+
+    while let Some(src) = read_input() {
+        if src.trim().is_empty() {
+            continue;
+        }
+
+        // An expression typed on its own can leave out the final semicolon
+        let mut vm_ref = vm.lock().unwrap();
+        let prog = vm_ref.prog_mut();
+        let unit = parse_repl_input(&src, prog)
+            .or_else(|err| parse_repl_input(&format!("{};", src.trim_end()), prog).map_err(|_| err))
+            .and_then(|unit| {
+                prog.resolve_repl_input(&mut env, &unit)?;
+                Ok(unit)
+            });
+        drop(vm_ref);
+
+        let unit = match unit {
+            Ok(unit) => unit,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| actor.call_repl_unit(unit.unit_fn))) {
+            Ok(val) => print_value(val),
+
+            // The error was reported as it happened. An error can end
+            // execution while the VM is locked, which leaves it poisoned.
+            Err(_) => {
+                actor.reset_stack();
+                vm.clear_poison();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn brackets()
+    {
+        assert_eq!(bracket_depth("let x = 1;"), 0);
+        assert_eq!(bracket_depth("fun f() {"), 1);
+        assert_eq!(bracket_depth("fun f() { return [1, (2"), 3);
+        assert_eq!(bracket_depth("}"), -1);
+
+        // Brackets in strings and comments don't count
+        assert_eq!(bracket_depth("let s = '{\\'(';"), 0);
+        assert_eq!(bracket_depth("let s = \"}\"; // {"), 0);
+        assert_eq!(bracket_depth("/* { */ {"), 1);
+    }
+}
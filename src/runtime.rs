@@ -1,8 +1,12 @@
 use crate::array::Array;
+use crate::bigint::BigNum;
 use crate::ast::*;
 use crate::vm::Actor;
 use crate::value::*;
 use crate::str::Str;
+use crate::object::Object;
+use crate::alloc::{Tag, HEADER_SIZE};
+use crate::host::HostFn;
 use crate::*;
 
 fn identity_method(_actor: &mut Actor, self_val: Value) -> Result<Value, String>
@@ -28,7 +32,10 @@ fn nil_to_s(actor: &mut Actor, _v: Value) -> Result<Value, String>
 fn int64_abs(actor: &mut Actor, v: Value) -> Result<Value, String>
 {
     let v = unwrap_i64!(v);
-    Ok(actor.int64(if v > 0 { v } else { -v }))
+    match v.checked_abs() {
+        Some(abs) => Ok(actor.int64(abs)),
+        None => Ok(actor.bigint(BigNum::from_i64(v).abs())),
+    }
 }
 
 fn int64_min(actor: &mut Actor, v: Value, other: Value) -> Result<Value, String>
@@ -70,6 +77,41 @@ fn int64_to_hex(actor: &mut Actor, v: Value, digits: Value) -> Result<Value, Str
     Ok(Str::new(&s, &mut actor.alloc))
 }
 
+fn bigint_abs(actor: &mut Actor, v: Value) -> Result<Value, String>
+{
+    let v = unwrap_val!(to_bignum, "integer", v, "");
+    Ok(actor.bigint(v.abs()))
+}
+
+fn bigint_to_f(actor: &mut Actor, v: Value) -> Result<Value, String>
+{
+    let v = unwrap_val!(to_bignum, "integer", v, "");
+    Ok(actor.float64(v.to_f64()))
+}
+
+fn bigint_to_s(actor: &mut Actor, v: Value) -> Result<Value, String>
+{
+    let v = unwrap_val!(to_bignum, "integer", v, "");
+    let s = v.to_string();
+
+    actor.gc_check(Str::alloc_size(s.len()), &mut []);
+    Ok(Str::new(&s, &mut actor.alloc))
+}
+
+/// Hex digits of the magnitude, with a minus sign in front if negative,
+/// since a bigint has no fixed width to take the two's complement in
+fn bigint_to_hex(actor: &mut Actor, v: Value, digits: Value) -> Result<Value, String>
+{
+    let v = unwrap_val!(to_bignum, "integer", v, "");
+    let digits = unwrap_usize!(digits);
+    let hex = v.abs().to_string_radix(16).to_uppercase();
+    let sign = if v < BigNum::from_i64(0) { "-" } else { "" };
+    let s = format!("{}{:0>width$}", sign, hex, width = digits);
+
+    actor.gc_check(Str::alloc_size(s.len()), &mut []);
+    Ok(Str::new(&s, &mut actor.alloc))
+}
+
 fn float64_abs(actor: &mut Actor, v: Value) -> Result<Value, String>
 {
     let v = unwrap_f64!(v);
@@ -244,14 +286,22 @@ fn string_char_at(actor: &mut Actor, s: Value, byte_idx: Value) -> Result<Value,
 }
 
 /// Try to parse the string as an integer with the given radix
+/// Integers too large for an int64 are parsed as bigints
 fn string_parse_int(actor: &mut Actor, s: Value, radix: Value) -> Result<Value, String>
 {
     let s = unwrap_str!(s);
     let radix = unwrap_u32!(radix);
 
+    if !(2..=36).contains(&radix) {
+        return Err(format!("radix {} is not between 2 and 36", radix));
+    }
+
     match i64::from_str_radix(s, radix) {
         Ok(int_val) => Ok(actor.int64(int_val)),
-        Err(_) => Ok(Value::NIL),
+        Err(_) => match BigNum::parse(s, radix) {
+            Some(num) => Ok(actor.bigint(num)),
+            None => Ok(Value::NIL),
+        }
     }
 }
 
@@ -375,6 +425,43 @@ pub fn init_runtime(prog: &mut Program)
     audio_data.reg_field("device_id");
     audio_data.reg_field("num_samples");
     prog.reg_class(audio_data);
+
+    // Error
+    // Runtime errors caught by a try block are instances of this class
+    let mut error_class = Class {
+        name: "Error".to_string(),
+        id: ERROR_ID,
+        ..Default::default()
+    };
+    error_class.reg_field("message");
+    error_class.reg_field("pos");
+    prog.reg_class(error_class);
+
+    // Range
+    // Note: the field order must match the slots
+    // read by the iter_next instruction
+    let mut range_class = Class {
+        name: "Range".to_string(),
+        id: RANGE_ID,
+        ..Default::default()
+    };
+    range_class.reg_field("start");
+    range_class.reg_field("end");
+    range_class.reg_field("step");
+    prog.reg_class(range_class);
+
+    // ActorError
+    // Failures of other actors are reported as instances of this class
+    // Note: the field order must match the slots written by ActorFailure
+    let mut actor_error_class = Class {
+        name: "ActorError".to_string(),
+        id: ACTOR_ERROR_ID,
+        ..Default::default()
+    };
+    actor_error_class.reg_field("message");
+    actor_error_class.reg_field("actor_id");
+    actor_error_class.reg_field("stack_trace");
+    prog.reg_class(actor_error_class);
 }
 
 fn dict_has(_actor: &mut Actor, d: Value, key: Value) -> Result<Value, String>
@@ -384,13 +471,163 @@ fn dict_has(_actor: &mut Actor, d: Value, key: Value) -> Result<Value, String>
     Ok(Value::from(d.has(key)))
 }
 
+/// Get the value for a key, or a default value if the key is absent
+fn dict_get(_actor: &mut Actor, d: Value, key: Value, default: Value) -> Result<Value, String>
+{
+    let d = unwrap_dict!(d);
+    let key = unwrap_str!(key);
+    Ok(d.get(key).unwrap_or(default))
+}
+
+/// Remove the entry for a key, returning its value, or nil if absent
+fn dict_remove(_actor: &mut Actor, d: Value, key: Value) -> Result<Value, String>
+{
+    let d = unwrap_dict!(d);
+    let key = unwrap_str!(key);
+    Ok(d.remove(key).unwrap_or(Value::NIL))
+}
+
+fn dict_len(_actor: &mut Actor, d: Value) -> Result<Value, String>
+{
+    let d = unwrap_dict!(d);
+    Ok(Value::fixnum(d.len() as i64))
+}
+
+fn dict_clear(_actor: &mut Actor, d: Value) -> Result<Value, String>
+{
+    unwrap_dict!(d).clear();
+    Ok(Value::NIL)
+}
+
+/// Collect the entries of a dict into a new array, in table order,
+/// mapping each key and value to an array element
+fn dict_to_array(
+    actor: &mut Actor,
+    mut d: Value,
+    elem_size: usize,
+    f: fn(&mut Actor, Value, Value) -> Value
+) -> Result<Value, String>
+{
+    let len = unwrap_dict!(d).len();
+
+    // Reserve room for the array and for any arrays
+    // created for its elements up front
+    actor.gc_check(
+        Array::alloc_size(len) + len * elem_size,
+        &mut [&mut d]
+    );
+
+    let arr = Array::with_capacity(len, &mut actor.alloc);
+
+    let mut slot_idx = 0;
+    while let Some((idx, key, val)) = d.as_dict().next_entry(slot_idx) {
+        let elem = f(actor, key, val);
+        arr.as_arr().push(elem, &mut actor.alloc);
+        slot_idx = idx + 1;
+    }
+
+    Ok(arr)
+}
+
+fn dict_keys(actor: &mut Actor, d: Value) -> Result<Value, String>
+{
+    dict_to_array(actor, d, 0, |_, key, _| key)
+}
+
+fn dict_values(actor: &mut Actor, d: Value) -> Result<Value, String>
+{
+    dict_to_array(actor, d, 0, |_, _, val| val)
+}
+
+/// Get the entries of a dict as an array of [key, value] arrays
+fn dict_items(actor: &mut Actor, d: Value) -> Result<Value, String>
+{
+    dict_to_array(actor, d, Array::alloc_size(2), |actor, key, val| {
+        let pair = Array::with_capacity(2, &mut actor.alloc);
+        pair.as_arr().push(key, &mut actor.alloc);
+        pair.as_arr().push(val, &mut actor.alloc);
+        pair
+    })
+}
+
+/// Copy all the entries of another dict into this one,
+/// replacing the values of keys present in both
+fn dict_merge(actor: &mut Actor, mut d: Value, mut other: Value) -> Result<Value, String>
+{
+    unwrap_dict!(d);
+    unwrap_dict!(other);
+
+    let mut slot_idx = 0;
+    loop {
+        // Room for the table to grow. This may move both dicts,
+        // so the next entry is only read afterwards.
+        let alloc_size = d.as_dict().will_allocate();
+        actor.gc_check(alloc_size, &mut [&mut d, &mut other]);
+
+        let (idx, key, val) = match other.as_dict().next_entry(slot_idx) {
+            Some(entry) => entry,
+            None => break
+        };
+
+        d.as_dict().set(key.as_string(), val, &mut actor.alloc);
+        actor.remember(d);
+        slot_idx = idx + 1;
+    }
+
+    Ok(Value::NIL)
+}
+
+/// Create a weak reference to a value. The collector clears it once the
+/// value is no longer referenced by anything else.
+fn weakref_new(actor: &mut Actor, mut target: Value) -> Result<Value, String>
+{
+    actor.gc_check(HEADER_SIZE + size_of::<Value>(), &mut [&mut target]);
+    let p = actor.alloc.alloc(target, Tag::WeakRef);
+    Ok(Value::weakref(p))
+}
+
+/// Get the value a weak reference refers to, or nil if it was collected
+fn weakref_get(_actor: &mut Actor, weakref: Value) -> Result<Value, String>
+{
+    Ok(*weakref.as_weakref())
+}
+
+/// Create an Error object with a given message, to be thrown
+fn error_new(actor: &mut Actor, mut msg: Value) -> Result<Value, String>
+{
+    let num_slots = actor.get_num_slots(ERROR_ID);
+    actor.gc_check(Object::alloc_size(num_slots), &mut [&mut msg]);
+
+    let err = Object::new(ERROR_ID, num_slots, &mut actor.alloc);
+    actor.set_field(err, "message", msg);
+    actor.set_field(err, "pos", Value::NIL);
+    Ok(err)
+}
+
+/// Host function that calling a core class runs instead of allocating an
+/// object and calling its init method, for the core classes whose instances
+/// are not objects, or which have no constructor written in plush
+pub fn get_core_ctor(class_id: ClassId) -> Option<&'static HostFn>
+{
+    use crate::host::FnPtr::*;
+
+    static WEAKREF_NEW: HostFn = HostFn { name: "WeakRef", f: Fn1(weakref_new) };
+    static ERROR_NEW: HostFn = HostFn { name: "Error", f: Fn1(error_new) };
+
+    match class_id {
+        WEAKREF_ID => Some(&WEAKREF_NEW),
+        ERROR_ID => Some(&ERROR_NEW),
+        _ => None,
+    }
+}
+
 /// Get the method associated with a core value
 pub fn get_method(val: Value, method_name: &str) -> Value
 {
-    use crate::host::HostFn;
     use crate::host::FnPtr::*;
     use crate::array::*;
     use crate::bytearray::*;
+    use crate::shared::*;
 
     static TRUE_TO_S: HostFn = HostFn { name: "to_s", f: Fn1(true_to_s) };
     static FALSE_TO_S: HostFn = HostFn { name: "to_s", f: Fn1(false_to_s) };
@@ -403,6 +640,11 @@ pub fn get_method(val: Value, method_name: &str) -> Value
     static INT64_TO_S: HostFn = HostFn { name: "to_s", f: Fn1(int64_to_s) };
     static INT64_TO_HEX: HostFn = HostFn { name: "to_hex", f: Fn2(int64_to_hex) };
 
+    static BIGINT_ABS: HostFn = HostFn { name: "abs", f: Fn1(bigint_abs) };
+    static BIGINT_TO_F: HostFn = HostFn { name: "to_f", f: Fn1(bigint_to_f) };
+    static BIGINT_TO_S: HostFn = HostFn { name: "to_s", f: Fn1(bigint_to_s) };
+    static BIGINT_TO_HEX: HostFn = HostFn { name: "to_hex", f: Fn2(bigint_to_hex) };
+
     static FLOAT64_ABS: HostFn = HostFn { name: "abs", f: Fn1(float64_abs) };
     static FLOAT64_CEIL: HostFn = HostFn { name: "ceil", f: Fn1(float64_ceil) };
     static FLOAT64_FLOOR: HostFn = HostFn { name: "floor", f: Fn1(float64_floor) };
@@ -459,7 +701,25 @@ pub fn get_method(val: Value, method_name: &str) -> Value
     static BA_FILL_U32: HostFn = HostFn { name: "fill_u32", f: Fn4(ba_fill_u32) };
     static BA_BLIT_BGRA32: HostFn = HostFn { name: "blit_bgra32", f: Fn8(ba_blit_bgra32) };
 
+    static SBA_WITH_SIZE: HostFn = HostFn { name: "with_size", f: Fn2(sba_with_size) };
+    static SBA_LOAD_U32: HostFn = HostFn { name: "load_u32", f: Fn2(sba_load_u32) };
+    static SBA_STORE_U32: HostFn = HostFn { name: "store_u32", f: Fn3(sba_store_u32) };
+    static SBA_ATOMIC_LOAD_U32: HostFn = HostFn { name: "atomic_load_u32", f: Fn2(sba_atomic_load_u32) };
+    static SBA_ATOMIC_STORE_U32: HostFn = HostFn { name: "atomic_store_u32", f: Fn3(sba_atomic_store_u32) };
+    static SBA_ATOMIC_ADD_U32: HostFn = HostFn { name: "atomic_add_u32", f: Fn3(sba_atomic_add_u32) };
+    static SBA_COMPARE_EXCHANGE_U32: HostFn = HostFn { name: "compare_exchange_u32", f: Fn4(sba_compare_exchange_u32) };
+
     static DICT_HAS: HostFn = HostFn { name: "has", f: Fn2(dict_has) };
+    static DICT_GET: HostFn = HostFn { name: "get", f: Fn3(dict_get) };
+    static DICT_REMOVE: HostFn = HostFn { name: "remove", f: Fn2(dict_remove) };
+    static DICT_LEN: HostFn = HostFn { name: "len", f: Fn1(dict_len) };
+    static DICT_CLEAR: HostFn = HostFn { name: "clear", f: Fn1(dict_clear) };
+    static DICT_KEYS: HostFn = HostFn { name: "keys", f: Fn1(dict_keys) };
+    static DICT_VALUES: HostFn = HostFn { name: "values", f: Fn1(dict_values) };
+    static DICT_ITEMS: HostFn = HostFn { name: "items", f: Fn1(dict_items) };
+    static DICT_MERGE: HostFn = HostFn { name: "merge", f: Fn2(dict_merge) };
+
+    static WEAKREF_GET: HostFn = HostFn { name: "get", f: Fn1(weakref_get) };
 
     // Dispatch on the language-level type first, so that a value that
     // has no methods at all costs one branch and no string compares
@@ -471,6 +731,11 @@ pub fn get_method(val: Value, method_name: &str) -> Value
         (Type::Int64, "to_s") => &INT64_TO_S,
         (Type::Int64, "to_hex") => &INT64_TO_HEX,
 
+        (Type::BigInt, "abs") => &BIGINT_ABS,
+        (Type::BigInt, "to_f") => &BIGINT_TO_F,
+        (Type::BigInt, "to_s") => &BIGINT_TO_S,
+        (Type::BigInt, "to_hex") => &BIGINT_TO_HEX,
+
         (Type::Float64, "abs") => &FLOAT64_ABS,
         (Type::Float64, "ceil") => &FLOAT64_CEIL,
         (Type::Float64, "floor") => &FLOAT64_FLOOR,
@@ -525,7 +790,24 @@ pub fn get_method(val: Value, method_name: &str) -> Value
         (Type::ByteArray, "fill_u32") => &BA_FILL_U32,
         (Type::ByteArray, "blit_bgra32") => &BA_BLIT_BGRA32,
 
+        (Type::SharedByteArray, "load_u32") => &SBA_LOAD_U32,
+        (Type::SharedByteArray, "store_u32") => &SBA_STORE_U32,
+        (Type::SharedByteArray, "atomic_load_u32") => &SBA_ATOMIC_LOAD_U32,
+        (Type::SharedByteArray, "atomic_store_u32") => &SBA_ATOMIC_STORE_U32,
+        (Type::SharedByteArray, "atomic_add_u32") => &SBA_ATOMIC_ADD_U32,
+        (Type::SharedByteArray, "compare_exchange_u32") => &SBA_COMPARE_EXCHANGE_U32,
+
         (Type::Dict, "has") => &DICT_HAS,
+        (Type::Dict, "get") => &DICT_GET,
+        (Type::Dict, "remove") => &DICT_REMOVE,
+        (Type::Dict, "len") => &DICT_LEN,
+        (Type::Dict, "clear") => &DICT_CLEAR,
+        (Type::Dict, "keys") => &DICT_KEYS,
+        (Type::Dict, "values") => &DICT_VALUES,
+        (Type::Dict, "items") => &DICT_ITEMS,
+        (Type::Dict, "merge") => &DICT_MERGE,
+
+        (Type::WeakRef, "get") => &WEAKREF_GET,
 
         (Type::Bool, "to_s") => if val.as_bool() { &TRUE_TO_S } else { &FALSE_TO_S },
         (Type::Nil, "to_s") => &NIL_TO_S,
@@ -535,6 +817,7 @@ pub fn get_method(val: Value, method_name: &str) -> Value
             (STRING_ID, "from_codepoint") => &STRING_FROM_CODEPOINT,
             (ARRAY_ID, "with_size") => &ARRAY_WITH_SIZE,
             (BYTEARRAY_ID, "with_size") => &BA_WITH_SIZE,
+            (SHARED_BYTEARRAY_ID, "with_size") => &SBA_WITH_SIZE,
             _ => return Value::NIL,
         }
 
@@ -554,10 +837,13 @@ pub fn get_class_id(val: Value) -> ClassId
         Type::Bool => BOOL_ID,
         Type::Int64 => INT64_ID,
         Type::Float64 => FLOAT64_ID,
+        Type::BigInt => BIGINT_ID,
         Type::String => STRING_ID,
         Type::Array => ARRAY_ID,
         Type::ByteArray => BYTEARRAY_ID,
+        Type::SharedByteArray => SHARED_BYTEARRAY_ID,
         Type::Dict => DICT_ID,
+        Type::WeakRef => WEAKREF_ID,
 
         t => todo!("get_class_id for {:?} values", t)
     }
@@ -0,0 +1,417 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::{Arc, Mutex, Condvar, Once};
+use std::sync::atomic::{self, AtomicUsize};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::vm::{VM, Actor};
+use crate::value::Value;
+
+/// Number of backward jumps an actor makes before giving the other
+/// actors waiting to run on its worker thread a turn
+pub const TIME_SLICE: u32 = 10_000;
+
+/// Why a scheduled actor stopped running before its function returned
+#[derive(Copy, Clone, Debug)]
+pub enum Wait
+{
+    /// It used up its time slice, and goes to the back of the run queue
+    Yield,
+
+    /// It waits to be woken up by a message or by an actor it is joining,
+    /// or until a deadline passes. The blocking operation that parked it
+    /// is made again once it is resumed, and can pick up from `progress`.
+    Wake { deadline: Option<Instant>, progress: usize },
+}
+
+struct TaskState
+{
+    // The actor, while it is parked or waiting in a run queue. The
+    // worker thread running it takes it out for as long as it runs.
+    actor: Option<Box<Actor>>,
+
+    // Function the actor calls when it first runs, and its arguments
+    entry: Option<(Value, Vec<Value>)>,
+
+    // Whether the actor is parked, waiting to be woken up
+    parked: bool,
+
+    // Whether the actor was woken up while it was running. It goes back
+    // in a run queue instead of parking, since what it is waiting for
+    // may have happened after it last looked.
+    woken: bool,
+
+    // Value the actor returned, once it is done
+    result: Option<Value>,
+
+    // Actors parked until this one is done
+    joiners: Vec<Arc<Task>>,
+}
+
+/// An actor as the scheduler sees it
+pub struct Task
+{
+    sched: Arc<Scheduler>,
+
+    state: Mutex<TaskState>,
+
+    // Signaled when the actor is done, for joiners blocking their thread
+    done: Condvar,
+}
+
+// The actor is only ever accessed by the one thread that took it out
+// of the task to run it, like the dedicated thread it used to have
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task
+{
+    /// Create the task for an actor that will call a function when it
+    /// first runs. It doesn't run until it is started.
+    pub fn new(sched: Arc<Scheduler>, fun: Value, args: Vec<Value>) -> Arc<Task>
+    {
+        Arc::new(Task {
+            sched,
+            state: Mutex::new(TaskState {
+                actor: None,
+                entry: Some((fun, args)),
+                parked: false,
+                woken: false,
+                result: None,
+                joiners: Vec::default(),
+            }),
+            done: Condvar::new(),
+        })
+    }
+
+    /// Hand the actor over to the scheduler, so that it starts running
+    pub fn start(self: &Arc<Self>, actor: Box<Actor>)
+    {
+        self.state.lock().unwrap().actor = Some(actor);
+        self.sched.schedule(self.clone());
+    }
+
+    /// Have the actor run again if it is parked. If it is running, it
+    /// goes back in a run queue instead of parking next time.
+    pub fn wake(self: &Arc<Self>)
+    {
+        let mut state = self.state.lock().unwrap();
+
+        if state.parked {
+            state.parked = false;
+            drop(state);
+            self.sched.schedule(self.clone());
+        } else {
+            state.woken = true;
+        }
+    }
+
+    /// Get the value the actor returned if it is done. Otherwise, the
+    /// joining actor is woken up once it is.
+    pub fn join_or_wake(&self, joiner: &Arc<Task>) -> Option<Value>
+    {
+        let mut state = self.state.lock().unwrap();
+
+        if state.result.is_none() && !state.joiners.iter().any(|task| Arc::ptr_eq(task, joiner)) {
+            state.joiners.push(joiner.clone());
+        }
+
+        state.result
+    }
+
+    /// Whether the actor is done running
+    pub fn is_done(&self) -> bool
+    {
+        self.state.lock().unwrap().result.is_some()
+    }
+
+    /// Block the calling thread until the actor is done,
+    /// and get the value it returned
+    pub fn wait_done(&self) -> Value
+    {
+        let state = self.state.lock().unwrap();
+        let state = self.done.wait_while(state, |state| state.result.is_none()).unwrap();
+        state.result.unwrap()
+    }
+
+    /// Run the actor until it is done, or until it has to wait
+    fn run(self: &Arc<Self>)
+    {
+        let (mut actor, entry) = {
+            let mut state = self.state.lock().unwrap();
+
+            // Whatever the actor was woken up for, it will see as it runs
+            state.woken = false;
+
+            (state.actor.take().unwrap(), state.entry.take())
+        };
+
+        let ret_val = match VM::run_actor(&mut actor, entry) {
+            Some(ret_val) => ret_val,
+            None => return self.suspend(actor),
+        };
+
+        // Free the actor's memory before anyone is told it is done
+        drop(actor);
+
+        let joiners = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(ret_val);
+            std::mem::take(&mut state.joiners)
+        };
+
+        self.done.notify_all();
+
+        for joiner in &joiners {
+            joiner.wake();
+        }
+    }
+
+    /// Put away an actor that stopped running before it was done
+    fn suspend(self: &Arc<Self>, actor: Box<Actor>)
+    {
+        let wait = actor.wait;
+        let mut state = self.state.lock().unwrap();
+        state.actor = Some(actor);
+
+        match wait {
+            Some(Wait::Wake { deadline, .. }) if !state.woken => {
+                state.parked = true;
+                drop(state);
+
+                if let Some(deadline) = deadline {
+                    self.sched.wake_at(deadline, self.clone());
+                }
+            }
+
+            _ => {
+                drop(state);
+                self.sched.schedule(self.clone());
+            }
+        }
+    }
+}
+
+/// Parked actor to wake up at a deadline
+struct Deadline(Instant, Arc<Task>);
+
+// Deadlines are ordered soonest first, for use in a max-heap
+impl Ord for Deadline
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Deadline
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Deadline
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Deadline {}
+
+thread_local! {
+    // Scheduler the current thread is a worker of, and the worker index
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Runs actors on a fixed pool of worker threads. Each worker has a run
+/// queue of its own, and takes actors from the others once it runs out.
+/// Actors waiting for a message or for another actor are parked, which
+/// takes them off the run queues until they are woken up.
+pub struct Scheduler
+{
+    // Run queue of each worker thread
+    queues: Vec<Mutex<VecDeque<Arc<Task>>>>,
+
+    // Queue that actors scheduled from outside of the worker threads go
+    // in next, so that they are spread over the workers
+    next_queue: AtomicUsize,
+
+    // Number of worker threads with nothing to run
+    num_sleeping: AtomicUsize,
+
+    // Workers with nothing to run wait on this
+    sleep_lock: Mutex<()>,
+    work_added: Condvar,
+
+    // Parked actors that are woken up at a deadline
+    deadlines: Mutex<BinaryHeap<Deadline>>,
+    deadline_added: Condvar,
+
+    // Threads are started when the first actor is scheduled
+    workers_started: Once,
+    timer_started: Once,
+}
+
+impl Scheduler
+{
+    pub fn new(num_threads: usize) -> Scheduler
+    {
+        assert!(num_threads > 0);
+
+        Scheduler {
+            queues: (0..num_threads).map(|_| Mutex::default()).collect(),
+            next_queue: AtomicUsize::new(0),
+            num_sleeping: AtomicUsize::new(0),
+            sleep_lock: Mutex::default(),
+            work_added: Condvar::new(),
+            deadlines: Mutex::default(),
+            deadline_added: Condvar::new(),
+            workers_started: Once::new(),
+            timer_started: Once::new(),
+        }
+    }
+
+    /// Number of worker threads to use by default, one per core
+    pub fn default_threads() -> usize
+    {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+
+    /// Index of the worker running on the current thread, if it is one of ours
+    fn current_worker(&self) -> Option<usize>
+    {
+        match WORKER.get() {
+            Some((sched, idx)) if sched == self as *const Self as usize => Some(idx),
+            _ => None,
+        }
+    }
+
+    /// Put an actor in a run queue
+    fn schedule(self: &Arc<Self>, task: Arc<Task>)
+    {
+        self.workers_started.call_once(|| {
+            for idx in 0..self.queues.len() {
+                let sched = self.clone();
+                thread::spawn(move || sched.work(idx));
+            }
+        });
+
+        // A worker keeps the actors it wakes up, and the others can take
+        // them if they run out of work
+        let idx = self.current_worker().unwrap_or_else(|| {
+            self.next_queue.fetch_add(1, atomic::Ordering::Relaxed) % self.queues.len()
+        });
+
+        self.queues[idx].lock().unwrap().push_back(task);
+
+        if self.num_sleeping.load(atomic::Ordering::SeqCst) > 0 {
+            let _guard = self.sleep_lock.lock().unwrap();
+            self.work_added.notify_one();
+        }
+    }
+
+    /// Wake up a parked actor once a deadline has passed
+    fn wake_at(self: &Arc<Self>, deadline: Instant, task: Arc<Task>)
+    {
+        self.timer_started.call_once(|| {
+            let sched = self.clone();
+            thread::spawn(move || sched.wake_deadlines());
+        });
+
+        self.deadlines.lock().unwrap().push(Deadline(deadline, task));
+        self.deadline_added.notify_one();
+    }
+
+    /// Run actors as they become ready, on one of the worker threads
+    fn work(&self, idx: usize)
+    {
+        WORKER.set(Some((self as *const Self as usize, idx)));
+
+        loop {
+            match self.find_task(idx) {
+                Some(task) => task.run(),
+                None => self.sleep(),
+            }
+        }
+    }
+
+    /// Take the next actor from our own run queue, or else
+    /// take half of the actors in the queue of another worker
+    fn find_task(&self, idx: usize) -> Option<Arc<Task>>
+    {
+        if let Some(task) = self.queues[idx].lock().unwrap().pop_front() {
+            return Some(task);
+        }
+
+        let num_queues = self.queues.len();
+
+        for ofs in 1..num_queues {
+            let mut victim = self.queues[(idx + ofs) % num_queues].lock().unwrap();
+
+            if victim.is_empty() {
+                continue;
+            }
+
+            // Take from the back, which the victim will get to last
+            let num_stolen = victim.len().div_ceil(2);
+            let stolen_idx = victim.len() - num_stolen;
+            let mut stolen = victim.split_off(stolen_idx);
+            drop(victim);
+
+            let task = stolen.pop_front();
+            self.queues[idx].lock().unwrap().append(&mut stolen);
+            return task;
+        }
+
+        None
+    }
+
+    /// Wait for actors to be scheduled
+    fn sleep(&self)
+    {
+        let guard = self.sleep_lock.lock().unwrap();
+        self.num_sleeping.fetch_add(1, atomic::Ordering::SeqCst);
+
+        // Look again now that we are counted as sleeping, since an actor
+        // scheduled before that would not have woken us up. The timeout
+        // is only a safety net.
+        if self.queues.iter().all(|queue| queue.lock().unwrap().is_empty()) {
+            let _ = self.work_added.wait_timeout(guard, Duration::from_millis(100)).unwrap();
+        }
+
+        self.num_sleeping.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Wake up parked actors as their deadlines pass
+    fn wake_deadlines(&self)
+    {
+        let mut deadlines = self.deadlines.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+
+            let deadline = match deadlines.peek() {
+                Some(next) => next.0,
+                None => {
+                    deadlines = self.deadline_added.wait(deadlines).unwrap();
+                    continue;
+                }
+            };
+
+            if deadline > now {
+                deadlines = self.deadline_added.wait_timeout(deadlines, deadline - now).unwrap().0;
+                continue;
+            }
+
+            let Deadline(_, task) = deadlines.pop().unwrap();
+            drop(deadlines);
+            task.wake();
+            deadlines = self.deadlines.lock().unwrap();
+        }
+    }
+}
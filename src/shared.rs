@@ -0,0 +1,210 @@
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, Ordering};
+use crate::vm::Actor;
+use crate::value::*;
+use crate::alloc::{Alloc, Tag, HEADER_SIZE};
+use crate::*;
+
+/// Bytes shared between actors. These live outside of any heap, and are
+/// freed once no heap holds a bytearray referencing them any more. Every
+/// access is atomic, since other actors may be accessing them at the same
+/// time from other threads.
+pub struct SharedBytes
+{
+    // Stored as words so that every aligned u32 is a valid atomic
+    words: Box<[AtomicU64]>,
+    len: usize,
+}
+
+impl SharedBytes
+{
+    pub fn num_bytes(&self) -> usize
+    {
+        self.len
+    }
+
+    /// Allocate zeroed shared bytes, or return None if there is no
+    /// memory for them
+    fn try_new(num_bytes: usize) -> Option<Self>
+    {
+        let num_words = num_bytes.div_ceil(8);
+
+        let mut words = Vec::new();
+        words.try_reserve_exact(num_words).ok()?;
+        words.resize_with(num_words, || AtomicU64::new(0));
+
+        Some(SharedBytes {
+            words: words.into_boxed_slice(),
+            len: num_bytes,
+        })
+    }
+
+    fn byte(&self, idx: usize) -> &AtomicU8
+    {
+        debug_assert!(idx < self.len);
+        unsafe { &*(self.words.as_ptr() as *const AtomicU8).add(idx) }
+    }
+
+    fn u32(&self, byte_idx: usize) -> &AtomicU32
+    {
+        debug_assert!(byte_idx.is_multiple_of(4) && byte_idx + 4 <= self.len);
+        unsafe { &*(self.words.as_ptr() as *const AtomicU32).add(byte_idx / 4) }
+    }
+}
+
+/// Bytearray whose bytes are shared with other actors. Sending one to
+/// another actor copies the bytearray, but both copies share the bytes.
+pub struct SharedByteArray
+{
+    // Kept alive by the heap holding the bytearray, which the collector
+    // tells about every shared bytearray it copies
+    pub(crate) bytes: *const SharedBytes,
+}
+
+impl SharedByteArray
+{
+    /// Bytes a shared bytearray occupies in the heap
+    pub fn alloc_size() -> usize
+    {
+        HEADER_SIZE + size_of::<SharedByteArray>()
+    }
+
+    /// Allocate a zeroed shared bytearray of a given size, or return
+    /// None if there is no memory for its bytes
+    pub fn with_size(num_bytes: usize, alloc: &mut Alloc) -> Option<Value>
+    {
+        let bytes = Arc::new(SharedBytes::try_new(num_bytes)?);
+        let sba = alloc.alloc(SharedByteArray { bytes: Arc::as_ptr(&bytes) }, Tag::SharedByteArray);
+        alloc.retain_shared(bytes);
+        Some(Value::shared_bytearray(sba))
+    }
+
+    fn shared(&self) -> &SharedBytes
+    {
+        unsafe { &*self.bytes }
+    }
+
+    pub fn num_bytes(&self) -> usize
+    {
+        self.shared().len
+    }
+
+    pub fn get(&self, idx: usize) -> u8
+    {
+        self.shared().byte(idx).load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, idx: usize, val: u8)
+    {
+        self.shared().byte(idx).store(val, Ordering::Relaxed)
+    }
+
+    /// Copy bytes out into a slice
+    pub fn copy_to(&self, src_idx: usize, dst: &mut [u8])
+    {
+        for (i, b) in dst.iter_mut().enumerate() {
+            *b = self.get(src_idx + i);
+        }
+    }
+
+    /// Check that a u32 access is aligned and within bounds
+    fn check_u32(&self, byte_idx: usize) -> Result<&AtomicU32, String>
+    {
+        if !byte_idx.is_multiple_of(4) {
+            return Err(format!("byte index {} is not aligned to 4 bytes", byte_idx));
+        }
+
+        if byte_idx + 4 > self.num_bytes() {
+            return Err(format!(
+                "byte index {} out of bounds for shared bytearray of length {}",
+                byte_idx, self.num_bytes()
+            ));
+        }
+
+        Ok(self.shared().u32(byte_idx))
+    }
+}
+
+pub fn sba_with_size(actor: &mut Actor, _self: Value, num_bytes: Value) -> Result<Value, String>
+{
+    let num_bytes = unwrap_usize!(num_bytes);
+
+    // The shared bytes are kept outside of the heap, and count against
+    // its limit like the tables of large bytearrays
+    actor.outside_check(num_bytes, &mut []);
+    actor.gc_check(SharedByteArray::alloc_size(), &mut []);
+
+    match SharedByteArray::with_size(num_bytes, &mut actor.alloc) {
+        Some(sba) => Ok(sba),
+        None => Err(format!("could not allocate a shared bytearray of {} bytes", num_bytes)),
+    }
+}
+
+pub fn sba_load_u32(_actor: &mut Actor, sba: Value, byte_idx: Value) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let val = sba.check_u32(byte_idx)?.load(Ordering::Relaxed);
+    Ok(Value::from(val))
+}
+
+pub fn sba_store_u32(_actor: &mut Actor, sba: Value, byte_idx: Value, val: Value) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let val = unwrap_u32!(val);
+    sba.check_u32(byte_idx)?.store(val, Ordering::Relaxed);
+    Ok(Value::NIL)
+}
+
+pub fn sba_atomic_load_u32(_actor: &mut Actor, sba: Value, byte_idx: Value) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let val = sba.check_u32(byte_idx)?.load(Ordering::SeqCst);
+    Ok(Value::from(val))
+}
+
+pub fn sba_atomic_store_u32(_actor: &mut Actor, sba: Value, byte_idx: Value, val: Value) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let val = unwrap_u32!(val);
+    sba.check_u32(byte_idx)?.store(val, Ordering::SeqCst);
+    Ok(Value::NIL)
+}
+
+/// Add to a u32, wrapping around on overflow
+/// Returns the value from before the addition
+pub fn sba_atomic_add_u32(_actor: &mut Actor, sba: Value, byte_idx: Value, val: Value) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let val = unwrap_u32!(val);
+    let prev = sba.check_u32(byte_idx)?.fetch_add(val, Ordering::SeqCst);
+    Ok(Value::from(prev))
+}
+
+/// Store a new u32 if the current one equals the expected one
+/// Returns the value from before, which equals the expected one on success
+pub fn sba_compare_exchange_u32(
+    _actor: &mut Actor,
+    sba: Value,
+    byte_idx: Value,
+    expected: Value,
+    new: Value,
+) -> Result<Value, String>
+{
+    let sba = unwrap_sba!(sba);
+    let byte_idx = unwrap_usize!(byte_idx);
+    let expected = unwrap_u32!(expected);
+    let new = unwrap_u32!(new);
+
+    let word = sba.check_u32(byte_idx)?;
+    let prev = match word.compare_exchange(expected, new, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(prev) | Err(prev) => prev,
+    };
+
+    Ok(Value::from(prev))
+}
@@ -0,0 +1,375 @@
+use std::fmt::Write;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use crate::vm::Actor;
+use crate::value::Value;
+use crate::alloc::{header_of, Tag, HEADER_SIZE};
+use crate::ast::ClassId;
+use crate::array::Array;
+use crate::closure::Closure;
+use crate::dict::Dict;
+use crate::gc::block_size;
+use crate::json::write_str;
+use crate::object::Object;
+use crate::str::Str;
+
+/// Kinds of nodes, in the order the file lists them. These are the ones
+/// V8 uses, which is what the tools reading the file expect.
+const NODE_TYPES: [&str; 14] = [
+    "hidden", "array", "string", "object", "code", "closure", "regexp", "number",
+    "native", "synthetic", "concatenated string", "sliced string", "symbol", "bigint",
+];
+
+const NODE_HIDDEN: usize = 0;
+const NODE_STRING: usize = 2;
+const NODE_OBJECT: usize = 3;
+const NODE_CLOSURE: usize = 5;
+const NODE_NUMBER: usize = 7;
+const NODE_SYNTHETIC: usize = 9;
+const NODE_BIGINT: usize = 13;
+
+/// Kinds of edges, in the order the file lists them
+const EDGE_TYPES: [&str; 7] = [
+    "context", "element", "property", "internal", "hidden", "shortcut", "weak",
+];
+
+const EDGE_CONTEXT: usize = 0;
+const EDGE_ELEMENT: usize = 1;
+const EDGE_PROPERTY: usize = 2;
+const EDGE_INTERNAL: usize = 3;
+const EDGE_HIDDEN: usize = 4;
+const EDGE_WEAK: usize = 6;
+
+/// Fields of every node and edge, which the file stores one after the
+/// other in a flat array of numbers
+const NODE_FIELDS: [&str; 7] = [
+    "type", "name", "id", "self_size", "edge_count", "trace_node_id", "detachedness",
+];
+const EDGE_FIELDS: [&str; 3] = ["type", "name_or_index", "to_node"];
+
+/// Longest string a string node is named after. Longer ones are cut.
+const MAX_NAME_LEN: usize = 256;
+
+struct Node
+{
+    kind: usize,
+
+    // Index of the name in the string table
+    name: usize,
+
+    self_size: usize,
+    edges: Vec<Edge>,
+}
+
+struct Edge
+{
+    kind: usize,
+
+    // Element index for element and hidden edges, and the index of the
+    // name in the string table for the others
+    name_or_index: usize,
+
+    // Index of the node the edge points to
+    to_node: usize,
+}
+
+/// Graph of the heap of an actor, as a V8 heap snapshot describes it.
+///
+/// Every live block is a node, except for tables, which are part of the
+/// node of the block they belong to. The first node is the root, which
+/// has an edge to a node for each kind of root the collector forwards,
+/// and those have an edge to each value they hold.
+struct Snapshot
+{
+    nodes: Vec<Node>,
+
+    // Strings the nodes and edges are named after, each listed once
+    strings: Vec<String>,
+    string_idxs: HashMap<String, usize>,
+
+    // Node of each block, by address
+    node_of: HashMap<usize, usize>,
+
+    // Names of the slots of each class, by slot index
+    field_names: HashMap<ClassId, Vec<String>>,
+}
+
+impl Snapshot
+{
+    fn new() -> Self
+    {
+        Self {
+            nodes: Vec::new(),
+            strings: Vec::new(),
+            string_idxs: HashMap::default(),
+            node_of: HashMap::default(),
+            field_names: HashMap::default(),
+        }
+    }
+
+    fn string(&mut self, s: &str) -> usize
+    {
+        if let Some(idx) = self.string_idxs.get(s) {
+            return *idx;
+        }
+
+        let idx = self.strings.len();
+        self.strings.push(s.to_string());
+        self.string_idxs.insert(s.to_string(), idx);
+        idx
+    }
+
+    fn add_node(&mut self, kind: usize, name: &str, self_size: usize) -> usize
+    {
+        let name = self.string(name);
+        self.nodes.push(Node { kind, name, self_size, edges: Vec::new() });
+        self.nodes.len() - 1
+    }
+
+    /// Add an edge named after a string
+    fn add_named_edge(&mut self, from: usize, kind: usize, name: &str, to_node: usize)
+    {
+        let name_or_index = self.string(name);
+        self.nodes[from].edges.push(Edge { kind, name_or_index, to_node });
+    }
+
+    /// Add an edge to what a value points at, if it is a block in the heap
+    fn add_edge(&mut self, from: usize, kind: usize, name_or_index: usize, val: Value)
+    {
+        if let Some(to_node) = self.node_of_val(val) {
+            self.nodes[from].edges.push(Edge { kind, name_or_index, to_node });
+        }
+    }
+
+    fn add_val_edge(&mut self, from: usize, kind: usize, name: &str, val: Value)
+    {
+        if let Some(to_node) = self.node_of_val(val) {
+            self.add_named_edge(from, kind, name, to_node);
+        }
+    }
+
+    fn node_of_val(&self, val: Value) -> Option<usize>
+    {
+        if !val.is_heap() {
+            return None;
+        }
+
+        self.node_of.get(&(val.heap_ptr() as usize)).copied()
+    }
+
+    /// Names of the slots of a class, by slot index
+    fn field_names(&mut self, actor: &mut Actor, class_id: ClassId) -> &[String]
+    {
+        self.field_names.entry(class_id).or_insert_with(|| {
+            actor.with_class(class_id, |class| {
+                let mut names = vec![String::new(); class.fields.len()];
+                for (name, idx) in &class.fields {
+                    names[*idx] = name.clone();
+                }
+                names
+            })
+        })
+    }
+
+    /// Add the node of a block, with its kind and name
+    fn add_block(&mut self, actor: &mut Actor, p: *const u8, self_size: usize)
+    {
+        let (kind, name) = match header_of(p).tag() {
+            Tag::Str => {
+                let s = unsafe { &*(p as *const Str) }.as_str();
+                let mut end = s.len().min(MAX_NAME_LEN);
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                (NODE_STRING, s[..end].to_string())
+            }
+
+            Tag::Object => {
+                let obj = unsafe { &*(p as *const Object) };
+                (NODE_OBJECT, actor.get_class_name(obj.class_id))
+            }
+
+            Tag::Closure => {
+                let clos = unsafe { &*(p as *const Closure) };
+                (NODE_CLOSURE, actor.get_fun_name(clos.fun_id))
+            }
+
+            Tag::Int64 => (NODE_NUMBER, unsafe { *(p as *const i64) }.to_string()),
+            Tag::Float64 => (NODE_NUMBER, unsafe { *(p as *const f64) }.to_string()),
+            Tag::BigInt => (NODE_BIGINT, "BigInt".to_string()),
+            Tag::Cell => (NODE_HIDDEN, "Cell".to_string()),
+
+            // Arrays, dicts and bytearrays
+            tag => (NODE_OBJECT, format!("{:?}", tag)),
+        };
+
+        let node = self.add_node(kind, &name, self_size);
+        self.node_of.insert(p as usize, node);
+    }
+
+    /// Add the edges from the node of a block to what the block references
+    fn add_block_edges(&mut self, actor: &mut Actor, p: *const u8)
+    {
+        let node = self.node_of[&(p as usize)];
+
+        match header_of(p).tag() {
+            Tag::Object => {
+                let obj = unsafe { &*(p as *const Object) };
+                let names = self.field_names(actor, obj.class_id).to_vec();
+
+                for i in 0..obj.num_slots() {
+                    let name = match names.get(i) {
+                        Some(name) => name.clone(),
+                        None => i.to_string(),
+                    };
+                    self.add_val_edge(node, EDGE_PROPERTY, &name, obj.get(i));
+                }
+            }
+
+            Tag::Closure => {
+                let clos = unsafe { &*(p as *const Closure) };
+                for i in 0..clos.num_slots() {
+                    self.add_val_edge(node, EDGE_CONTEXT, &i.to_string(), clos.get(i));
+                }
+            }
+
+            Tag::Cell => {
+                let val = unsafe { *(p as *const Value) };
+                self.add_val_edge(node, EDGE_INTERNAL, "value", val);
+            }
+
+            Tag::WeakRef => {
+                let val = unsafe { *(p as *const Value) };
+                self.add_val_edge(node, EDGE_WEAK, "target", val);
+            }
+
+            Tag::Array => {
+                let arr = unsafe { &*(p as *const Array) };
+                for i in 0..arr.len() {
+                    self.add_edge(node, EDGE_ELEMENT, i, arr.get(i));
+                }
+            }
+
+            // Each entry is a property, and the dict also holds its key
+            Tag::Dict => {
+                let dict = unsafe { &*(p as *const Dict) };
+                let mut slot_idx = 0;
+                let mut num_keys = 0;
+
+                while let Some((idx, key, val)) = dict.next_entry(slot_idx) {
+                    self.add_val_edge(node, EDGE_PROPERTY, key.as_str(), val);
+                    self.add_edge(node, EDGE_HIDDEN, num_keys, key);
+                    num_keys += 1;
+                    slot_idx = idx + 1;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Write the graph out as the JSON of a heap snapshot file
+    fn to_json(&self) -> String
+    {
+        let mut out = String::new();
+        let list = |names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| format!("\"{}\"", n)).collect();
+            format!("[{}]", names.join(","))
+        };
+
+        let num_edges: usize = self.nodes.iter().map(|node| node.edges.len()).sum();
+
+        write!(out, "{{\"snapshot\":{{\"meta\":{{").unwrap();
+        write!(out, "\"node_fields\":{},", list(&NODE_FIELDS)).unwrap();
+        write!(out, "\"node_types\":[{},\"string\",\"number\",\"number\",\"number\",\"number\",\"number\"],", list(&NODE_TYPES)).unwrap();
+        write!(out, "\"edge_fields\":{},", list(&EDGE_FIELDS)).unwrap();
+        write!(out, "\"edge_types\":[{},\"string_or_number\",\"node\"],", list(&EDGE_TYPES)).unwrap();
+        write!(out, "\"trace_function_info_fields\":{},", list(&["function_id", "name", "script_name", "script_id", "line", "column"])).unwrap();
+        write!(out, "\"trace_node_fields\":{},", list(&["id", "function_info_index", "count", "size", "children"])).unwrap();
+        write!(out, "\"sample_fields\":{},", list(&["timestamp_us", "last_assigned_id"])).unwrap();
+        write!(out, "\"location_fields\":{}", list(&["object_index", "script_id", "line", "column"])).unwrap();
+        writeln!(out, "}},\"node_count\":{},\"edge_count\":{},\"trace_function_count\":0}},", self.nodes.len(), num_edges).unwrap();
+
+        // Node ids only have to be unique. V8 gives objects odd ones.
+        out.push_str("\"nodes\":[");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let sep = if idx == 0 { "" } else { ",\n" };
+            write!(out, "{}{},{},{},{},{},0,0", sep, node.kind, node.name, 2 * idx + 1, node.self_size, node.edges.len()).unwrap();
+        }
+        out.push_str("],\n");
+
+        // Edges point at the offset of a node in the flat array of nodes
+        out.push_str("\"edges\":[");
+        let mut first = true;
+        for edge in self.nodes.iter().flat_map(|node| &node.edges) {
+            let sep = if first { "" } else { ",\n" };
+            write!(out, "{}{},{},{}", sep, edge.kind, edge.name_or_index, edge.to_node * NODE_FIELDS.len()).unwrap();
+            first = false;
+        }
+        out.push_str("],\n");
+
+        out.push_str("\"trace_function_infos\":[],\"trace_tree\":[],\"samples\":[],\"locations\":[],\n");
+
+        out.push_str("\"strings\":[");
+        for (idx, s) in self.strings.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(",\n");
+            }
+            write_str(&mut out, s);
+        }
+        out.push_str("]}\n");
+
+        out
+    }
+}
+
+/// Collect the heap of an actor, then produce a snapshot of everything
+/// live in it, as the JSON of a V8 `.heapsnapshot` file. Tools that read
+/// these, such as the memory panel of the Chrome developer tools, can then
+/// work out what retains what.
+pub fn heap_snapshot(actor: &mut Actor) -> String
+{
+    // Only what is live ends up in the old space, and nothing moves from
+    // here on, since building the graph allocates nothing in the heap
+    actor.gc_collect_full(&mut []);
+
+    let mut snapshot = Snapshot::new();
+    let root = snapshot.add_node(NODE_SYNTHETIC, "", 0);
+
+    // The blocks of the heap, leaving out tables, which are counted as
+    // part of the block they belong to
+    let mut blocks = Vec::new();
+    let space = actor.old_space();
+    let mut offset = 0;
+    while offset < space.bytes_used() {
+        let p = space.block_at(offset);
+        offset += HEADER_SIZE + header_of(p).size();
+
+        if let Some(self_size) = block_size(p) {
+            blocks.push((p as *const u8, self_size));
+        }
+    }
+
+    for (p, self_size) in &blocks {
+        snapshot.add_block(actor, *p, *self_size);
+    }
+
+    for (p, _) in &blocks {
+        snapshot.add_block_edges(actor, *p);
+    }
+
+    // A value held in more than one place of the same kind, such as a
+    // string constant used by many instructions, gets a single edge
+    for (idx, (name, vals)) in actor.heap_roots().into_iter().enumerate() {
+        let group = snapshot.add_node(NODE_SYNTHETIC, name, 0);
+        snapshot.nodes[root].edges.push(Edge { kind: EDGE_ELEMENT, name_or_index: idx, to_node: group });
+
+        let mut seen = HashSet::default();
+        for (i, val) in vals.into_iter().enumerate() {
+            if val.is_heap() && seen.insert(val.heap_ptr() as usize) {
+                snapshot.add_edge(group, EDGE_ELEMENT, i, val);
+            }
+        }
+    }
+
+    snapshot.to_json()
+}
@@ -62,6 +62,35 @@ struct Env
 
 impl Env
 {
+    /// Create an environment with a scope holding the core classes
+    fn with_core_classes(next_global_idx: u32) -> Self
+    {
+        let mut env = Env {
+            next_global_idx,
+            ..Default::default()
+        };
+        env.push_scope();
+
+        // Register core classes
+        env.define("Int64", Decl::Class { id: INT64_ID });
+        env.define("Float64", Decl::Class { id: FLOAT64_ID });
+        env.define("String", Decl::Class { id: STRING_ID });
+        env.define("Array", Decl::Class { id: ARRAY_ID });
+        env.define("ByteArray", Decl::Class { id: BYTEARRAY_ID });
+        env.define("SharedByteArray", Decl::Class { id: SHARED_BYTEARRAY_ID });
+        env.define("BigInt", Decl::Class { id: BIGINT_ID });
+        env.define("Dict", Decl::Class { id: DICT_ID });
+        env.define("WeakRef", Decl::Class { id: WEAKREF_ID });
+        env.define("UIEvent", Decl::Class { id: UIEVENT_ID });
+        env.define("AudioNeeded", Decl::Class { id: AUDIO_NEEDED_ID });
+        env.define("AudioData", Decl::Class { id: AUDIO_DATA_ID });
+        env.define("Error", Decl::Class { id: ERROR_ID });
+        env.define("Range", Decl::Class { id: RANGE_ID });
+        env.define("ActorError", Decl::Class { id: ACTOR_ERROR_ID });
+
+        env
+    }
+
     fn push_scope(&mut self)
     {
         let num_scopes = self.scopes.len();
@@ -154,24 +183,26 @@ impl Env
     }
 }
 
+/// Environment of a REPL session. The declarations of each input remain
+/// visible to the inputs that follow, and can be shadowed by them.
+pub struct ReplEnv
+{
+    env: Env,
+}
+
+impl ReplEnv
+{
+    pub fn new() -> Self
+    {
+        Self { env: Env::with_core_classes(0) }
+    }
+}
+
 impl Program
 {
     pub fn resolve_syms(&mut self) -> Result<(), ParseError>
     {
-        let mut env = Env::default();
-        env.next_global_idx = self.num_globals;
-        env.push_scope();
-
-        // Register core classes
-        env.define("Int64", Decl::Class { id: INT64_ID });
-        env.define("Float64", Decl::Class { id: FLOAT64_ID });
-        env.define("String", Decl::Class { id: STRING_ID });
-        env.define("Array", Decl::Class { id: ARRAY_ID });
-        env.define("ByteArray", Decl::Class { id: BYTEARRAY_ID });
-        env.define("Dict", Decl::Class { id: DICT_ID });
-        env.define("UIEvent", Decl::Class { id: UIEVENT_ID });
-        env.define("AudioNeeded", Decl::Class { id: AUDIO_NEEDED_ID });
-        env.define("AudioData", Decl::Class { id: AUDIO_DATA_ID });
+        let mut env = Env::with_core_classes(self.num_globals);
 
         // For each unit in the program
         let unit_paths: Vec<String> = self.units.keys().cloned().collect();
@@ -184,6 +215,56 @@ impl Program
         // Set the number of globals
         self.num_globals = env.next_global_idx;
 
+        let class_ids = self.classes.keys().cloned().collect();
+        self.resolve_inheritance(class_ids)
+    }
+
+    /// Resolve the symbols of one REPL input. If this fails, none of the
+    /// declarations made by the input are kept.
+    pub fn resolve_repl_input(&mut self, repl_env: &mut ReplEnv, unit: &Unit) -> Result<(), ParseError>
+    {
+        let env = &mut repl_env.env;
+        let num_scopes = env.scopes.len();
+        env.next_global_idx = self.num_globals;
+
+        let result = self.resolve_repl_unit(env, unit);
+        if result.is_err() {
+            env.scopes.truncate(num_scopes);
+        }
+
+        self.num_globals = env.next_global_idx;
+        result
+    }
+
+    fn resolve_repl_unit(&mut self, env: &mut Env, unit: &Unit) -> Result<(), ParseError>
+    {
+        // Classes and statements get separate scopes,
+        // as they would in the body of a unit
+        env.push_scope();
+        for (name, id) in &unit.classes {
+            env.define(name, Decl::Class { id: *id });
+        }
+        env.push_scope();
+
+        // The statements are resolved in a scope that is left open
+        // rather than in a block, so that their declarations persist
+        let mut unit_fn = std::mem::take(self.funs.get_mut(&unit.unit_fn).unwrap());
+        let mut body = std::mem::take(&mut unit_fn.body);
+        let result = match body.stmt.as_mut() {
+            Stmt::Block(stmts) => resolve_stmts(stmts, self, &mut unit_fn, env),
+            _ => unreachable!(),
+        };
+        unit_fn.body = body;
+        *self.funs.get_mut(&unit.unit_fn).unwrap() = unit_fn;
+        result?;
+
+        self.resolve_inheritance(unit.classes.values().cloned().collect())
+    }
+
+    /// Copy inherited methods and fields into the given classes. Classes
+    /// outside of this set are taken to have been processed already.
+    fn resolve_inheritance(&mut self, class_ids: HashSet<ClassId>) -> Result<(), ParseError>
+    {
         // Recursively process the inheritance chain for a given class
         fn process(
             class_id: ClassId,
@@ -252,11 +333,13 @@ impl Program
         }
 
         // Set of classes that have been processed
-        let mut processed = HashSet::<ClassId>::default();
+        let mut processed: HashSet<ClassId> = self.classes.keys()
+            .filter(|id| !class_ids.contains(id))
+            .cloned()
+            .collect();
 
         // For each class id
-        let classes: Vec<ClassId> = self.classes.keys().cloned().collect();
-        for class_id in classes{
+        for class_id in class_ids {
             // If this class has already been processed, skip it
             if processed.contains(&class_id) {
                 continue;
@@ -378,6 +461,37 @@ impl Function
     }
 }
 
+/// Resolve the statements of a block in the current scope
+fn resolve_stmts(
+    stmts: &mut [StmtBox],
+    prog: &mut Program,
+    fun: &mut Function,
+    env: &mut Env
+) -> Result<(), ParseError>
+{
+    // Pre-declare functions before symbols are resolved
+    // This allows referencing functiond before their definition occurs
+    for stmt in stmts.iter_mut() {
+        if let Stmt::Let { mutable, var_name, init_expr, ref mut decl } = stmt.stmt.as_mut() {
+            if let Expr::Fun { fun_id, .. } = init_expr.expr.as_ref() {
+                let new_decl = if fun.is_unit && !*mutable {
+                    env.define(var_name, Decl::Fun { id: *fun_id })
+                } else {
+                    env.define_local(var_name, *mutable, fun)
+                };
+
+                *decl = Some(new_decl)
+            }
+        }
+    }
+
+    for stmt in stmts {
+        stmt.resolve_syms(prog, fun, env)?;
+    }
+
+    Ok(())
+}
+
 impl StmtBox
 {
     fn resolve_syms(
@@ -400,27 +514,7 @@ impl StmtBox
 
             Stmt::Block(stmts) => {
                 env.push_scope();
-
-                // Pre-declare functions before symbols are resolved
-                // This allows referencing functiond before their definition occurs
-                for stmt in stmts.iter_mut() {
-                    if let Stmt::Let { mutable, var_name, init_expr, ref mut decl } = stmt.stmt.as_mut() {
-                        if let Expr::Fun { fun_id, .. } = init_expr.expr.as_ref() {
-                            let new_decl = if fun.is_unit && !*mutable {
-                                env.define(var_name, Decl::Fun { id: *fun_id })
-                            } else {
-                                env.define_local(var_name, *mutable, fun)
-                            };
-
-                            *decl = Some(new_decl)
-                        }
-                    }
-                }
-
-                for stmt in stmts {
-                    stmt.resolve_syms(prog, fun, env)?;
-                }
-
+                resolve_stmts(stmts, prog, fun, env)?;
                 env.pop_scope();
             }
 
@@ -442,10 +536,33 @@ impl StmtBox
                 env.pop_scope();
             }
 
+            Stmt::ForIn { var_names, iter_expr, body_stmt, decls } => {
+                iter_expr.resolve_syms(prog, fun, env)?;
+
+                env.push_scope();
+                *decls = var_names.iter().map(|name| env.define_local(name, false, fun)).collect();
+                body_stmt.resolve_syms(prog, fun, env)?;
+                env.pop_scope();
+            }
+
             Stmt::Assert { test_expr } => {
                 test_expr.resolve_syms(prog, fun, env)?;
             }
 
+            Stmt::Try { body_stmt, catch_var, catch_stmt, decl } => {
+                body_stmt.resolve_syms(prog, fun, env)?;
+
+                // The caught value is only visible inside the catch block
+                env.push_scope();
+                *decl = Some(env.define_local(catch_var, false, fun));
+                catch_stmt.resolve_syms(prog, fun, env)?;
+                env.pop_scope();
+            }
+
+            Stmt::Throw(expr) => {
+                expr.resolve_syms(prog, fun, env)?;
+            }
+
             // Variable declaration
             Stmt::Let { mutable, var_name, init_expr, decl } => {
                 init_expr.resolve_syms(prog, fun, env)?;
@@ -662,16 +779,26 @@ impl ExprBox
                 match callee.expr.as_ref() {
                     // New class instance
                     Expr::Ref { decl: Decl::Class { id }, name } => {
-                        match prog.classes.get(id) {
+                        match (crate::runtime::get_core_ctor(*id), prog.classes.get(id)) {
+                            // If this is a core class created by a host function
+                            (Some(ctor), _) => {
+                                if ctor.num_params() != args.len() {
+                                    return ParseError::with_pos(
+                                        &format!("argument mismatch in call to constructor of class `{}`", name),
+                                        &callee.pos
+                                    );
+                                }
+                            }
+
                             // If this is a core class with no definition
-                            None => {
+                            (None, None) => {
                                 return ParseError::with_pos(
                                     &format!("cannot instantiate core class `{}` via constructor call", name),
                                     &callee.pos
                                 );
-                            },
+                            }
 
-                            Some(class) => {
+                            (None, Some(class)) => {
                                 let ctor_argc = match class.methods.get("init") {
                                     Some(init_id) => prog.funs[init_id].params.len(),
                                     None => 1
@@ -871,4 +998,44 @@ mod tests
     {
         fails("Array();");
     }
+
+    #[test]
+    fn try_catch()
+    {
+        succeeds("try {} catch (e) { $println(e); }");
+        succeeds("fun f() { try { throw 1; } catch (e) { return e; } }");
+        succeeds("try {} catch (e) {} try {} catch (e) {}");
+
+        // The caught value is only visible in the catch block
+        fails("try {} catch (e) {} $println(e);");
+        fails("try { $println(e); } catch (e) {}");
+        fails("try {} catch (e) { e = 1; }");
+    }
+
+    #[test]
+    fn repl_inputs()
+    {
+        let mut prog = crate::ast::Program::new();
+        let mut env = super::ReplEnv::new();
+
+        let mut input = |src: &str| {
+            dbg!(src);
+            let unit = crate::parser::parse_repl_input(src, &mut prog).unwrap();
+            prog.resolve_repl_input(&mut env, &unit).is_ok()
+        };
+
+        // Declarations stay visible to later inputs
+        assert!(input("let x = 1;"));
+        assert!(input("fun f() { return x; }"));
+        assert!(input("class C {}"));
+        assert!(input("f(); C(); x;"));
+
+        // Later inputs can shadow them
+        assert!(input("let x = 2;"));
+        assert!(input("let var f = 3; f = 4;"));
+
+        // Nothing declared by an input that fails is kept
+        assert!(!input("let y = 1; z;"));
+        assert!(!input("y;"));
+    }
 }
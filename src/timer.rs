@@ -0,0 +1,262 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Weak, Mutex, Condvar};
+use std::sync::mpsc::TrySendError;
+use std::thread;
+use std::time::{Duration, Instant};
+use rustc_hash::FxHashMap as HashMap;
+use crate::vm::{VM, Actor};
+use crate::alloc::Alloc;
+use crate::gc::{undo_forwarding, Copier, StrTable, UndoLog};
+use crate::value::*;
+use crate::*;
+
+/// Timer waiting to deliver a message to an actor
+struct Timer
+{
+    // Actor the message is delivered to
+    actor_id: u64,
+
+    // VM reference, to send messages to the actor
+    vm: Arc<Mutex<VM>>,
+
+    // Message allocator of the actor
+    actor_msg_alloc: Weak<Mutex<Alloc>>,
+
+    // Time between deliveries, for a timer that repeats
+    period: Option<Duration>,
+
+    // Message to deliver. A copy of it is delivered each time.
+    msg: Value,
+
+    // Allocator holding the message, if it is heap-allocated
+    #[allow(dead_code)]
+    msg_alloc: Option<Alloc>,
+}
+
+impl Timer
+{
+    /// Copy the message into the actor's message allocator and send it
+    fn deliver(&mut self, str_table: &mut StrTable, undo_log: &mut UndoLog) -> Delivery
+    {
+        let alloc_rc = match self.actor_msg_alloc.upgrade() {
+            Some(rc) => rc,
+            None => return Delivery::Gone, // Actor is terminated
+        };
+
+        // The VM lock is only held long enough to look the actor up, so
+        // that a full mailbox can't hold up every other actor
+        let actor_tx = match self.vm.lock().unwrap().get_actor_tx(self.actor_id) {
+            Some(actor_tx) => actor_tx,
+            None => return Delivery::Gone,
+        };
+
+        let mut msg_alloc = alloc_rc.lock().unwrap();
+
+        // Look for room before copying, so that a full mailbox doesn't
+        // leave copies behind that pile up in the message allocator
+        if !actor_tx.has_room(&msg_alloc) {
+            return Delivery::Full;
+        }
+
+        // The message has to stay intact for the next delivery, so the
+        // forwarding addresses the copy leaves in it are taken back out
+        let bytes_before = msg_alloc.bytes_used();
+        let mut copier = Copier::with_undo(&mut msg_alloc, str_table, undo_log);
+        let msg = copier.forward(self.msg);
+        copier.run();
+        undo_forwarding(undo_log);
+        let size = msg_alloc.bytes_used() - bytes_before;
+
+        // Send while holding the message allocator lock, so that the
+        // actor doesn't reset its message allocator in the meantime
+        match actor_tx.try_send_nocopy(msg, size) {
+            Ok(()) => Delivery::Sent,
+            Err(TrySendError::Full(())) => Delivery::Full,
+            Err(TrySendError::Disconnected(())) => Delivery::Gone,
+        }
+    }
+}
+
+/// Outcome of trying to deliver the message of a timer
+enum Delivery
+{
+    Sent,
+
+    // The actor's mailbox is full
+    Full,
+
+    // The actor is terminated
+    Gone,
+}
+
+// Messages are only accessed while the timers are locked
+unsafe impl Send for Timer {}
+
+#[derive(Default)]
+struct TimerState
+{
+    // Next timer id to assign
+    next_id: u64,
+
+    // Timers that have not fired yet, or that repeat
+    timers: HashMap<u64, Timer>,
+
+    // When each timer is next due, soonest first. Cancelled timers
+    // are left in here and skipped when they come up.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+
+    // Whether the thread delivering messages has been started
+    started: bool,
+
+    // Scratch space for copying messages
+    str_table: StrTable,
+    undo_log: UndoLog,
+}
+
+/// Delivers the messages of timers from a thread of its own, which is
+/// started when the first timer is added
+#[derive(Default)]
+pub struct TimerService
+{
+    state: Mutex<TimerState>,
+
+    // Signaled when a timer is added, which can make the next one due sooner
+    added: Condvar,
+}
+
+impl TimerService
+{
+    /// Add a timer delivering a message to an actor after a delay, and
+    /// then every period after that if there is one. Returns the timer id.
+    fn add(self: &Arc<Self>, actor: &mut Actor, delay: Duration, period: Option<Duration>, msg: Value) -> u64
+    {
+        let (msg, msg_alloc) = actor.copy_out(msg);
+
+        let timer = Timer {
+            actor_id: actor.actor_id,
+            vm: actor.vm.clone(),
+            actor_msg_alloc: Arc::downgrade(&actor.msg_alloc),
+            period,
+            msg,
+            msg_alloc,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let timer_id = state.next_id;
+        state.next_id += 1;
+        state.timers.insert(timer_id, timer);
+        state.deadlines.push(Reverse((Instant::now() + delay, timer_id)));
+
+        if !state.started {
+            state.started = true;
+            let service = self.clone();
+            thread::spawn(move || service.run());
+        }
+
+        drop(state);
+        self.added.notify_one();
+
+        timer_id
+    }
+
+    /// Cancel a timer. Returns false if it had already fired,
+    /// or was already cancelled.
+    fn cancel(&self, timer_id: u64) -> bool
+    {
+        self.state.lock().unwrap().timers.remove(&timer_id).is_some()
+    }
+
+    /// Deliver messages as timers come due
+    fn run(&self)
+    {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+
+            let (deadline, timer_id) = match state.deadlines.peek() {
+                Some(&Reverse(next)) => next,
+                None => {
+                    state = self.added.wait(state).unwrap();
+                    continue;
+                }
+            };
+
+            if deadline > now {
+                state = self.added.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+
+            state.deadlines.pop();
+
+            let TimerState { timers, str_table, undo_log, .. } = &mut *state;
+
+            // The timer may have been cancelled
+            let timer = match timers.get_mut(&timer_id) {
+                Some(timer) => timer,
+                None => continue,
+            };
+
+            let delivery = timer.deliver(str_table, undo_log);
+
+            match (delivery, timer.period) {
+                // A repeating timer skips a delivery if the mailbox is full
+                (Delivery::Sent | Delivery::Full, Some(period)) => {
+                    // Skip the deliveries we are too late for,
+                    // rather than delivering them all at once
+                    let next = std::cmp::max(deadline + period, now);
+                    state.deadlines.push(Reverse((next, timer_id)));
+                }
+
+                // A one-shot timer tries again once there may be room
+                (Delivery::Full, None) => {
+                    let next = now + Duration::from_millis(1);
+                    state.deadlines.push(Reverse((next, timer_id)));
+                }
+
+                _ => {
+                    state.timers.remove(&timer_id);
+                }
+            }
+        }
+    }
+}
+
+/// Get the timer service of the VM an actor belongs to
+fn timer_service(actor: &Actor) -> Arc<TimerService>
+{
+    actor.vm.lock().unwrap().timers.clone()
+}
+
+/// Send a message to the current actor after a number of milliseconds
+/// Returns a timer id
+pub fn timer_after(actor: &mut Actor, msecs: Value, msg: Value) -> Result<Value, String>
+{
+    let msecs = unwrap_u64!(msecs);
+    let timer_id = timer_service(actor).add(actor, Duration::from_millis(msecs), None, msg);
+    Ok(actor.int64(timer_id as i64))
+}
+
+/// Send a message to the current actor every given number of milliseconds
+/// Returns a timer id
+pub fn timer_every(actor: &mut Actor, msecs: Value, msg: Value) -> Result<Value, String>
+{
+    let msecs = unwrap_u64!(msecs);
+
+    if msecs == 0 {
+        return Err("timer period must be greater than zero".into());
+    }
+
+    let period = Duration::from_millis(msecs);
+    let timer_id = timer_service(actor).add(actor, period, Some(period), msg);
+    Ok(actor.int64(timer_id as i64))
+}
+
+/// Stop a timer from sending any more messages
+/// Returns false if it had already fired or was cancelled
+pub fn timer_cancel(actor: &mut Actor, timer_id: Value) -> Result<Value, String>
+{
+    let timer_id = unwrap_u64!(timer_id);
+    Ok(Value::from(timer_service(actor).cancel(timer_id)))
+}
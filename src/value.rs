@@ -5,8 +5,9 @@
 //! ```text
 //!   bits 2..0   class
 //!   x00         fixnum, 62-bit signed integer, stored as n << 2
-//!   001         pointer compared by identity: Object Array ByteArray Dict Closure Cell
-//!   011         pointer compared by value:    Str Int64 Float64
+//!   001         pointer compared by identity: Object Array ByteArray SharedByteArray
+//!               Dict Closure Cell WeakRef
+//!   011         pointer compared by value:    Str Int64 Float64 BigInt
 //!   101         immediate: nil true false undef Fun Class HostFn
 //!   x10         flonum (see below)
 //!   111         reserved
@@ -14,7 +15,7 @@
 //!
 //! Bit 1 is the *compare by value* bit. It is set only for the types whose
 //! equality is not bitwise: flonums (+0.0 vs -0.0, NaN), strings (structural)
-//! and boxed numbers (int/float cross-comparison). So `eq` is a mask test
+//! and boxed numbers (int/float cross-comparison, bigints by value). So `eq` is a mask test
 //! plus a word compare, and every other combination falls out correct.
 //!
 //! Pointers only record *that* they point at a heap block. The kind comes
@@ -52,6 +53,7 @@
 //! sound:
 //! - an Int64 box never holds a value that fits in a fixnum
 //! - a Float64 box never holds a double that fits in a flonum
+//! - a BigInt never holds an integer that fits in an Int64 box
 //! - unused immediate payload bits are zero
 //!
 //! # Assumptions
@@ -66,8 +68,10 @@
 use std::fmt;
 use crate::alloc::{header_of, Tag};
 use crate::array::Array;
+use crate::bigint::{BigInt, BigNum};
 use crate::ast::{ClassId, FunId};
 use crate::bytearray::ByteArray;
+use crate::shared::SharedByteArray;
 use crate::closure::Closure;
 use crate::dict::Dict;
 use crate::host::HostFn;
@@ -118,13 +122,16 @@ pub enum Type
     Bool,
     Int64,
     Float64,
+    BigInt,
     String,
     Array,
     ByteArray,
+    SharedByteArray,
     Dict,
     Object,
     Closure,
     Cell,
+    WeakRef,
     Fun,
     Class,
     HostFn,
@@ -400,6 +407,19 @@ impl Value
     #[inline(always)]
     pub fn is_float64_box(self) -> bool { self.is_ptr_val(Tag::Float64) }
 
+    #[inline(always)]
+    pub fn bigint(p: *mut BigInt) -> Value { Value::ptr_val(p as *const u8) }
+
+    #[inline(always)]
+    pub fn is_bigint(self) -> bool { self.is_ptr_val(Tag::BigInt) }
+
+    #[inline(always)]
+    pub fn as_bigint<'a>(self) -> &'a BigInt
+    {
+        debug_assert!(self.is_bigint());
+        unsafe { &*(self.heap_ptr() as *const BigInt) }
+    }
+
     // Heap objects
 
     #[inline(always)]
@@ -484,6 +504,25 @@ impl Value
         if self.is_bytearray() { Some(self.as_ba()) } else { None }
     }
 
+    #[inline(always)]
+    pub fn shared_bytearray(p: *mut SharedByteArray) -> Value { Value::ptr_id(p as *const u8) }
+
+    #[inline(always)]
+    pub fn is_shared_bytearray(self) -> bool { self.is_ptr_id(Tag::SharedByteArray) }
+
+    #[inline(always)]
+    pub fn as_sba<'a>(self) -> &'a mut SharedByteArray
+    {
+        debug_assert!(self.is_shared_bytearray());
+        unsafe { &mut *(self.heap_ptr() as *mut SharedByteArray) }
+    }
+
+    #[inline(always)]
+    pub fn to_sba<'a>(self) -> Option<&'a mut SharedByteArray>
+    {
+        if self.is_shared_bytearray() { Some(self.as_sba()) } else { None }
+    }
+
     #[inline(always)]
     pub fn dict(p: *mut Dict) -> Value { Value::ptr_id(p as *const u8) }
 
@@ -553,6 +592,19 @@ impl Value
         if self.is_cell() { Some(self.as_cell()) } else { None }
     }
 
+    #[inline(always)]
+    pub fn weakref(p: *mut Value) -> Value { Value::ptr_id(p as *const u8) }
+
+    #[inline(always)]
+    pub fn is_weakref(self) -> bool { self.is_ptr_id(Tag::WeakRef) }
+
+    #[inline(always)]
+    pub fn as_weakref<'a>(self) -> &'a mut Value
+    {
+        debug_assert!(self.is_weakref());
+        unsafe { &mut *(self.heap_ptr() as *mut Value) }
+    }
+
     // Numbers
 
     #[inline(always)]
@@ -570,7 +622,7 @@ impl Value
     #[inline(always)]
     pub fn is_num(self) -> bool
     {
-        self.is_int64() || self.is_float64()
+        self.is_int64() || self.is_float64() || self.is_bigint()
     }
 
     #[inline(always)]
@@ -597,6 +649,16 @@ impl Value
         }
     }
 
+    /// Integer value of any size, copied out of the heap if it is a bigint
+    pub fn to_bignum(self) -> Option<BigNum>
+    {
+        if self.is_bigint() {
+            Some(self.as_bigint().to_num())
+        } else {
+            self.to_i64().map(BigNum::from_i64)
+        }
+    }
+
     /// Numeric value as a double, whatever the representation
     #[inline(always)]
     pub fn num_as_f64(self) -> f64
@@ -605,6 +667,7 @@ impl Value
 
         match self.to_i64() {
             Some(v) => v as f64,
+            None if self.is_bigint() => self.as_bigint().to_num().to_f64(),
             None => self.to_f64().unwrap(),
         }
     }
@@ -638,10 +701,13 @@ impl Value
                 Tag::Closure => Type::Closure,
                 Tag::Array => Type::Array,
                 Tag::ByteArray => Type::ByteArray,
+                Tag::SharedByteArray => Type::SharedByteArray,
                 Tag::Dict => Type::Dict,
                 Tag::Cell => Type::Cell,
+                Tag::WeakRef => Type::WeakRef,
                 Tag::Int64 => Type::Int64,
                 Tag::Float64 => Type::Float64,
+                Tag::BigInt => Type::BigInt,
                 tag => panic!("value points at a {:?} block", tag),
             },
 
@@ -687,6 +753,13 @@ fn slow_eq(a: Value, b: Value) -> bool
     }
 
     if a.is_num() && b.is_num() {
+        if a.is_bigint() || b.is_bigint() {
+            return match (a.to_bignum(), b.to_bignum()) {
+                (Some(a), Some(b)) => a == b,
+                _ => a.num_as_f64() == b.num_as_f64(),
+            };
+        }
+
         return match (a.to_i64(), b.to_i64()) {
             (Some(a), Some(b)) => a == b,
             _ => a.num_as_f64() == b.num_as_f64(),
@@ -706,6 +779,7 @@ impl fmt::Debug for Value
             Type::Bool => write!(f, "{}", self.as_bool()),
             Type::Int64 => write!(f, "{}", self.to_i64().unwrap()),
             Type::Float64 => write!(f, "{}", self.to_f64().unwrap()),
+            Type::BigInt => write!(f, "{}", self.as_bigint().to_num()),
             Type::String => write!(f, "{:?}", self.as_str()),
             Type::Fun => write!(f, "Fun({:?})", self.as_fun()),
             Type::Class => write!(f, "Class({:?})", self.as_class()),
@@ -806,9 +880,21 @@ macro_rules! unwrap_arr {
     ($val: expr) => { $crate::value::unwrap_val!(to_arr, "array", $val, "") };
 }
 
+// A bytearray whose bytes were moved to another actor can't be used
 macro_rules! unwrap_ba {
-    ($val: expr, $req: literal) => { $crate::value::unwrap_val!(to_ba, "byte array", $val, $req) };
-    ($val: expr) => { $crate::value::unwrap_val!(to_ba, "byte array", $val, "") };
+    ($val: expr, $req: literal) => {{
+        let ba = $crate::value::unwrap_val!(to_ba, "byte array", $val, $req);
+        if ba.is_detached() {
+            error!($req, "byte array was moved to another actor and can no longer be used")
+        }
+        ba
+    }};
+    ($val: expr) => { $crate::value::unwrap_ba!($val, "") };
+}
+
+macro_rules! unwrap_sba {
+    ($val: expr, $req: literal) => { $crate::value::unwrap_val!(to_sba, "shared byte array", $val, $req) };
+    ($val: expr) => { $crate::value::unwrap_val!(to_sba, "shared byte array", $val, "") };
 }
 
 macro_rules! unwrap_dict {
@@ -831,8 +917,8 @@ macro_rules! unwrap_fun {
 #[allow(unused_imports)]
 pub(crate) use {
     unwrap_arr, unwrap_ba, unwrap_bool, unwrap_clos, unwrap_dict, unwrap_f64, unwrap_fun,
-    unwrap_i32, unwrap_i64, unwrap_obj, unwrap_str, unwrap_u32, unwrap_u64, unwrap_u8,
-    unwrap_usize,
+    unwrap_i32, unwrap_i64, unwrap_obj, unwrap_sba, unwrap_str, unwrap_u32, unwrap_u64,
+    unwrap_u8, unwrap_usize,
 };
 
 #[cfg(test)]
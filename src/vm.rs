@@ -1,21 +1,29 @@
 use rustc_hash::FxHashMap as HashMap;
 use std::thread;
-use std::sync::{Arc, Weak, Mutex, mpsc};
-use std::time::Duration;
+use std::sync::{Arc, Weak, Mutex, MutexGuard, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::any::Any;
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
 use crate::dict::Dict;
 // Only the GC logging below formats numbers this way
 #[cfg(feature = "log_gc")]
 use crate::utils::thousands_sep;
 use crate::lexer::SrcPos;
-use crate::ast::{Program, FunId, ClassId, Class};
-use crate::alloc::{Alloc, Tag, HEADER_SIZE, INIT_SIZE, MSG_INIT_SIZE};
+use crate::ast::{Program, FunId, ClassId, Class, ERROR_ID, RANGE_ID, ACTOR_ERROR_ID};
+use crate::alloc::{header_of, set_remembered, Alloc, ExtTable, Tag, HEADER_SIZE, INIT_SIZE, MSG_INIT_SIZE, NURSERY_SIZE};
 use crate::object::Object;
 use crate::closure::Closure;
 use crate::array::Array;
 use crate::bytearray::ByteArray;
+use crate::bigint::{BigInt, BigNum, MAX_SHIFT_BITS};
 use crate::codegen::CompiledFun;
-use crate::gc::{undo_forwarding, Copier, StrTable, UndoLog};
+use crate::gc::{heap_histogram, undo_forwarding, Copier, GcStats, HeapHistogram, StrTable, UndoLog};
 use crate::host::*;
+use crate::permissions::{Permission, Permissions};
+use crate::timer::TimerService;
+use crate::sched::{Scheduler, Task, Wait, TIME_SLICE};
 use crate::str::Str;
 use crate::value::*;
 use std::mem::size_of;
@@ -31,6 +39,105 @@ use std::ops::{Add, Sub, Mul};
 /// the buffer without bound.
 const MSG_BACKLOG_LIMIT: usize = 64 * 1024 * 1024;
 
+/// Number of messages an actor's queue holds before senders have to wait
+const MSG_QUEUE_SIZE: usize = 1024;
+
+/// Number of frames an actor's call stack can hold when no limit is set.
+/// Deep enough for any reasonable recursion, while runaway recursion
+/// fails with an error long before it exhausts memory.
+const DEFAULT_MAX_STACK_DEPTH: usize = 100_000;
+
+/// Number of frames shown at each end of a stack trace too long to be
+/// shown in full, such as that of a stack overflow
+const TRACE_EDGE_FRAMES: usize = 10;
+
+/// Limits an actor runs under. Those set on the VM apply to every actor,
+/// and can be tightened for a single actor when it is spawned.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ActorLimits
+{
+    // Size in bytes the actor's heap may not grow past
+    pub max_heap: Option<usize>,
+
+    // Number of frames the call stack may not grow past
+    pub max_stack_depth: Option<usize>,
+}
+
+impl ActorLimits
+{
+    /// Limits for a child actor, with those asked for at spawn time taking
+    /// precedence where they are stricter than the ones inherited
+    pub fn restrict(self, other: ActorLimits) -> ActorLimits
+    {
+        let min = |a: Option<usize>, b: Option<usize>| match (a, b) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        ActorLimits {
+            max_heap: min(self.max_heap, other.max_heap),
+            max_stack_depth: min(self.max_stack_depth, other.max_stack_depth),
+        }
+    }
+}
+
+/// Error that ended an actor. Actors end by unwinding with this as the
+/// payload, and it is kept until the actor is joined.
+#[derive(Clone, Debug)]
+pub struct ActorFailure
+{
+    pub actor_id: u64,
+    pub message: String,
+    pub stack_trace: String,
+}
+
+impl ActorFailure
+{
+    /// Recover the failure from what an actor unwound with. Anything
+    /// else is a Rust panic, which the panic hook already printed.
+    fn from_panic(actor_id: u64, payload: Box<dyn Any + Send>) -> ActorFailure
+    {
+        let payload = match payload.downcast::<ActorFailure>() {
+            Ok(failure) => return *failure,
+            Err(payload) => payload,
+        };
+
+        let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        ActorFailure {
+            actor_id,
+            message: format!("actor {} terminated with an internal error: {}", actor_id, msg),
+            stack_trace: String::new(),
+        }
+    }
+
+    /// Bytes needed to allocate the failure as an object
+    pub fn alloc_size(&self) -> usize
+    {
+        Object::alloc_size(3) +
+        Str::alloc_size(self.message.len()) +
+        Str::alloc_size(self.stack_trace.len())
+    }
+
+    /// Allocate the failure as an ActorError object. This doesn't
+    /// collect, so enough space has to be available.
+    /// Note: the slots must match the field order of the ActorError class
+    pub fn to_value(&self, alloc: &mut Alloc) -> Value
+    {
+        let obj_val = Object::new(ACTOR_ERROR_ID, 3, alloc);
+        let message = Str::new(&self.message, alloc);
+        let stack_trace = Str::new(&self.stack_trace, alloc);
+
+        let obj = obj_val.as_obj();
+        obj.set(0, message);
+        obj.set(1, Value::fixnum(self.actor_id as i64));
+        obj.set(2, stack_trace);
+        obj_val
+    }
+}
+
 /// Instruction opcodes
 /// Note: commonly used upcodes should be in the [0, 127] range (one byte)
 ///       less frequently used opcodes can take multiple bytes if necessary.
@@ -165,6 +272,20 @@ pub enum Insn
     // Unconditional jump
     jump { target_ofs: i32 },
 
+    // Install an exception handler jumping to the catch clause
+    try_begin { target_ofs: i32 },
+
+    // Uninstall the innermost exception handler
+    try_end,
+
+    // Advance the iteration of a for-in loop. The iterable and a cursor
+    // are on top of the stack. Built-in iterables push the next value(s)
+    // and true, or jump when exhausted. Objects push false.
+    iter_next { num_vals: u8, target_ofs: i32 },
+
+    // Throw the value on top of the stack
+    throw,
+
     // Call a host function
     //call_host { host_fn: HostFn, argc: u8 },
 
@@ -188,6 +309,15 @@ pub enum Insn
     // Call a host method on a primitive, guarded on the type tag
     call_method_host { name: Value, argc: u8, type_tag: Type, host_fn: &'static HostFn },
 
+    // Calls in tail position, which work like the calls above, except that
+    // the callee's frame takes the place of the caller's. A ret follows each
+    // of them, for when the caller's frame has to stay.
+    tail_call { argc: u8 },
+    tail_call_direct { fun_id: FunId, argc: u8 },
+    tail_call_pc { entry_pc: u32, fun_id: FunId, num_locals: u16, argc: u8 },
+    tail_call_method { name: Value, argc: u8 },
+    tail_call_method_pc { name: Value, argc: u8, class_id: ClassId, entry_pc: u32, fun_id: FunId, num_locals: u16 },
+
     // Return
     ret,
 }
@@ -217,6 +347,10 @@ pub struct Message
     // allocator. The receiver uses this to make room in its own heap
     // before copying the message out.
     size: usize,
+
+    // Bytes of a bytearray moved to the receiver, which the bytearray in
+    // the message points to. They are freed if the message is never taken.
+    moved: Option<ExtTable>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -235,6 +369,23 @@ struct StackFrame
     ret_addr: usize,
 }
 
+/// Exception handler installed by a try block
+#[derive(Copy, Clone, Debug)]
+struct Handler
+{
+    // Address of the catch clause
+    catch_pc: usize,
+
+    // Number of stack frames when the handler was installed
+    num_frames: usize,
+
+    // Stack size when the handler was installed
+    stack_len: usize,
+
+    // Base pointer of the frame that installed the handler
+    bp: usize,
+}
+
 pub struct Actor
 {
     // Actor id
@@ -246,18 +397,48 @@ pub struct Actor
     // Parent VM
     pub vm: Arc<Mutex<VM>>,
 
-    // Private allocator
+    // Nursery, which new objects are allocated out of. Whatever survives
+    // a collection is promoted into the old space.
     pub alloc: Alloc,
 
+    // Old space, holding the objects that survived a collection. Only a
+    // full collection moves them.
+    old: Alloc,
+
+    // Blocks of the old space written to since the last collection, which
+    // may reference the nursery. A minor collection treats them as roots.
+    remembered: Vec<Value>,
+
     // Allocator for incoming messages
     pub msg_alloc: Arc<Mutex<Alloc>>,
 
     // Message queue receiver endpoint
     queue_rx: mpsc::Receiver<Message>,
 
-    // Spare allocator used as to-space for copying GC
+    // What other actors can see of this one, or change
+    status: Arc<ActorStatus>,
+
+    // Messages already received, which a selective receive passed over.
+    // These are taken before the queue, in the order they arrived.
+    skipped_msgs: VecDeque<Value>,
+
+    // Spare allocator used as to-space for full collections
     to_space: Option<Alloc>,
 
+    // Size in bytes the heap may not grow past
+    max_heap: Option<usize>,
+
+    // Number of frames the call stack may not grow past
+    max_stack_depth: usize,
+
+    // Bytes the heap may keep alive outside of itself, in external tables
+    // and shared bytes, before a collection is run to free those no
+    // longer in use
+    outside_limit: usize,
+
+    // What collections have done so far, for $vm_gc_stats()
+    gc_stats: GcStats,
+
     // Strings copied during the current copy, so that equal strings can
     // share one allocation. Forwarding pointers work by address, so
     // nothing else would deduplicate them.
@@ -279,38 +460,60 @@ pub struct Actor
     // List of stack frames (activation records)
     frames: Vec<StackFrame>,
 
+    // Exception handlers for the try blocks being executed
+    handlers: Vec<Handler>,
+
+    // Scheduler task running this actor, unless it is the main actor,
+    // which runs on the main thread
+    task: Option<Arc<Task>>,
+
+    // Whether the interpreter loop running is the outermost one of a
+    // scheduled actor, which is the only one that can be suspended
+    may_park: bool,
+
+    // Why the actor was suspended. This is kept while it is parked, so
+    // that the blocking operation made again once it is resumed can tell
+    // how long it has already waited.
+    pub(crate) wait: Option<Wait>,
+
+    // Where the suspended interpreter loop resumes, and its base pointer
+    suspended_at: Option<(usize, usize)>,
+
+    // Backward jumps left before the actor yields to the others
+    slice_left: u32,
+
     // Map of classes referenced by this actor
     classes: HashMap<ClassId, Class>,
 
     // Map of compiled functions
-    funs: HashMap<FunId, CompiledFun>,
+    pub(crate) funs: HashMap<FunId, CompiledFun>,
 
     // Array of compiled instructions
     pub(crate) insns: Vec<Insn>,
-}
 
-/// Why an integer operation produced no result
-fn int_op_error(insn: &str, divisor: i64) -> String
-{
-    if divisor == 0 {
-        format!("division by zero in {}", insn)
-    } else {
-        format!("integer overflow in {}", insn)
-    }
+    // Source position of each compiled instruction
+    pub(crate) insn_pos: Vec<SrcPos>,
 }
 
 /// Slow path of an arithmetic instruction: integers that don't fit a
-/// fixnum, floats, and mixed operands. `$checked` is the i64 operation
-/// and `$op` the float one.
+/// fixnum, floats, and mixed operands. `$checked` is the i64 operation,
+/// `$big` the one integers that overflow it are promoted to, which
+/// produces None when dividing by zero, and `$op` the float one.
 macro_rules! num_slow_path {
-    ($name: ident, $insn: literal, $checked: ident, $op: tt) => {
+    ($name: ident, $insn: literal, $checked: ident, $big: expr, $op: tt) => {
         #[cold]
         fn $name(&mut self, v0: Value, v1: Value) -> Result<Value, String>
         {
             if let (Some(a), Some(b)) = (v0.to_i64(), v1.to_i64()) {
-                return match a.$checked(b) {
-                    Some(r) => Ok(self.int64(r)),
-                    None => Err(int_op_error($insn, b)),
+                if let Some(r) = a.$checked(b) {
+                    return Ok(self.int64(r));
+                }
+            }
+
+            if let (Some(a), Some(b)) = (v0.to_bignum(), v1.to_bignum()) {
+                return match ($big)(&a, &b) {
+                    Some(r) => Ok(self.bigint(r)),
+                    None => Err(format!("division by zero in {}", $insn)),
                 };
             }
 
@@ -325,24 +528,31 @@ macro_rules! num_slow_path {
 }
 
 /// Slow path of an instruction that only accepts integers. `$op` is
-/// the operation, and produces None where it is undefined.
+/// the i64 operation and `$big` the one for integers of any size. Both
+/// produce None where the result is undefined or, for `$op`, too large.
 macro_rules! int_slow_path {
-    ($name: ident, $insn: literal, $op: expr) => {
+    ($name: ident, $insn: literal, $op: expr, $big: expr) => {
         #[cold]
         fn $name(&mut self, v0: Value, v1: Value) -> Result<Value, String>
         {
-            let a = unwrap_i64!(v0, $insn);
-            let b = unwrap_i64!(v1, $insn);
+            if let (Some(a), Some(b)) = (v0.to_i64(), v1.to_i64()) {
+                if let Some(r) = ($op)(a, b) {
+                    return Ok(self.int64(r));
+                }
+            }
 
-            match ($op)(a, b) {
-                Some(r) => Ok(self.int64(r)),
-                None => Err(int_op_error($insn, b)),
+            let a = unwrap_val!(to_bignum, "integer", v0, $insn);
+            let b = unwrap_val!(to_bignum, "integer", v1, $insn);
+
+            match ($big)(&a, &b) {
+                Some(r) => Ok(self.bigint(r)),
+                None => Err(format!("division by zero in {}", $insn)),
             }
         }
     }
 }
 
-/// Slow path of a comparison: boxed integers, floats and strings
+/// Slow path of a comparison: boxed integers, bigints, floats and strings
 macro_rules! cmp_slow_path {
     ($name: ident, $insn: literal, $op: tt) => {
         #[cold]
@@ -352,6 +562,12 @@ macro_rules! cmp_slow_path {
                 return Ok(a $op b);
             }
 
+            if v0.is_bigint() || v1.is_bigint() {
+                if let (Some(a), Some(b)) = (v0.to_bignum(), v1.to_bignum()) {
+                    return Ok(a $op b);
+                }
+            }
+
             if v0.is_num() && v1.is_num() {
                 return Ok(v0.num_as_f64() $op v1.num_as_f64());
             }
@@ -365,18 +581,86 @@ macro_rules! cmp_slow_path {
     }
 }
 
+/// Operands of a shift: an integer of any size, and a shift amount
+/// that has to be a non-negative Int64
+fn shift_operands(insn: &str, v0: Value, v1: Value) -> Result<(BigNum, usize), String>
+{
+    let num = match v0.to_bignum() {
+        Some(num) => num,
+        None => return Err(format!("expected integer value but got {:?}", v0)),
+    };
+
+    match v1.to_i64() {
+        Some(shift) if shift >= 0 => Ok((num, shift as usize)),
+        _ => Err(format!("invalid shift amount in {}: {:?}", insn, v1)),
+    }
+}
+
 cmp_slow_path!(cmp_lt, "less-than", <);
 cmp_slow_path!(cmp_le, "less-than-or-equal", <=);
 cmp_slow_path!(cmp_gt, "greater-than", >);
 cmp_slow_path!(cmp_ge, "greater-than-or-equal", >=);
 
+/// Forward the roots of an actor through a copier: its globals, its stack
+/// and frames, the values referenced by its instructions, and any extra
+/// roots given. This is a macro rather than a method because the copier
+/// borrows the actor's heaps while the roots are its other fields.
+macro_rules! forward_roots {
+    ($actor: expr, $copier: expr, $extra_roots: expr) => {{
+        // Global variables
+        for val in &mut $actor.globals {
+            *val = $copier.forward(*val);
+        }
+
+        // Values on the stack
+        for val in &mut $actor.stack {
+            *val = $copier.forward(*val);
+        }
+
+        // Messages passed over by a selective receive
+        for val in &mut $actor.skipped_msgs {
+            *val = $copier.forward(*val);
+        }
+
+        // Closures in the stack frames
+        for frame in &mut $actor.frames {
+            frame.fun = $copier.forward(frame.fun);
+        }
+
+        // Heap values referenced in instructions
+        for insn in &mut $actor.insns {
+            match insn {
+                Insn::push { val } |
+                Insn::get_field { field: val, .. } |
+                Insn::set_field { field: val, .. } |
+                Insn::call_method { name: val, .. } |
+                Insn::call_method_pc { name: val, .. } |
+                Insn::call_method_host { name: val, .. } |
+                Insn::tail_call_method { name: val, .. } |
+                Insn::tail_call_method_pc { name: val, .. } => {
+                    *val = $copier.forward(*val);
+                }
+
+                _ => {}
+            }
+        }
+
+        // Extra roots supplied by the user
+        for val in $extra_roots.iter_mut() {
+            **val = $copier.forward(**val);
+        }
+    }};
+}
+
 impl Actor
 {
+    /// Create an actor. The heap it is given, which may already hold its
+    /// function and globals, becomes its old space.
     pub fn new(
         actor_id: u64,
         parent_id: Option<u64>,
         vm: Arc<Mutex<VM>>,
-        alloc: Alloc,
+        old: Alloc,
         msg_alloc: Arc<Mutex<Alloc>>,
         queue_rx: mpsc::Receiver<Message>,
         globals: Vec<Value>,
@@ -386,28 +670,67 @@ impl Actor
             actor_id,
             parent_id,
             vm,
-            alloc,
+            alloc: Alloc::with_size(NURSERY_SIZE),
+            old,
+            remembered: Vec::default(),
             msg_alloc,
             queue_rx,
+            status: Arc::default(),
+            skipped_msgs: VecDeque::default(),
             globals,
             to_space: None,
+            max_heap: None,
+            max_stack_depth: DEFAULT_MAX_STACK_DEPTH,
+            outside_limit: INIT_SIZE,
+            gc_stats: GcStats::default(),
             str_table: StrTable::default(),
             undo_log: UndoLog::default(),
             actor_map: HashMap::default(),
             stack: Vec::default(),
             frames: Vec::default(),
+            handlers: Vec::default(),
+            task: None,
+            may_park: false,
+            wait: None,
+            suspended_at: None,
+            slice_left: TIME_SLICE,
             insns: Vec::default(),
+            insn_pos: Vec::default(),
             classes: HashMap::default(),
             funs: HashMap::default(),
         }
     }
 
+    /// Limit how large the heap can grow. A heap already larger than the
+    /// limit is left as it is, and runs out of memory when next collected.
+    pub fn set_max_heap(&mut self, max_heap: Option<usize>)
+    {
+        self.max_heap = max_heap;
+
+        if let Some(max_heap) = max_heap {
+            let new_size = std::cmp::max(max_heap, self.old.bytes_used());
+            if new_size < self.old.mem_size() {
+                self.old.shrink_to(new_size);
+            }
+
+            let nursery_size = std::cmp::max(self.nursery_size(), self.alloc.bytes_used());
+            if nursery_size < self.alloc.mem_size() {
+                self.alloc.shrink_to(nursery_size);
+            }
+        }
+    }
+
     /// Copy a received message out of the message allocator and into this
     /// actor's own heap. Doing this on receipt means nothing the actor can
     /// reach ever lives outside its own heap, so the message allocator is
     /// a plain transfer buffer that the GC never has to look at.
     fn take_msg(&mut self, msg: Message) -> Value
     {
+        // Released so that a sender seeing the queue has room also sees
+        // the message taken off it
+        self.status.num_queued.fetch_sub(1, Ordering::Release);
+        self.status.wake_senders();
+
         if !msg.msg.is_heap() {
             return msg.msg;
         }
@@ -434,47 +757,212 @@ impl Actor
         copier.run();
         self.str_table = str_table;
 
+        // The copy adopted the bytes of a moved bytearray into our heap
+        if let Some(table) = msg.moved {
+            table.into_raw();
+        }
+
         val
     }
 
+    /// Have the actor stop running until it is woken up, or until a
+    /// deadline passes, if it runs on the scheduler and can be suspended
+    /// here. The blocking operation that parks it returns a placeholder,
+    /// which the interpreter discards, and is made again once the actor
+    /// is resumed. Returns false if the caller has to block instead.
+    fn park(&mut self, deadline: Option<Instant>) -> bool
+    {
+        if !self.may_park {
+            return false;
+        }
+
+        self.wait = Some(Wait::Wake { deadline, progress: 0 });
+        true
+    }
+
+    /// Take the deadline a blocking operation parked the actor until,
+    /// now that the operation is made again, or else compute a new one
+    fn retry_deadline(&mut self, timeout: Duration) -> Instant
+    {
+        match self.wait.take() {
+            Some(Wait::Wake { deadline: Some(deadline), .. }) => deadline,
+            _ => Instant::now() + timeout,
+        }
+    }
+
+    /// Pause the actor for an amount of time
+    pub fn sleep(&mut self, duration: Duration)
+    {
+        let deadline = self.retry_deadline(duration);
+        let now = Instant::now();
+
+        if now < deadline && !self.park(Some(deadline)) {
+            thread::sleep(deadline - now);
+        }
+    }
+
     /// Receive a message from the message queue
     /// This will block until a message is available
     pub fn recv(&mut self) -> Value
+    {
+        self.wait = None;
+
+        match self.skipped_msgs.pop_front() {
+            Some(msg) => msg,
+            None => self.wait_msg(None).unwrap_or(Value::NIL),
+        }
+    }
+
+    /// Receive a message from the message queue, blocking for at most
+    /// a given amount of time. Returns None if no message came in time.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Value>
+    {
+        let deadline = self.retry_deadline(timeout);
+
+        match self.skipped_msgs.pop_front() {
+            Some(msg) => Some(msg),
+            None => self.wait_msg(Some(deadline)),
+        }
+    }
+
+    /// Receive the first message a predicate function returns true for,
+    /// blocking until there is one. The other messages are left for later
+    /// receives, in the order they arrived.
+    pub fn recv_match(&mut self, pred: Value) -> Result<Value, String>
+    {
+        // The predicate can allocate, so it is kept on the stack, where
+        // the collector can update it. Messages being looked at are kept
+        // with the skipped ones for the same reason.
+        let pred_idx = self.stack.len();
+        self.stack.push(pred);
+
+        // Messages already looked at before the actor was parked
+        // are not looked at again
+        let mut idx = match self.wait.take() {
+            Some(Wait::Wake { progress, .. }) => progress,
+            _ => 0,
+        };
+
+        loop {
+            while idx < self.skipped_msgs.len() {
+                let pred = self.stack[pred_idx];
+                let msg = self.skipped_msgs[idx];
+
+                let matched = self.call(pred, &[msg]);
+
+                if matched.is_true() {
+                    self.stack.truncate(pred_idx);
+                    return Ok(self.skipped_msgs.remove(idx).unwrap());
+                } else if !matched.is_false() {
+                    self.stack.truncate(pred_idx);
+                    return Err(format!("predicate should return a boolean, but returned {:?}", matched));
+                }
+
+                idx += 1;
+            }
+
+            match self.wait_msg(None) {
+                Some(msg) => self.skipped_msgs.push_back(msg),
+                None => {
+                    self.stack.truncate(pred_idx);
+                    self.wait = Some(Wait::Wake { deadline: None, progress: idx });
+                    return Ok(Value::NIL);
+                }
+            }
+        }
+    }
+
+    /// Wait for a message to come in, until a deadline if there is one.
+    /// Returns None if the deadline passed, or if the actor was parked.
+    fn wait_msg(&mut self, deadline: Option<Instant>) -> Option<Value>
     {
         use crate::window::poll_ui_msg;
 
-        // Call try_recv first, so that a message that is already waiting
+        // Poll first, so that a message that is already waiting
         // is taken before we block
-        if let Some(msg) = self.try_recv() {
-            return msg;
+        if let Some(msg) = self.poll_msg() {
+            return Some(msg);
         }
 
-        if self.actor_id != 0 {
-            let msg = self.queue_rx.recv().unwrap();
-            return self.take_msg(msg);
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) || self.park(deadline) {
+            return None;
         }
 
-        // Actor 0 (the main actor) may need to poll for UI events
         loop {
+            // Actor 0 (the main actor) may need to poll for UI events,
+            // so it only blocks for up to 8ms at a time
+            let mut wait = if self.actor_id == 0 { Some(Duration::from_millis(8)) } else { None };
+
+            if let Some(deadline) = deadline {
+                let time_left = deadline.saturating_duration_since(Instant::now());
+                wait = Some(wait.map_or(time_left, |wait| wait.min(time_left)));
+            }
+
+            let msg = match wait {
+                Some(wait) => self.queue_rx.recv_timeout(wait).ok(),
+                None => Some(self.queue_rx.recv().unwrap()),
+            };
+
+            if let Some(msg) = msg {
+                return Some(self.take_msg(msg));
+            }
+
             // Poll for UI messages. These are built directly in this
             // actor's heap, so they need no copying.
-            let ui_msg = poll_ui_msg(self);
-            if let Some(msg) = ui_msg {
-                return msg;
+            if self.actor_id == 0 {
+                if let Some(msg) = poll_ui_msg(self) {
+                    return Some(msg);
+                }
             }
 
-            // Block on the message queue for up to 8ms
-            let msg = self.queue_rx.recv_timeout(Duration::from_millis(8));
-
-            if let Ok(msg) = msg {
-                return self.take_msg(msg);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
             }
         }
     }
 
+    /// Number of messages waiting to be received
+    pub fn mailbox_len(&self) -> usize
+    {
+        self.status.num_queued.load(Ordering::Relaxed) + self.skipped_msgs.len()
+    }
+
+    /// End the actor if another actor has killed it. This is checked at
+    /// backward jumps and calls, so that any running code soon gets here.
+    #[inline(always)]
+    fn check_killed(&self, pc: usize)
+    {
+        if self.status.killed.load(Ordering::Relaxed) {
+            self.end_killed(pc);
+        }
+    }
+
+    /// End the actor because it was killed, at the instruction before `pc`.
+    /// This can't be caught, and isn't reported like an error, since it
+    /// is what the actor that killed this one wanted.
+    #[cold]
+    #[inline(never)]
+    fn end_killed(&self, pc: usize) -> !
+    {
+        panic::resume_unwind(Box::new(ActorFailure {
+            actor_id: self.actor_id,
+            message: format!("actor {} was killed", self.actor_id),
+            stack_trace: self.stack_trace(Some(pc - 1)),
+        }));
+    }
+
     /// Try to receive a message from the message queue
     /// This function will not block if no message is available
     pub fn try_recv(&mut self) -> Option<Value>
+    {
+        match self.skipped_msgs.pop_front() {
+            Some(msg) => Some(msg),
+            None => self.poll_msg(),
+        }
+    }
+
+    /// Take a message off the queue if there is one, without blocking
+    fn poll_msg(&mut self) -> Option<Value>
     {
         use crate::window::poll_ui_msg;
 
@@ -532,6 +1020,9 @@ impl Actor
                         if msg_alloc.mem_size() > 4 * MSG_BACKLOG_LIMIT {
                             msg_alloc.shrink_to(MSG_INIT_SIZE);
                         }
+
+                        // Senders waiting on the backlog can go on
+                        self.status.wake_senders();
                     }
                 }
             }
@@ -541,47 +1032,103 @@ impl Actor
         None
     }
 
-    /// Send a message to another actor
-    pub fn send(&mut self, actor_id: u64, msg: Value) -> Result<(), ()>
+    /// Bytes in use in both the nursery and the old space, counting the
+    /// external tables, which a copy of the heap would bring into it
+    fn heap_bytes(&self) -> usize
     {
-        // Lookup the queue endpoint in our local cache
-        let mut actor_tx = self.actor_map.get(&actor_id);
+        self.alloc.bytes_used() + self.alloc.ext_bytes() +
+        self.old.bytes_used() + self.old.ext_bytes()
+    }
 
-        if actor_tx.is_none() {
-            let vm = self.vm.lock().unwrap();
+    /// Copy a value out of this actor's heap into an allocator of its
+    /// own, so that it can be kept for as long as needed. Values that
+    /// are not heap-allocated need no allocator.
+    pub fn copy_out(&mut self, val: Value) -> (Value, Option<Alloc>)
+    {
+        if !val.is_heap() {
+            return (val, None);
+        }
 
-            let tx = vm.actor_txs.get(&actor_id);
+        // The copy cannot come out larger than our heap, and whatever
+        // it leaves unused is given back below
+        // Bytes kept outside of the heap are copied into it like any other
+        let heap_bytes = self.heap_bytes();
+        let mut alloc = Alloc::with_size(std::cmp::max(heap_bytes, 4096));
 
-            if tx.is_none() {
-                return Err(());
-            }
+        let mut str_table = std::mem::take(&mut self.str_table);
+        let mut undo_log = std::mem::take(&mut self.undo_log);
+
+        let mut copier = Copier::with_undo(
+            &mut alloc,
+            &mut str_table,
+            &mut undo_log
+        );
+        let val = copier.forward(val);
+        copier.run();
 
-            self.actor_map.insert(actor_id, tx.unwrap().clone());
-            actor_tx = self.actor_map.get(&actor_id);
+        undo_forwarding(&mut undo_log);
+        self.str_table = str_table;
+        self.undo_log = undo_log;
+
+        let size = alloc.bytes_used();
+        alloc.shrink_to(size);
+
+        (val, Some(alloc))
+    }
+
+    /// Get the handle used to send messages to another actor, caching
+    /// it so that the VM doesn't have to be locked on every send
+    fn actor_tx(&mut self, actor_id: u64) -> Option<ActorTx>
+    {
+        if let Some(actor_tx) = self.actor_map.get(&actor_id) {
+            return Some(actor_tx.clone());
         }
 
-        // Take owned handles to the receiver so that we stop borrowing
-        // ourselves, since the copy below needs our scratch buffers
-        // Note: upgrading can fail if the receiving thread panics
-        let actor_tx = actor_tx.unwrap();
-        let sender = actor_tx.sender.clone();
+        let actor_tx = self.vm.lock().unwrap().actor_txs.get(&actor_id)?.clone();
+        self.actor_map.insert(actor_id, actor_tx.clone());
+        Some(actor_tx)
+    }
 
-        // Copy the message using the receiver's message allocator
-        let alloc_rc = match actor_tx.msg_alloc.upgrade() {
-            Some(rc) => rc,
-            None => return Err(()),
-        };
-        // Wait if too many undrained messages have piled up. The receiver
-        // resets the buffer once it has copied every message out, so this
-        // makes progress as long as it is still running. Give up eventually
-        // rather than spinning forever if it is not.
+    /// Lock the message allocator of the actor a message is sent to, once
+    /// its mailbox has room for the message. A scheduled actor is parked
+    /// until the receiver takes messages out of it, in which case None is
+    /// returned, and the send is made again once the actor is resumed.
+    fn lock_mailbox<'a>(&mut self, actor_tx: &ActorTx, alloc_rc: &'a Mutex<Alloc>) -> Result<Option<MutexGuard<'a, Alloc>>, ()>
+    {
+        self.wait = None;
+
+        // Actors that can't be parked, such as the main actor, wait here
+        // if too many undrained messages have piled up, and block on the
+        // queue if it is full. The receiver resets the buffer once it has
+        // copied every message out, so this makes progress as long as it
+        // is still running. Give up eventually rather than spinning
+        // forever if it is not.
         let mut attempts = 0;
 
-        let mut msg_alloc = loop {
+        loop {
             let msg_alloc = alloc_rc.lock().unwrap();
 
+            if actor_tx.has_room(&msg_alloc) {
+                return Ok(Some(msg_alloc));
+            }
+
+            if self.may_park {
+                // Registered before looking again, so that the receiver
+                // can't make room in between without waking us up
+                if !actor_tx.status.block_sender(self.task.as_ref().unwrap()) {
+                    return Err(());
+                }
+
+                if actor_tx.has_room(&msg_alloc) {
+                    return Ok(Some(msg_alloc));
+                }
+
+                self.park(None);
+                return Ok(None);
+            }
+
             if msg_alloc.bytes_used() <= MSG_BACKLOG_LIMIT {
-                break msg_alloc;
+                return Ok(Some(msg_alloc));
             }
 
             drop(msg_alloc);
@@ -592,6 +1139,26 @@ impl Actor
             }
 
             std::thread::yield_now();
+        }
+    }
+
+    /// Send a message to another actor
+    pub fn send(&mut self, actor_id: u64, msg: Value) -> Result<(), ()>
+    {
+        // Take owned handles to the receiver so that we stop borrowing
+        // ourselves, since the copy below needs our scratch buffers
+        let actor_tx = self.actor_tx(actor_id).ok_or(())?;
+
+        // Copy the message using the receiver's message allocator
+        // Note: upgrading can fail if the receiving thread panics
+        let alloc_rc = match actor_tx.msg_alloc.upgrade() {
+            Some(rc) => rc,
+            None => return Err(()),
+        };
+
+        let mut msg_alloc = match self.lock_mailbox(&actor_tx, &alloc_rc)? {
+            Some(msg_alloc) => msg_alloc,
+            None => return Ok(()),
         };
 
         let bytes_before = msg_alloc.bytes_used();
@@ -619,13 +1186,77 @@ impl Actor
         // Queue the message while still holding the message allocator
         // lock. That way the receiver never observes an empty queue while
         // a message is sitting unqueued in its buffer, which is what makes
-        // it safe for the receiver to reset the buffer.
-        let res = sender.send(Message { sender: self.actor_id, msg, size });
+        // it safe for the receiver to reset the buffer. It also keeps other
+        // senders from taking the room the queue was found to have.
+        let res = actor_tx.send(Message { sender: self.actor_id, msg, size, moved: None });
+        drop(msg_alloc);
+
+        res
+    }
+
+    /// Send a bytearray to another actor by moving its bytes there rather
+    /// than copying them. The bytearray is left detached, and can no longer
+    /// be used. Bytes in our heap are first copied out of it once, so that
+    /// they can be passed on from then on without copying.
+    pub fn send_move(&mut self, actor_id: u64, ba: Value) -> Result<(), ()>
+    {
+        let actor_tx = self.actor_tx(actor_id).ok_or(())?;
+        let alloc_rc = actor_tx.msg_alloc.upgrade().ok_or(())?;
+
+        // Wait for room before the bytes are taken out of the bytearray
+        let mut msg_alloc = match self.lock_mailbox(&actor_tx, &alloc_rc)? {
+            Some(msg_alloc) => msg_alloc,
+            None => return Ok(()),
+        };
+
+        let ba = ba.as_ba();
+        let num_bytes = ba.num_bytes();
+        let bytes = ba.bytes as *mut u8;
+
+        let is_ext = header_of(bytes).tag() == Tag::ExtBytes;
+        let in_nursery = is_ext && self.alloc.release_ext_table(bytes);
+        let owned = in_nursery || (is_ext && self.old.release_ext_table(bytes));
+
+        let table = if owned {
+            ExtTable::from_raw(bytes)
+        } else {
+            let table = ExtTable::new(num_bytes);
+            unsafe { std::ptr::copy_nonoverlapping(bytes, table.as_slice_ptr() as *mut u8, num_bytes) };
+            table
+        };
+
+        // Only the bytearray itself goes in the message allocator
+        let bytes_before = msg_alloc.bytes_used();
+        let msg = ByteArray::with_ext_table(&table, num_bytes, &mut msg_alloc);
+        let size = msg_alloc.bytes_used() - bytes_before;
+
+        // Queue the message while holding the allocator lock, as send does
+        let res = actor_tx.send_or_return(Message { sender: self.actor_id, msg, size, moved: Some(table) });
         drop(msg_alloc);
 
         match res {
-            Ok(_) => Ok(()),
-            Err(_) => Err(()),
+            // The bytearray only gives up its bytes once they were sent
+            Ok(()) => {
+                ba.detach();
+                Ok(())
+            }
+
+            // The receiver is gone, so the bytes we owned are taken back,
+            // and the bytearray is left as it was
+            Err(msg) => {
+                let table = msg.moved.unwrap();
+
+                if owned {
+                    let p = table.into_raw();
+                    if in_nursery {
+                        self.alloc.adopt_ext_table(p);
+                    } else {
+                        self.old.adopt_ext_table(p);
+                    }
+                }
+
+                Err(())
+            }
         }
     }
 
@@ -637,6 +1268,13 @@ impl Actor
         vm.prog.funs[&fun_id].params.len()
     }
 
+    /// Get the name of a function
+    pub fn get_fun_name(&self, fun_id: FunId) -> String
+    {
+        let vm = self.vm.lock().unwrap();
+        vm.prog.funs[&fun_id].name.clone()
+    }
+
     /// Get a compiled function entry for a given function id
     /// Compile a function, if it has not been compiled yet.
     ///
@@ -766,15 +1404,18 @@ impl Actor
     /// Set the value of an object field on an object the runtime itself
     /// allocated, e.g. a UI event. The class and its fields are known here,
     /// so a failure means the runtime is at fault, not the running program.
-    pub fn set_field(&mut self, obj: Value, field_name: &str, val: Value)
+    pub fn set_field(&mut self, obj_val: Value, field_name: &str, val: Value)
     {
-        let obj = match obj.to_obj() {
+        let obj = match obj_val.to_obj() {
             Some(obj) => obj,
-            None => panic!("internal error: set_field on non-object value {:?}", obj)
+            None => panic!("internal error: set_field on non-object value {:?}", obj_val)
         };
 
         match self.get_slot_idx(obj.class_id, field_name) {
-            Some(slot_idx) => obj.set(slot_idx, val),
+            Some(slot_idx) => {
+                obj.set(slot_idx, val);
+                self.write_barrier(obj_val, val);
+            }
             None => panic!(
                 "internal error: no field `{}` on class `{}`",
                 field_name,
@@ -797,39 +1438,248 @@ impl Actor
         Str::new(str_const, &mut self.alloc)
     }
 
-    /// Perform a garbage collection cycle
+    /// Perform a garbage collection cycle. Only the nursery is collected,
+    /// unless the old space has no room left for what survives it.
     pub fn gc_collect(&mut self, bytes_needed: usize, extra_roots: &mut [&mut Value])
     {
-        // Collections can happen many times a second, so the reporting here,
-        // its argument formatting, and the timing it needs all compile out
-        // unless the `log_gc` feature is enabled.
+        // Checking before the cycle as well catches missing write barriers,
+        // which leave old blocks referencing the nursery unremembered
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+
+        if self.needs_full_gc(bytes_needed) {
+            self.gc_full(bytes_needed, extra_roots);
+        } else {
+            self.gc_minor(extra_roots);
+        }
+
+        self.reset_nursery(bytes_needed);
+
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+    }
+
+    /// Perform a garbage collection cycle over both the nursery and the
+    /// old space, whether or not the old space is full
+    pub fn gc_collect_full(&mut self, extra_roots: &mut [&mut Value])
+    {
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+
+        self.gc_full(0, extra_roots);
+        self.reset_nursery(0);
+
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+    }
+
+    /// What the collections of this actor have done so far
+    pub fn gc_stats(&self) -> &GcStats
+    {
+        &self.gc_stats
+    }
+
+    /// Size of the nursery and the old space together
+    pub fn heap_size(&self) -> usize
+    {
+        self.alloc.mem_size() + self.old.mem_size()
+    }
+
+    /// Bytes left free in the nursery and the old space
+    pub fn heap_free(&self) -> usize
+    {
+        self.alloc.bytes_free() + self.old.bytes_free()
+    }
+
+    /// Bytes of the large objects kept outside of the heap
+    pub fn large_object_bytes(&self) -> usize
+    {
+        self.alloc.ext_bytes() + self.old.ext_bytes()
+    }
+
+    /// The old space, which holds everything live right after a full
+    /// collection, when the nursery is empty
+    pub fn old_space(&self) -> &Alloc
+    {
+        &self.old
+    }
+
+    /// The roots of the heap, grouped by what holds them. These are what
+    /// forward_roots! forwards, for the heap snapshot to show.
+    pub fn heap_roots(&self) -> Vec<(&'static str, Vec<Value>)>
+    {
+        let consts = self.insns.iter().filter_map(|insn| match insn {
+            Insn::push { val } |
+            Insn::get_field { field: val, .. } |
+            Insn::set_field { field: val, .. } |
+            Insn::call_method { name: val, .. } |
+            Insn::call_method_pc { name: val, .. } |
+            Insn::call_method_host { name: val, .. } |
+            Insn::tail_call_method { name: val, .. } |
+            Insn::tail_call_method_pc { name: val, .. } => Some(*val),
+            _ => None,
+        });
+
+        vec![
+            ("(Globals)", self.globals.clone()),
+            ("(Stack)", self.stack.clone()),
+            ("(Skipped messages)", self.skipped_msgs.iter().copied().collect()),
+            ("(Stack frames)", self.frames.iter().map(|frame| frame.fun).collect()),
+            ("(Instruction constants)", consts.collect()),
+        ]
+    }
+
+    /// Collect the heap, then count what is live in it by kind and class
+    pub fn heap_histogram(&mut self) -> HeapHistogram
+    {
+        self.gc_collect_full(&mut []);
+        heap_histogram(&self.old)
+    }
+
+    /// Whether a collection has to include the old space. That is the case
+    /// when what survives the nursery may not fit in it, when the old space
+    /// keeps too much alive outside of itself, which only a full collection
+    /// can free, or when a limited heap has to find out whether it is out
+    /// of memory. Large objects that die young are freed by a minor
+    /// collection, like anything else in the nursery.
+    fn needs_full_gc(&self, bytes_needed: usize) -> bool
+    {
+        if self.old.bytes_free() < self.alloc.bytes_used() {
+            return true;
+        }
+
+        if self.old.outside_bytes() > self.outside_limit {
+            return true;
+        }
+
+        // What is kept outside of the heap counts against its limit too
+        match self.max_heap {
+            Some(max_heap) => self.old.bytes_used() + self.outside_bytes() + bytes_needed > max_heap,
+            None => false,
+        }
+    }
+
+    /// Size to give the nursery. A limited heap gets a smaller one, so
+    /// that the nursery doesn't take up most of what the heap may use.
+    fn nursery_size(&self) -> usize
+    {
+        match self.max_heap {
+            Some(max_heap) => std::cmp::min(NURSERY_SIZE, max_heap / 4),
+            None => NURSERY_SIZE,
+        }
+    }
+
+    /// Size of what the nursery and the old space keep alive outside of
+    /// themselves, in external tables and shared bytes
+    fn outside_bytes(&self) -> usize
+    {
+        self.alloc.outside_bytes() + self.old.outside_bytes()
+    }
+
+    /// Promote everything live in the nursery into the old space. Only the
+    /// roots and the remembered set can reference the nursery, so the rest
+    /// of the old space is left alone.
+    fn gc_minor(&mut self, extra_roots: &mut [&mut Value])
+    {
         #[cfg(feature = "log_gc")]
-        println!("Running GC cycle, {} bytes free", self.alloc.bytes_free());
+        println!(
+            "Running minor GC cycle, {} bytes in the nursery, {} blocks remembered",
+            thousands_sep(self.alloc.bytes_used()),
+            thousands_sep(self.remembered.len()),
+        );
+
+        let start_time = Instant::now();
+        let old_bytes = self.old.bytes_used();
+
+        // The old space was checked to have room for the whole nursery,
+        // so the promotion cannot run out of space
+        let mut str_table = std::mem::take(&mut self.str_table);
+        let promoted = {
+            let mut copier = Copier::for_nursery(&mut self.old, &mut str_table, &self.alloc);
+            forward_roots!(self, copier, extra_roots);
+
+            // Old blocks written to since the last cycle
+            for block in &self.remembered {
+                copier.scan_remembered(*block);
+            }
+
+            copier.run();
+
+            #[cfg(feature = "log_gc")]
+            println!(
+                "GC promoted {} blocks, {} bytes",
+                thousands_sep(copier.num_blocks()),
+                thousands_sep(self.old.bytes_used() - old_bytes),
+            );
+
+            *copier.blocks_by_tag()
+        };
+        self.str_table = str_table;
+
+        // Nothing in the old space references the nursery any more
+        for block in self.remembered.drain(..) {
+            set_remembered(block.heap_ptr(), false);
+        }
+
+        // The old space adopted the external tables of the bytearrays it
+        // promoted, and the others are gone
+        self.alloc.free_ext_tables_except(&self.old);
 
         #[cfg(feature = "log_gc")]
-        let start_time = crate::host::get_time_ms();
+        println!(
+            "Large objects now {} bytes outside of the heap",
+            thousands_sep(self.old.ext_bytes()),
+        );
+
+        let pause = start_time.elapsed();
+        self.gc_stats.record_minor(pause, self.old.bytes_used() - old_bytes, &promoted);
+
+        #[cfg(feature = "log_gc")]
+        println!("GC time: {} ms", pause.as_millis());
+    }
+
+    /// Copy everything live in the nursery and the old space into a new
+    /// old space, which is sized from the live data
+    fn gc_full(&mut self, bytes_needed: usize, extra_roots: &mut [&mut Value])
+    {
+        // Collections can happen many times a second, so the reporting here
+        // and its argument formatting compile out unless the `log_gc`
+        // feature is enabled. The timing is kept for $vm_gc_stats().
+        #[cfg(feature = "log_gc")]
+        println!(
+            "Running full GC cycle, {} bytes in the old space",
+            thousands_sep(self.old.bytes_used()),
+        );
+
+        let start_time = Instant::now();
 
         // How big to make the to-space. A block costs its header plus its
         // own rounded size and nothing else, and each one is copied at
         // most once, so the copy can never come out larger than what it
         // copies. That makes what the copy needs exactly the bytes in use
-        // plus what the allocation waiting on us wants.
+        // in both spaces.
         //
-        // The heap is also sized from the live data below, and it has to
-        // be grown for that here rather than after the fact, so take
-        // whichever of the two is larger.
-        let used_bytes = self.alloc.bytes_used();
-        let to_space_bytes = std::cmp::max(
-            ((used_bytes + bytes_needed) * 3) / 2,
+        // The old space is also sized from the live data below, with room
+        // to promote a full nursery into, and it has to be grown for that
+        // here rather than after the fact, so take whichever is larger.
+        let used_bytes = self.old.bytes_used() + self.alloc.bytes_used();
+        let mut to_space_bytes = std::cmp::max(
+            ((used_bytes + self.nursery_size()) * 3) / 2,
             INIT_SIZE,
         );
 
+        // A limited heap is never sized past its limit below, so the
+        // to-space doesn't need to be either, as long as the copy fits
+        if let Some(max_heap) = self.max_heap {
+            to_space_bytes = std::cmp::max(std::cmp::min(to_space_bytes, max_heap), used_bytes);
+        }
+
         // Get an allocator to copy the data into. It still holds whatever
-        // it had when it was last a heap, and its allocation point is the
-        // high water mark of that: everything past it was never written
-        // and is still zero. The copy overwrites every byte of what it
-        // allocates, so only the rest has to be cleared, once we know how
-        // far the copy got.
+        // it had when it was last the old space, and its allocation point
+        // is the high water mark of that: everything past it was never
+        // written and is still zero. The copy overwrites every byte of what
+        // it allocates, so only the rest has to be cleared, once we know
+        // how far the copy got.
         let mut dst_alloc = match self.to_space.take() {
             Some(alloc) => alloc,
             None => Alloc::new()
@@ -846,50 +1696,15 @@ impl Actor
         dst_alloc.grow(to_space_bytes);
 
         // Copy the roots into the new allocator, then everything they
-        // reach. The from-space is discarded below, so the copier is free
-        // to leave forwarding addresses behind in it.
+        // reach. Both spaces are discarded below, so the copier is free
+        // to leave forwarding addresses behind in them.
         //
         // Roots are updated in place as they are forwarded, so unlike a
         // copy through a translation map this needs no second pass.
         let mut str_table = std::mem::take(&mut self.str_table);
-        {
+        let copied = {
             let mut copier = Copier::new(&mut dst_alloc, &mut str_table);
-
-            // Global variables
-            for val in &mut self.globals {
-                *val = copier.forward(*val);
-            }
-
-            // Values on the stack
-            for val in &mut self.stack {
-                *val = copier.forward(*val);
-            }
-
-            // Closures in the stack frames
-            for frame in &mut self.frames {
-                frame.fun = copier.forward(frame.fun);
-            }
-
-            // Heap values referenced in instructions
-            for insn in &mut self.insns {
-                match insn {
-                    Insn::push { val } |
-                    Insn::get_field { field: val, .. } |
-                    Insn::set_field { field: val, .. } |
-                    Insn::call_method { name: val, .. } |
-                    Insn::call_method_pc { name: val, .. } |
-                    Insn::call_method_host { name: val, .. } => {
-                        *val = copier.forward(*val);
-                    }
-
-                    _ => {}
-                }
-            }
-
-            // Extra roots supplied by the user
-            for val in extra_roots {
-                **val = copier.forward(**val);
-            }
+            forward_roots!(self, copier, extra_roots);
 
             // Sized as above, this cannot run out of space
             copier.run();
@@ -900,43 +1715,116 @@ impl Actor
                 thousands_sep(copier.num_blocks()),
                 thousands_sep(dst_alloc.bytes_used()),
             );
-        }
+
+            *copier.blocks_by_tag()
+        };
         self.str_table = str_table;
 
-        // Size the heap from the live data we just measured, rather than
-        // guessing from the old heap size. This lets the heap shrink again
+        // The blocks the remembered set holds are in the old space being
+        // discarded, and their copies reference nothing in the nursery
+        self.remembered.clear();
+
+        // Size the old space from the live data we just measured, rather
+        // than guessing from its old size. This lets the heap shrink again
         // when a program's live set gets smaller.
         let live_bytes = dst_alloc.bytes_used();
-        let new_mem_size = std::cmp::max(
-            ((live_bytes + bytes_needed) * 3) / 2,
+        let mut new_mem_size = std::cmp::max(
+            ((live_bytes + self.nursery_size()) * 3) / 2,
             INIT_SIZE,
         );
+
+        // Stay within the heap limit, if there is one. What is live
+        // has to fit even if it doesn't, so that the error can be
+        // reported once the collection is complete.
+        if let Some(max_heap) = self.max_heap {
+            new_mem_size = std::cmp::max(std::cmp::min(new_mem_size, max_heap), live_bytes);
+        }
         dst_alloc.shrink_to(new_mem_size);
 
-        // Clear what the copy did not overwrite, so that the mutator
-        // still allocates out of zeroed memory. Shrinking above released
-        // its pages, which come back zeroed, so only what is left of the
-        // old high water mark is worth touching.
+        // Clear what the copy did not overwrite, so that promotions still
+        // land in zeroed memory. Shrinking above released its pages, which
+        // come back zeroed, so only what is left of the old high water mark
+        // is worth touching.
         dst_alloc.zero_up_to(dirty_bytes);
 
         #[cfg(feature = "log_gc")]
         println!(
-            "Heap size now {} bytes ({}% free)",
+            "Old space size now {} bytes ({}% free)",
             thousands_sep(dst_alloc.mem_size()),
             100 * dst_alloc.bytes_free() / dst_alloc.mem_size(),
         );
 
-        // Swap the old and new allocators. The from-space is left as it
-        // is: keeping its allocation point is what tells the next cycle
-        // how much of it holds stale bytes.
-        std::mem::swap(&mut self.alloc, &mut dst_alloc);
+        // Swap the old space for the new one. The previous old space is
+        // left as it is: keeping its allocation point is what tells the
+        // next cycle how much of it holds stale bytes.
+        std::mem::swap(&mut self.old, &mut dst_alloc);
+
+        // Free the external tables of the bytearrays that are gone, and let
+        // go of the shared bytes. Then let the heap take on twice as many
+        // bytes outside of itself again before the next cycle.
+        dst_alloc.free_ext_tables_except(&self.old);
+        self.alloc.free_ext_tables_except(&self.old);
+        dst_alloc.release_shared();
+        self.outside_limit = std::cmp::max(2 * self.old.outside_bytes(), INIT_SIZE);
+
         self.to_space = Some(dst_alloc);
 
-        #[cfg(feature = "verify_gc")]
-        crate::gc::verify_heap(&self.alloc);
+        #[cfg(feature = "log_gc")]
+        println!(
+            "Large objects now {} bytes outside of the heap",
+            thousands_sep(self.old.ext_bytes()),
+        );
+
+        let pause = start_time.elapsed();
+        self.gc_stats.record_full(pause, live_bytes, &copied);
 
         #[cfg(feature = "log_gc")]
-        println!("GC time: {} ms", crate::host::get_time_ms() - start_time);
+        println!("GC time: {} ms", pause.as_millis());
+
+        if let Some(max_heap) = self.max_heap {
+            let live_bytes = live_bytes + self.outside_bytes();
+            if live_bytes + bytes_needed > max_heap {
+                self.out_of_memory(live_bytes, bytes_needed, max_heap);
+            }
+        }
+    }
+
+    /// Empty the nursery once everything live in it was copied out, and
+    /// size it so that the allocation waiting on the collection fits
+    fn reset_nursery(&mut self, bytes_needed: usize)
+    {
+        // The mutator allocates out of zeroed memory, so what was used of
+        // the nursery has to be cleared before it is allocated from again
+        let dirty_bytes = self.alloc.bytes_used();
+        self.alloc.reset();
+
+        let new_size = std::cmp::max(self.nursery_size(), bytes_needed);
+
+        if new_size > self.alloc.mem_size() {
+            // A replaced reservation is freshly mapped and already zeroed
+            self.alloc.grow_reserve(new_size);
+            let dirty_bytes = std::cmp::min(dirty_bytes, self.alloc.mem_size());
+            self.alloc.grow(new_size);
+            self.alloc.zero_up_to(dirty_bytes);
+        } else {
+            // Shrinking releases pages, which come back zeroed
+            self.alloc.shrink_to(new_size);
+            self.alloc.zero_up_to(dirty_bytes);
+        }
+    }
+
+    /// End the actor because its heap would have to grow past its limit.
+    /// Unlike other runtime errors, this cannot be caught by the actor
+    /// itself, since handling it would need memory there is none of.
+    #[cold]
+    #[inline(never)]
+    fn out_of_memory(&self, live_bytes: usize, bytes_needed: usize, max_heap: usize) -> !
+    {
+        self.report_error("", &format!(
+            "actor {} ran out of memory: {} bytes are live and {} more were needed, \
+            but the heap is limited to {} bytes",
+            self.actor_id, live_bytes, bytes_needed, max_heap
+        ), None);
     }
 
     /// Ensure that at least bytes_needed of free space are available in the
@@ -946,13 +1834,64 @@ impl Actor
         // Add some extra bytes for alignment
         let bytes_needed = bytes_needed + 16;
 
-        if self.alloc.bytes_free() >= bytes_needed {
+        if self.alloc.bytes_free() >= bytes_needed && self.outside_bytes() <= self.outside_limit {
             return;
         }
 
         self.gc_collect(bytes_needed, extra_roots);
     }
 
+    /// Ensure that bytes_needed more can be kept outside of the heap, in an
+    /// external table or in shared bytes, without going over the heap
+    /// limit. If they don't fit, perform a full GC, which ends the actor
+    /// with an out of memory error if they still don't. This is checked
+    /// before the bytes are allocated, since there may be more of them
+    /// than the system can allocate at all.
+    pub fn outside_check(&mut self, bytes_needed: usize, extra_roots: &mut [&mut Value])
+    {
+        let max_heap = match self.max_heap {
+            Some(max_heap) => max_heap,
+            None => return,
+        };
+
+        if self.old.bytes_used() + self.alloc.bytes_used() + self.outside_bytes() + bytes_needed <= max_heap {
+            return;
+        }
+
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+
+        self.gc_full(bytes_needed, extra_roots);
+        self.reset_nursery(0);
+
+        #[cfg(feature = "verify_gc")]
+        crate::gc::verify_heap(&self.old, &self.alloc);
+    }
+
+    /// Write barrier, to call after storing a value into a heap block. A
+    /// block of the old space that comes to reference the nursery is added
+    /// to the remembered set, so that minor collections see the reference.
+    #[inline(always)]
+    pub fn write_barrier(&mut self, block: Value, val: Value)
+    {
+        if val.is_heap() && self.alloc.contains(val.heap_ptr()) {
+            self.remember(block);
+        }
+    }
+
+    /// Add a block to the remembered set if it is in the old space. This
+    /// is the barrier for changes that may allocate, such as an array
+    /// growing its table, which the new table then lands in the nursery.
+    pub fn remember(&mut self, block: Value)
+    {
+        let p = block.heap_ptr();
+
+        if self.old.contains(p) && !header_of(p).is_remembered() {
+            set_remembered(p, true);
+            self.remembered.push(block);
+        }
+    }
+
     /// Wrap an integer in a value, boxing it if it is too large to be
     /// a fixnum. Boxing allocates, so this may collect.
     #[inline(always)]
@@ -972,6 +1911,18 @@ impl Actor
         self.alloc.heap_int64(val)
     }
 
+    /// Wrap an integer of any size in a value. Only integers too large
+    /// for an Int64 become bigints. This may collect.
+    pub fn bigint(&mut self, num: BigNum) -> Value
+    {
+        if let Some(val) = num.to_i64() {
+            return self.int64(val);
+        }
+
+        self.gc_check(BigInt::alloc_size(num.num_bits().div_ceil(32)), &mut []);
+        BigInt::from_num(&num, &mut self.alloc)
+    }
+
     /// Wrap a double in a value, boxing it if it has no inline encoding
     #[inline(always)]
     pub fn float64(&mut self, val: f64) -> Value
@@ -996,10 +1947,13 @@ impl Actor
     fn add_slow(&mut self, mut v0: Value, mut v1: Value) -> Result<Value, String>
     {
         if let (Some(a), Some(b)) = (v0.to_i64(), v1.to_i64()) {
-            return match a.checked_add(b) {
-                Some(r) => Ok(self.int64(r)),
-                None => Err(int_op_error("add", b)),
-            };
+            if let Some(r) = a.checked_add(b) {
+                return Ok(self.int64(r));
+            }
+        }
+
+        if let (Some(a), Some(b)) = (v0.to_bignum(), v1.to_bignum()) {
+            return Ok(self.bigint(a.add(&b)));
         }
 
         if v0.is_num() && v1.is_num() {
@@ -1032,26 +1986,42 @@ impl Actor
         Err(format!("unsupported operand types for div: {:?} and {:?}", v0, v1))
     }
 
-    num_slow_path!(sub_slow, "sub", checked_sub, -);
-    num_slow_path!(mul_slow, "mul", checked_mul, *);
-    num_slow_path!(modulo_slow, "modulo", checked_rem, %);
+    num_slow_path!(sub_slow, "sub", checked_sub, |a: &BigNum, b| Some(a.sub(b)), -);
+    num_slow_path!(mul_slow, "mul", checked_mul, |a: &BigNum, b| Some(a.mul(b)), *);
+    num_slow_path!(modulo_slow, "modulo", checked_rem, |a: &BigNum, b| a.div_rem(b).map(|(_, r)| r), %);
 
-    int_slow_path!(div_int_slow, "div_int", |a: i64, b: i64| a.checked_div(b));
-    int_slow_path!(bit_and_slow, "bit_and", |a: i64, b: i64| Some(a & b));
-    int_slow_path!(bit_or_slow, "bit_or", |a: i64, b: i64| Some(a | b));
-    int_slow_path!(bit_xor_slow, "bit_xor", |a: i64, b: i64| Some(a ^ b));
-    int_slow_path!(lshift_slow, "lshift", |a: i64, b: i64| Some(a << b));
-    int_slow_path!(rshift_slow, "rshift", |a: i64, b: i64| Some(a >> b));
+    int_slow_path!(div_int_slow, "div_int",
+        |a: i64, b: i64| a.checked_div(b),
+        |a: &BigNum, b| a.div_rem(b).map(|(q, _)| q)
+    );
+    int_slow_path!(bit_and_slow, "bit_and", |a: i64, b: i64| Some(a & b), |a: &BigNum, b| Some(a.bit_and(b)));
+    int_slow_path!(bit_or_slow, "bit_or", |a: i64, b: i64| Some(a | b), |a: &BigNum, b| Some(a.bit_or(b)));
+    int_slow_path!(bit_xor_slow, "bit_xor", |a: i64, b: i64| Some(a ^ b), |a: &BigNum, b| Some(a.bit_xor(b)));
+
+    #[cold]
+    fn lshift_slow(&mut self, v0: Value, v1: Value) -> Result<Value, String>
+    {
+        let (num, shift) = shift_operands("lshift", v0, v1)?;
+
+        if !num.is_zero() && num.num_bits().saturating_add(shift) > MAX_SHIFT_BITS {
+            return Err(format!("integer too large in lshift, shifting by {} bits", shift));
+        }
+
+        Ok(self.bigint(num.shl(shift)))
+    }
+
+    #[cold]
+    fn rshift_slow(&mut self, v0: Value, v1: Value) -> Result<Value, String>
+    {
+        let (num, shift) = shift_operands("rshift", v0, v1)?;
+        Ok(self.bigint(num.shr(shift)))
+    }
 
     /// Call a host function
     /// Kept out of line so its arity dispatch doesn't bloat the interpreter loop
     #[inline(never)]
     fn call_host(&mut self, host_fn: &HostFn, argc: usize) -> Result<(), String>
     {
-        macro_rules! pop {
-            () => { self.stack.pop().unwrap() }
-        }
-
         macro_rules! push {
             ($val: expr) => { self.stack.push($val) }
         }
@@ -1065,6 +2035,14 @@ impl Actor
             ));
         }
 
+        // The arguments stay on the stack until the call is done, so that
+        // a call that parks the actor can be made again with them
+        let args_idx = self.stack.len() - argc;
+
+        macro_rules! arg {
+            ($idx: expr) => { self.stack[args_idx + $idx] }
+        }
+
         let result = match host_fn.f
         {
             FnPtr::Fn0(fun) => {
@@ -1072,87 +2050,77 @@ impl Actor
             }
 
             FnPtr::Fn1(fun) => {
-                let a0 = pop!();
-                fun(self, a0)
+                fun(self, arg!(0))
             }
 
             FnPtr::Fn2(fun) => {
-                let a1 = pop!();
-                let a0 = pop!();
-                fun(self, a0, a1)
+                fun(self, arg!(0), arg!(1))
             }
 
             FnPtr::Fn3(fun) => {
-                let a2 = pop!();
-                let a1 = pop!();
-                let a0 = pop!();
-                fun(self, a0, a1, a2)
+                fun(self, arg!(0), arg!(1), arg!(2))
             }
 
             FnPtr::Fn4(fun) => {
-                let a3 = pop!();
-                let a2 = pop!();
-                let a1 = pop!();
-                let a0 = pop!();
-                fun(self, a0, a1, a2, a3)
+                fun(self, arg!(0), arg!(1), arg!(2), arg!(3))
             }
 
             FnPtr::Fn5(fun) => {
-                let a4 = pop!();
-                let a3 = pop!();
-                let a2 = pop!();
-                let a1 = pop!();
-                let a0 = pop!();
-                fun(self, a0, a1, a2, a3, a4)
+                fun(self, arg!(0), arg!(1), arg!(2), arg!(3), arg!(4))
             }
 
             FnPtr::Fn8(fun) => {
-                let a7 = pop!();
-                let a6 = pop!();
-                let a5 = pop!();
-                let a4 = pop!();
-                let a3 = pop!();
-                let a2 = pop!();
-                let a1 = pop!();
-                let a0 = pop!();
-                fun(self, a0, a1, a2, a3, a4, a5, a6, a7)
+                fun(self, arg!(0), arg!(1), arg!(2), arg!(3), arg!(4), arg!(5), arg!(6), arg!(7))
             }
         };
 
         match result {
-            Ok(v) => { push!(v); Ok(()) },
+            Ok(_) if self.wait.is_some() => Ok(()),
+            Ok(v) => {
+                self.stack.truncate(args_idx);
+                push!(v);
+                Ok(())
+            }
             Err(e) => Err(format!("error during call to host function `{}`:\n{}", host_fn.name, e)),
         }
     }
 
-    /// Report a runtime error, printing the message along with a stack
-    /// trace, then terminate the execution. The instruction name is empty
-    /// for errors that don't come from executing an instruction.
-    ///
-    /// Marked cold so that the error paths in the interpreter loop, which
-    /// call this at many sites, stay out of the way of the hot code
-    #[cold]
-    #[inline(never)]
-    fn report_error(&self, insn_name: &str, msg: &str) -> !
+    /// Format a stack trace, from the top frame to the bottom one. Each
+    /// frame shows the position it is executing at: the call site of the
+    /// frame above it, or `fault_pc` for the top frame, when known.
+    fn stack_trace(&self, fault_pc: Option<usize>) -> String
     {
-        eprintln!();
+        let mut out = String::new();
+        let mut cur_pc = fault_pc;
+
+        // Only the frames at either end of a very deep stack are shown
+        let num_frames = self.frames.len();
+        let elided = if num_frames > 2 * TRACE_EDGE_FRAMES {
+            TRACE_EDGE_FRAMES..(num_frames - TRACE_EDGE_FRAMES)
+        } else {
+            0..0
+        };
 
-        if insn_name != "" {
-            eprintln!("Runtime error while executing `{}` instruction:", insn_name);
-        }
+        // For each stack frame, from top to bottom
+        for (idx, frame) in self.frames.iter().rev().enumerate() {
+            let pos = cur_pc.and_then(|pc| self.insn_pos.get(pc)).copied();
 
-        // Print the error message to standard error
-        eprintln!("{}", msg);
-        eprintln!();
+            // The call instruction precedes the return address
+            cur_pc = frame.ret_addr.checked_sub(1);
+
+            if elided.contains(&idx) {
+                if idx == elided.start {
+                    out.push_str(&format!("... {} more frames ...\n", elided.len()));
+                }
+                continue;
+            }
 
-        // For each stack frame, from top to bottom
-        for frame in self.frames.clone().into_iter().rev() {
             // A frame we can't identify shouldn't keep us from
             // reporting the error that got us here
             let fun_id = match frame.fun.to_fun_id() {
                 Some(id) => id,
                 None => {
-                    eprintln!("<unknown function>");
+                    out.push_str("<unknown function>\n");
                     continue;
                 }
             };
@@ -1172,22 +2140,228 @@ impl Actor
                 fun_name
             };
 
-            eprintln!("{}", fun_name);
-            eprintln!("  defined at {}", fun_pos);
+            out.push_str(&format!("{}\n", fun_name));
+            out.push_str(&format!("  defined at {}\n", fun_pos));
+
+            if let Some(pos) = pos {
+                out.push_str(&format!("  at {}\n", pos));
+            }
+        }
+
+        out
+    }
+
+    /// Report a runtime error, printing the message along with a stack
+    /// trace, then terminate the execution. The instruction name is empty
+    /// for errors that don't come from executing an instruction, and
+    /// `fault_pc` is the address of the faulting instruction, if any.
+    ///
+    /// Marked cold so that the error paths in the interpreter loop, which
+    /// call this at many sites, stay out of the way of the hot code
+    #[cold]
+    #[inline(never)]
+    fn report_error(&self, insn_name: &str, msg: &str, fault_pc: Option<usize>) -> !
+    {
+        let stack_trace = self.stack_trace(fault_pc);
+        self.print_error(insn_name, msg, &stack_trace);
+
+        // End execution of the actor. Unwinding this way skips the panic
+        // hook, since the error has already been reported.
+        panic::resume_unwind(Box::new(ActorFailure {
+            actor_id: self.actor_id,
+            message: msg.to_string(),
+            stack_trace,
+        }));
+    }
+
+    /// Message for a call that would make the stack deeper than allowed
+    fn stack_overflow_msg(&self) -> String
+    {
+        format!(
+            "stack overflow, call depth exceeds the maximum of {} frames",
+            self.max_stack_depth
+        )
+    }
+
+    /// Print an error message to standard error, followed by a stack trace
+    fn print_error(&self, insn_name: &str, msg: &str, stack_trace: &str)
+    {
+        eprintln!();
+
+        if insn_name != "" {
+            eprintln!("Runtime error while executing `{}` instruction:", insn_name);
+        }
+
+        // Print the error message to standard error
+        eprintln!("{}", msg);
+        eprintln!();
+
+        eprint!("{}", stack_trace);
+    }
+
+    /// Raise a runtime error at the instruction before `pc`. If a try
+    /// block is active, the error becomes an Error object which is
+    /// thrown to it, and the pc and base pointer of its catch clause
+    /// are returned. Otherwise the error is reported and execution ends.
+    #[cold]
+    #[inline(never)]
+    fn raise_error(&mut self, insn_name: &str, msg: String, pc: usize) -> (usize, usize)
+    {
+        if self.handlers.is_empty() {
+            self.report_error(insn_name, &msg, Some(pc - 1));
+        }
+
+        let pos = self.insn_pos[pc - 1];
+        let err = self.new_error(&msg, pos);
+        self.unwind(err)
+    }
+
+    /// Allocate an Error object with a message and source position
+    fn new_error(&mut self, msg: &str, pos: SrcPos) -> Value
+    {
+        let pos = pos.to_string();
+        let num_slots = self.get_num_slots(ERROR_ID);
+
+        // Make room for all three allocations up front so that
+        // none of them can trigger a collection
+        self.gc_check(
+            Object::alloc_size(num_slots) +
+            Str::alloc_size(msg.len()) +
+            Str::alloc_size(pos.len()) +
+            16,
+            &mut []
+        );
+
+        let err = Object::new(ERROR_ID, num_slots, &mut self.alloc);
+        let msg = Str::new(msg, &mut self.alloc);
+        let pos = Str::new(&pos, &mut self.alloc);
+        self.set_field(err, "message", msg);
+        self.set_field(err, "pos", pos);
+        err
+    }
+
+    /// Have the frame just pushed for a call in tail position take the
+    /// place of its caller's frame, so that chains of tail calls run in
+    /// constant stack space. The caller's frame stays if a try block it
+    /// started is still active. Returns the base pointer of the callee.
+    fn reuse_frame(&mut self, bp: usize) -> usize
+    {
+        let num_frames = self.frames.len();
+
+        if let Some(handler) = self.handlers.last() {
+            if handler.num_frames >= num_frames - 1 {
+                return bp;
+            }
         }
 
-        // End program execution
-        panic!();
+        let callee = self.frames[num_frames - 1];
+        let caller = self.frames[num_frames - 2];
+
+        // Move the callee's arguments and locals down over the caller's
+        let dst_idx = callee.prev_bp - caller.argc as usize;
+        let src_idx = bp - callee.argc as usize;
+        self.stack.drain(dst_idx..src_idx);
+
+        // The callee returns to where the caller would have
+        self.frames.pop();
+        self.frames[num_frames - 2] = StackFrame {
+            prev_bp: caller.prev_bp,
+            ret_addr: caller.ret_addr,
+            ..callee
+        };
+
+        bp - (src_idx - dst_idx)
+    }
+
+    /// Unwind the stack to the innermost exception handler and push the
+    /// thrown value. Returns the pc and base pointer of the catch clause.
+    fn unwind(&mut self, val: Value) -> (usize, usize)
+    {
+        let handler = self.handlers.pop().unwrap();
+        self.frames.truncate(handler.num_frames);
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(val);
+        (handler.catch_pc, handler.bp)
+    }
+
+    /// Check that the program was granted a permission. Host functions
+    /// call this before doing anything that needs one.
+    pub fn check_permission(&self, perm: Permission) -> Result<(), String>
+    {
+        self.vm.lock().unwrap().permissions.check(perm)
+    }
+
+    /// Run the unit function of a REPL input. Global slots declared
+    /// by the input are added first, the others keep their values.
+    pub fn call_repl_unit(&mut self, fun_id: FunId) -> Value
+    {
+        let num_globals = self.vm.lock().unwrap().prog.num_globals as usize;
+        self.globals.resize(num_globals, Value::UNDEF);
+        self.call(Value::fun(fun_id), &[])
+    }
+
+    /// Discard the execution state left behind by a call that ended in
+    /// a runtime error, so that the REPL can keep using this actor
+    pub fn reset_stack(&mut self)
+    {
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
     }
 
-    /// Call and execute a function in this actor
+    /// Call and execute a function in this actor. Host functions can
+    /// use this to call back into the program, in which case the frames
+    /// of the caller stay below those of the function being called.
     pub fn call(&mut self, fun: Value, args: &[Value]) -> Value
     {
-        assert!(self.stack.len() == 0);
-        assert!(self.frames.len() == 0);
+        // What the function returns to
+        let base_stack = self.stack.len();
+        let base_frames = self.frames.len();
+
+        // Errors can't be caught across a call from a host function,
+        // so the try blocks of the caller are set aside until we return
+        let caller_handlers = std::mem::take(&mut self.handlers);
+
+        // The host function making the call can't be resumed,
+        // so the actor can't be suspended until we return
+        let may_park = std::mem::replace(&mut self.may_park, false);
+
+        let (pc, bp) = self.enter(fun, args);
+        let ret_val = self.exec(pc, bp, base_stack, base_frames, caller_handlers);
+
+        self.may_park = may_park;
+        ret_val.expect("actor suspended in a call from a host function")
+    }
+
+    /// Start running the function of a scheduled actor, which can be
+    /// suspended while it waits. Returns None if it was suspended.
+    pub fn start(&mut self, fun: Value, args: &[Value]) -> Option<Value>
+    {
+        self.may_park = true;
+        let (pc, bp) = self.enter(fun, args);
+        self.exec(pc, bp, 0, 0, Vec::new())
+    }
+
+    /// Resume a scheduled actor where it was suspended.
+    /// Returns None if it was suspended again.
+    pub fn resume(&mut self) -> Option<Value>
+    {
+        // An actor that was parked retries the operation it was
+        // blocked in, which takes the wait it left
+        if let Some(Wait::Yield) = self.wait {
+            self.wait = None;
+        }
 
+        let (pc, bp) = self.suspended_at.take().unwrap();
+        self.exec(pc, bp, 0, 0, Vec::new())
+    }
+
+    /// Push a frame for a call to a function, with its arguments and locals
+    /// on the stack. Returns the address and base pointer it starts at.
+    fn enter(&mut self, fun: Value, args: &[Value]) -> (usize, usize)
+    {
         if fun.to_fun_id().is_none() {
-            self.report_error("", &format!("expected function value but got {:?}", fun));
+            self.report_error("", &format!("expected function value but got {:?}", fun), None);
         }
 
         // Push the arguments on the stack. Compiling below can collect,
@@ -1199,14 +2373,17 @@ impl Actor
         // Get a compiled address for this function
         let mut fun = fun;
         let fun_entry = self.get_compiled_fun(&mut fun);
-        let mut pc = fun_entry.entry_pc;
 
         if args.len() != fun_entry.num_params {
             self.report_error("", &format!(
                 "function takes {} argument(s) but was called with {}",
                 fun_entry.num_params,
                 args.len()
-            ));
+            ), None);
+        }
+
+        if self.frames.len() >= self.max_stack_depth {
+            self.report_error("", &self.stack_overflow_msg(), None);
         }
 
         // Push a new stack frame
@@ -1218,11 +2395,27 @@ impl Actor
         });
 
         // The base pointer will point at the first local
-        let mut bp = self.stack.len();
+        let bp = self.stack.len();
 
         // Allocate stack slots for the local variables
         self.stack.resize(self.stack.len() + fun_entry.num_locals, Value::NIL);
 
+        (fun_entry.entry_pc, bp)
+    }
+
+    /// Run the interpreter loop until the frame at `base_frames` returns,
+    /// restoring the stack and handlers to what they were before the call.
+    /// Returns None if the actor was suspended, which only the outermost
+    /// loop of a scheduled actor can be.
+    fn exec(
+        &mut self,
+        mut pc: usize,
+        mut bp: usize,
+        base_stack: usize,
+        base_frames: usize,
+        mut caller_handlers: Vec<Handler>,
+    ) -> Option<Value>
+    {
         macro_rules! pop {
             () => { self.stack.pop().unwrap() }
         }
@@ -1327,8 +2520,41 @@ impl Actor
         }
 
         // Set up a new frame for a function call
+        // Raise a stack overflow error if another frame would make the
+        // call stack deeper than allowed
+        macro_rules! check_depth {
+            () => {
+                if self.frames.len() >= self.max_stack_depth {
+                    error!("{}", self.stack_overflow_msg());
+                }
+            };
+        }
+
+        // Loops and tail calls are where a scheduled actor uses up its
+        // time slice, and gives the others waiting to run a turn
+        macro_rules! use_slice {
+            () => {
+                if self.may_park {
+                    self.slice_left -= 1;
+
+                    if self.slice_left == 0 {
+                        self.slice_left = TIME_SLICE;
+                        self.wait = Some(Wait::Yield);
+                        self.suspended_at = Some((pc, bp));
+                        return None;
+                    }
+                }
+            };
+        }
+
         macro_rules! call_fun {
-            ($fun: expr, $argc: expr) => {{
+            ($fun: expr, $argc: expr) => {
+                call_fun!($fun, $argc, false)
+            };
+
+            ($fun: expr, $argc: expr, $tail: expr) => {{
+                self.check_killed(pc);
+
                 if $argc as usize > self.stack.len() - bp {
                     error!("not enough call arguments on stack");
                 }
@@ -1344,6 +2570,16 @@ impl Actor
                         Some(f) => {
                             match self.call_host(f, $argc.into()) {
                                 Err(msg) => error!("{}", msg),
+
+                                // The host function has to wait, so the actor is
+                                // suspended, and the call is made again once it
+                                // is resumed
+                                Ok(()) if self.wait.is_some() => {
+                                    self.stack.push(fun_val);
+                                    self.suspended_at = Some((pc - 1, bp));
+                                    return None;
+                                }
+
                                 Ok(()) => continue
                             }
                         }
@@ -1355,17 +2591,24 @@ impl Actor
                 let fun_entry = self.get_compiled_fun(&mut fun_val);
 
                 if $argc as usize != fun_entry.num_params {
-                    let vm = self.vm.lock().unwrap();
-                    let fun = &vm.prog.funs[&fun_id];
+                    // The VM lock is released before raising the error,
+                    // since reporting it needs the lock again
+                    let (fun_name, fun_pos) = {
+                        let vm = self.vm.lock().unwrap();
+                        let fun = &vm.prog.funs[&fun_id];
+                        (fun.name.clone(), fun.pos)
+                    };
                     error!(
                         "incorrect argument count in call to function \"{}\", defined at {}, received {} arguments, expected {}",
-                        fun.name,
-                        fun.pos,
+                        fun_name,
+                        fun_pos,
                         $argc,
                         fun_entry.num_params
                     );
                 }
 
+                check_depth!();
+
                 self.frames.push(StackFrame {
                     argc: $argc,
                     fun: fun_val,
@@ -1380,19 +2623,26 @@ impl Actor
                 // Allocate stack slots for the local variables
                 self.stack.resize(self.stack.len() + fun_entry.num_locals, Value::NIL);
 
+                if $tail {
+                    bp = self.reuse_frame(bp);
+                    use_slice!();
+                }
+
                 fun_entry
             }}
         }
 
         // Handle a runtime error
-        // Print debug information including a stack trace
+        // Jump to the innermost catch clause if there is one, otherwise
+        // print debug information including a stack trace
         // and terminate the execution
         macro_rules! error {
             ($insn_name: literal, $format_str:literal $(, $arg:expr)* $(,)?) => {{
                 // The message is formatted first because the arguments may
                 // need to borrow the actor
                 let msg = format!($format_str $(, $arg)*);
-                self.report_error($insn_name, &msg);
+                (pc, bp) = self.raise_error($insn_name, msg, pc);
+                continue;
             }};
 
             ($format_str:literal $(, $arg:expr)* $(,)?) => {
@@ -1529,7 +2779,7 @@ impl Actor
                 }
 
                 // Integer division
-                // Division by zero will cause a panic (this is intentional)
+                // Division by zero raises a catchable error
                 Insn::div_int => {
                     let v1 = pop!();
                     let v0 = pop!();
@@ -1549,7 +2799,7 @@ impl Actor
                     push!(r);
                 }
 
-                // Division by zero will cause a panic (this is intentional)
+                // Modulo by zero raises a catchable error
                 Insn::modulo => {
                     let v1 = pop!();
                     let v0 = pop!();
@@ -1680,12 +2930,14 @@ impl Actor
                 // Set a closure slot
                 Insn::clos_set { idx } => {
                     let val = pop!();
-                    let clos = pop!();
+                    let clos_val = pop!();
 
-                    match clos.to_clos() {
+                    match clos_val.to_clos() {
                         Some(clos) => clos.set(idx as usize, val),
                         None => error!("clos_set", "expected closure")
                     }
+
+                    self.write_barrier(clos_val, val);
                 }
 
                 // Get a closure slot for the function currently executing
@@ -1724,6 +2976,8 @@ impl Actor
                         Some(p_cell) => *p_cell = val,
                         None => error!("cell_set", "expected cell")
                     };
+
+                    self.write_barrier(cell, val);
                 }
 
                 // Get the value stored in a mutable cell
@@ -1752,21 +3006,21 @@ impl Actor
                     let mut val = pop!();
                     let mut obj = pop!();
 
-                    if let Some(obj) = obj.to_obj() {
-                        if class_id == obj.class_id {
-                            obj.set(slot_idx as usize, val);
+                    if let Some(obj_ref) = obj.to_obj() {
+                        if class_id == obj_ref.class_id {
+                            obj_ref.set(slot_idx as usize, val);
                         } else {
-                            let slot_idx = match self.get_slot_idx(obj.class_id, field.as_str()) {
+                            let slot_idx = match self.get_slot_idx(obj_ref.class_id, field.as_str()) {
                                 Some(slot_idx) => slot_idx,
                                 None => error!(
                                     "set_field",
                                     "class `{}` has no field `{}`, known fields are: {}",
-                                    self.get_class_name(obj.class_id),
+                                    self.get_class_name(obj_ref.class_id),
                                     field.as_str(),
-                                    self.get_field_names(obj.class_id),
+                                    self.get_field_names(obj_ref.class_id),
                                 )
                             };
-                            let class_id = obj.class_id;
+                            let class_id = obj_ref.class_id;
 
                             // Update the cache
                             self.insns[pc - 1] = Insn::set_field {
@@ -1775,8 +3029,10 @@ impl Actor
                                 slot_idx: slot_idx as u32,
                             };
 
-                            obj.set(slot_idx, val);
+                            obj_ref.set(slot_idx, val);
                         }
+
+                        self.write_barrier(obj, val);
                     }
                     else if obj.is_dict() {
                         let alloc_size = obj.as_dict().will_allocate();
@@ -1791,6 +3047,7 @@ impl Actor
                         );
 
                         obj.as_dict().set(field.as_string() as *const Str, val, &mut self.alloc);
+                        self.remember(obj);
                     }
                     else {
                         error!("set_field", "set_field on non-object/dict value")
@@ -1835,6 +3092,9 @@ impl Actor
                 }
 
                 Insn::new_known_ctor { class_id, argc, num_slots, ctor_pc, fun_id, num_locals } => {
+                    self.check_killed(pc);
+                    check_depth!();
+
                     let num_slots = num_slots as usize;
 
                     self.gc_check(
@@ -1942,6 +3202,13 @@ impl Actor
                             }
                         }
 
+                        Tag::SharedByteArray => {
+                            match field.as_str() {
+                                "len" => Value::fixnum(obj.as_sba().num_bytes() as i64),
+                                _ => error!("get_field", "field not found on shared bytearray")
+                            }
+                        }
+
                         Tag::Str => {
                             match field.as_str() {
                                 "len" => Value::fixnum(obj.as_str().len() as i64),
@@ -1966,14 +3233,31 @@ impl Actor
                     let val = match arr.heap_tag() {
                         Tag::Array => {
                             let idx = unwrap_usize!(idx, "get_index");
+                            let len = arr.as_arr().len();
+                            if idx >= len {
+                                error!("get_index", "index {} out of bounds for array of length {}", idx, len);
+                            }
                             arr.as_arr().get(idx)
                         }
 
                         Tag::ByteArray => {
                             let idx = unwrap_usize!(idx, "get_index");
+                            let len = unwrap_ba!(arr, "get_index").num_bytes();
+                            if idx >= len {
+                                error!("get_index", "index {} out of bounds for bytearray of length {}", idx, len);
+                            }
                             Value::from(arr.as_ba().get::<u8>(idx))
                         }
 
+                        Tag::SharedByteArray => {
+                            let idx = unwrap_usize!(idx, "get_index");
+                            let len = arr.as_sba().num_bytes();
+                            if idx >= len {
+                                error!("get_index", "index {} out of bounds for shared bytearray of length {}", idx, len);
+                            }
+                            Value::from(arr.as_sba().get(idx))
+                        }
+
                         Tag::Dict => {
                             let key = unwrap_str!(idx, "get_index");
 
@@ -2001,15 +3285,34 @@ impl Actor
                     match arr.heap_tag() {
                         Tag::Array => {
                             let elem_idx = unwrap_usize!(idx, "set_index");
+                            let len = arr.as_arr().len();
+                            if elem_idx >= len {
+                                error!("set_index", "index {} out of bounds for array of length {}", elem_idx, len);
+                            }
                             arr.as_arr().set(elem_idx, val);
+                            self.write_barrier(arr, val);
                         }
 
                         Tag::ByteArray => {
                             let byte_idx = unwrap_usize!(idx, "set_index");
+                            let len = unwrap_ba!(arr, "set_index").num_bytes();
+                            if byte_idx >= len {
+                                error!("set_index", "index {} out of bounds for bytearray of length {}", byte_idx, len);
+                            }
                             let b = unwrap_u8!(val, "set_index");
                             arr.as_ba().set::<u8>(byte_idx, b);
                         }
 
+                        Tag::SharedByteArray => {
+                            let byte_idx = unwrap_usize!(idx, "set_index");
+                            let len = arr.as_sba().num_bytes();
+                            if byte_idx >= len {
+                                error!("set_index", "index {} out of bounds for shared bytearray of length {}", byte_idx, len);
+                            }
+                            let b = unwrap_u8!(val, "set_index");
+                            arr.as_sba().set(byte_idx, b);
+                        }
+
                         Tag::Dict => {
                             if !idx.is_string() {
                                 error!("set_index", "expected string key but got {:?}", idx);
@@ -2023,6 +3326,7 @@ impl Actor
 
                             let key = idx.as_string() as *const Str;
                             arr.as_dict().set(key, val, &mut self.alloc);
+                            self.remember(arr);
                         }
 
                         _ => error!("set_index", "expected array or dict type")
@@ -2085,33 +3389,194 @@ impl Actor
                     }
                 }
 
+                // Start a try block, recording where its catch clause is
+                Insn::try_begin { target_ofs } => {
+                    self.handlers.push(Handler {
+                        catch_pc: ((pc as i64) + (target_ofs as i64)) as usize,
+                        num_frames: self.frames.len(),
+                        stack_len: self.stack.len(),
+                        bp,
+                    });
+                }
+
+                Insn::try_end => {
+                    self.handlers.pop();
+                }
+
+                Insn::throw => {
+                    let val = pop!();
+
+                    if self.handlers.is_empty() {
+                        // Errors raised by the runtime and rethrown
+                        // are reported the same way as uncaught ones
+                        let msg = match val.to_obj() {
+                            Some(obj) if obj.class_id == ERROR_ID => {
+                                let msg = obj.get(self.get_slot_idx(ERROR_ID, "message").unwrap());
+                                match msg.to_str() {
+                                    Some(s) => s.to_string(),
+                                    None => format!("{:?}", msg),
+                                }
+                            }
+                            _ => format!("uncaught exception: {:?}", val)
+                        };
+
+                        self.report_error("", &msg, Some(pc - 1));
+                    }
+
+                    (pc, bp) = self.unwind(val);
+                }
+
                 // Unconditional jump
                 Insn::jump { target_ofs } => {
-                    pc = ((pc as i64) + (target_ofs as i64)) as usize
+                    if target_ofs < 0 {
+                        self.check_killed(pc);
+                    }
+
+                    pc = ((pc as i64) + (target_ofs as i64)) as usize;
+
+                    if target_ofs < 0 {
+                        use_slice!();
+                    }
+                }
+
+                Insn::iter_next { num_vals, target_ofs } => {
+                    let iter_val = self.stack[self.stack.len() - 2];
+                    let cursor = self.stack[self.stack.len() - 1].as_fixnum() as usize;
+
+                    if !iter_val.is_heap() {
+                        error!("iter_next", "value of type {:?} is not iterable", iter_val.type_of());
+                    }
+
+                    // Index or key, value and cursor for the next element
+                    let next = match iter_val.heap_tag() {
+                        Tag::Array => {
+                            let arr = iter_val.as_arr();
+                            if cursor < arr.len() {
+                                Some((Value::fixnum(cursor as i64), arr.get(cursor), cursor + 1))
+                            } else {
+                                None
+                            }
+                        }
+
+                        Tag::ByteArray => {
+                            let ba = iter_val.as_ba();
+                            if cursor < ba.num_bytes() {
+                                Some((Value::fixnum(cursor as i64), Value::from(ba.get::<u8>(cursor)), cursor + 1))
+                            } else {
+                                None
+                            }
+                        }
+
+                        // Strings are iterated one UTF-8 character at a time,
+                        // and the cursor is a byte index
+                        Tag::Str => {
+                            match iter_val.as_str()[cursor..].chars().next() {
+                                Some(ch) => {
+                                    let mut buf = [0; 4];
+                                    let ch = ch.encode_utf8(&mut buf);
+                                    self.gc_check(Str::alloc_size(ch.len()), &mut []);
+                                    let ch_val = Str::new(ch, &mut self.alloc);
+                                    Some((Value::fixnum(cursor as i64), ch_val, cursor + ch.len()))
+                                }
+                                None => None
+                            }
+                        }
+
+                        // Dicts are iterated in table order, and the cursor
+                        // is a slot index. A single loop variable gets the keys.
+                        Tag::Dict => {
+                            match iter_val.as_dict().next_entry(cursor) {
+                                Some((slot_idx, key, _)) if num_vals == 1 => Some((key, key, slot_idx + 1)),
+                                Some((slot_idx, key, val)) => Some((key, val, slot_idx + 1)),
+                                None => None
+                            }
+                        }
+
+                        Tag::Object if iter_val.as_obj().class_id == RANGE_ID => {
+                            // The program can construct ranges or change their
+                            // fields, so these are not known to be integers
+                            let range = iter_val.as_obj();
+                            let (start, end, step) = match (
+                                range.get(0).to_i64(),
+                                range.get(1).to_i64(),
+                                range.get(2).to_i64(),
+                            ) {
+                                (Some(start), Some(end), Some(step)) => (start, end, step),
+                                _ => error!("iter_next", "range start, end and step must be integers"),
+                            };
+
+                            let val = step.checked_mul(cursor as i64).and_then(|d| start.checked_add(d));
+                            match val {
+                                Some(v) if (step > 0 && v < end) || (step < 0 && v > end) => {
+                                    Some((Value::fixnum(cursor as i64), self.int64(v), cursor + 1))
+                                }
+                                _ => None
+                            }
+                        }
+
+                        // Other objects implement the iteration protocol
+                        Tag::Object => {
+                            if num_vals != 1 {
+                                error!("iter_next", "objects can only be iterated with one loop variable");
+                            }
+
+                            push!(Value::FALSE);
+                            continue;
+                        }
+
+                        _ => error!("iter_next", "value of type {:?} is not iterable", iter_val.type_of())
+                    };
+
+                    match next {
+                        Some((key, val, cursor)) => {
+                            let top = self.stack.len() - 1;
+                            self.stack[top] = Value::fixnum(cursor as i64);
+
+                            if num_vals == 2 {
+                                push!(key);
+                            }
+
+                            push!(val);
+                            push!(Value::TRUE);
+                        }
+
+                        None => {
+                            pc = ((pc as i64) + (target_ofs as i64)) as usize;
+                        }
+                    }
                 }
 
                 // call (arg0, arg1, ..., argN, fun)
-                Insn::call { argc } => {
+                Insn::call { argc } |
+                Insn::tail_call { argc } => {
+                    let tail = matches!(insn, Insn::tail_call { .. });
                     let fun = pop!();
-                    call_fun!(fun, argc);
+                    call_fun!(fun, argc, tail);
                 }
 
                 // call_direct (arg0, arg1, ..., argN)
-                Insn::call_direct { fun_id, argc } => {
+                Insn::call_direct { fun_id, argc } |
+                Insn::tail_call_direct { fun_id, argc } => {
+                    let tail = matches!(insn, Insn::tail_call_direct { .. });
                     let this_pc = pc - 1;
-                    let fun_entry = call_fun!(Value::fun(fun_id), argc);
+                    let fun_entry = call_fun!(Value::fun(fun_id), argc, tail);
 
                     // Patch the instruction to jump directly to the entry point next time
-                    self.insns[this_pc] = Insn::call_pc {
-                        entry_pc: fun_entry.entry_pc.try_into().unwrap(),
-                        fun_id,
-                        num_locals: fun_entry.num_locals.try_into().unwrap(),
-                        argc
+                    let entry_pc = fun_entry.entry_pc.try_into().unwrap();
+                    let num_locals = fun_entry.num_locals.try_into().unwrap();
+                    self.insns[this_pc] = if tail {
+                        Insn::tail_call_pc { entry_pc, fun_id, num_locals, argc }
+                    } else {
+                        Insn::call_pc { entry_pc, fun_id, num_locals, argc }
                     };
                 }
 
                 // call_pc (arg0, arg1, ..., argN)
-                Insn::call_pc { entry_pc, fun_id, num_locals, argc } => {
+                Insn::call_pc { entry_pc, fun_id, num_locals, argc } |
+                Insn::tail_call_pc { entry_pc, fun_id, num_locals, argc } => {
+                    self.check_killed(pc);
+                    check_depth!();
+
                     self.frames.push(StackFrame {
                         argc,
                         fun: Value::fun(fun_id),
@@ -2125,11 +3590,18 @@ impl Actor
 
                     // Allocate stack slots for the local variables
                     self.stack.resize(self.stack.len() + num_locals as usize, Value::NIL);
+
+                    if let Insn::tail_call_pc { .. } = insn {
+                        bp = self.reuse_frame(bp);
+                        use_slice!();
+                    }
                 }
 
                 // Call a method with a known name
                 // call_method (self, arg0, ..., argN)
-                Insn::call_method { name, argc } => {
+                Insn::call_method { name, argc } |
+                Insn::tail_call_method { name, argc } => {
+                    let tail = matches!(insn, Insn::tail_call_method { .. });
                     let self_val = self.stack[self.stack.len() - (1 + argc as usize)];
 
                     match self_val.to_obj() {
@@ -2148,24 +3620,25 @@ impl Actor
                             };
 
                             let this_pc = pc - 1;
-                            let fun_entry = call_fun!(Value::fun(fun_id), argc + 1);
+                            let fun_entry = call_fun!(Value::fun(fun_id), argc + 1, tail);
 
                             // Patch this instruction to avoid the method lookup
                             // next time. The name is read back out of the
                             // instruction rather than reused, since a collection
                             // in the call above would have moved the string.
                             let name = match self.insns[this_pc] {
-                                Insn::call_method { name, .. } => name,
+                                Insn::call_method { name, .. } |
+                                Insn::tail_call_method { name, .. } => name,
                                 _ => panic!("call_method instruction expected")
                             };
 
-                            self.insns[this_pc] = Insn::call_method_pc {
-                                name,
-                                argc: argc.try_into().unwrap(),
-                                class_id,
-                                entry_pc: fun_entry.entry_pc.try_into().unwrap(),
-                                fun_id,
-                                num_locals: fun_entry.num_locals.try_into().unwrap(),
+                            let argc = argc.try_into().unwrap();
+                            let entry_pc = fun_entry.entry_pc.try_into().unwrap();
+                            let num_locals = fun_entry.num_locals.try_into().unwrap();
+                            self.insns[this_pc] = if tail {
+                                Insn::tail_call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals }
+                            } else {
+                                Insn::call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals }
                             };
                         }
 
@@ -2186,10 +3659,12 @@ impl Actor
                             // Patch this instruction to avoid the method
                             // lookup next time. Bools and classes are left
                             // alone because their methods depend on more
-                            // than the type tag. Nothing has allocated since
-                            // the name was read, so it hasn't moved.
+                            // than the type tag. Tail calls are left alone too,
+                            // so that they stay tail calls if the guard of the
+                            // patched instruction were to fail. Nothing has
+                            // allocated since the name was read, so it hasn't moved.
                             let type_tag = self_val.type_of();
-                            if !matches!(type_tag, Type::Bool | Type::Class) {
+                            if !tail && !matches!(type_tag, Type::Bool | Type::Class) {
                                 self.insns[pc - 1] = Insn::call_method_host {
                                     name,
                                     argc,
@@ -2205,12 +3680,17 @@ impl Actor
                     };
                 }
 
-                Insn::call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals } => {
+                Insn::call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals } |
+                Insn::tail_call_method_pc { name, argc, class_id, entry_pc, fun_id, num_locals } => {
+                    let tail = matches!(insn, Insn::tail_call_method_pc { .. });
                     let self_val = self.stack[self.stack.len() - (1 + argc as usize)];
 
                     // Guard that self is an object with a matching class id
                     if let Some(obj) = self_val.to_obj() {
                         if obj.class_id == class_id {
+                            self.check_killed(pc);
+                            check_depth!();
+
                             let argc: u8 = argc.into();
                             self.frames.push(StackFrame {
                                 argc: argc + 1,
@@ -2226,6 +3706,11 @@ impl Actor
                             // Allocate stack slots for the local variables
                             self.stack.resize(self.stack.len() + num_locals as usize, Value::NIL);
 
+                            if tail {
+                                bp = self.reuse_frame(bp);
+                                use_slice!();
+                            }
+
                             // Proceed with the call
                             continue;
                         }
@@ -2233,9 +3718,11 @@ impl Actor
 
                     // The guard fail, deoptimize this instruction and try again
                     pc -= 1;
-                    self.insns[pc] = Insn::call_method {
-                        name,
-                        argc: argc.into(),
+                    let argc = argc.into();
+                    self.insns[pc] = if tail {
+                        Insn::tail_call_method { name, argc }
+                    } else {
+                        Insn::call_method { name, argc }
                     };
                 }
 
@@ -2267,13 +3754,23 @@ impl Actor
                     //println!("ret_val={:?}", ret_val);
 
                     // If this is a top-level return
-                    if self.frames.len() == 1 {
-                        self.stack.clear();
-                        self.frames.clear();
-                        return ret_val;
+                    if self.frames.len() == base_frames + 1 {
+                        self.stack.truncate(base_stack);
+                        self.frames.truncate(base_frames);
+                        self.handlers = std::mem::take(&mut caller_handlers);
+                        return Some(ret_val);
                     }
 
                     assert!(self.frames.len() > 0);
+
+                    // Drop the handlers installed by the returning function
+                    while let Some(handler) = self.handlers.last() {
+                        if handler.num_frames < self.frames.len() {
+                            break;
+                        }
+                        self.handlers.pop();
+                    }
+
                     let top_frame = self.frames.pop().unwrap();
 
                     // Pop all local variables and arguments
@@ -2296,10 +3793,154 @@ impl Actor
 }
 
 #[derive(Clone)]
-struct ActorTx
+pub struct ActorTx
 {
     sender: mpsc::SyncSender<Message>,
     msg_alloc: Weak<Mutex<Alloc>>,
+
+    // Task to wake up when a message is sent, for scheduled actors
+    task: Option<Arc<Task>>,
+
+    status: Arc<ActorStatus>,
+}
+
+/// State of an actor that other actors can see, or change
+#[derive(Default)]
+pub struct ActorStatus
+{
+    // Set to have the actor end the next time it checks
+    killed: AtomicBool,
+
+    // Number of messages sent to the actor and not yet received
+    num_queued: AtomicUsize,
+
+    // Scheduled actors parked until the mailbox has room
+    blocked_senders: Mutex<BlockedSenders>,
+}
+
+#[derive(Default)]
+struct BlockedSenders
+{
+    tasks: Vec<Arc<Task>>,
+
+    // Set once the actor is gone, after which no one waits for room
+    receiver_gone: bool,
+}
+
+impl ActorStatus
+{
+    /// Have a parked sender woken up once messages are taken out of the
+    /// mailbox. Returns false if the actor is gone, and never will take
+    /// any out.
+    fn block_sender(&self, task: &Arc<Task>) -> bool
+    {
+        let mut senders = self.blocked_senders.lock().unwrap();
+
+        if senders.receiver_gone {
+            return false;
+        }
+
+        senders.tasks.push(task.clone());
+        true
+    }
+
+    /// Wake up the senders waiting for room in the mailbox
+    fn wake_senders(&self)
+    {
+        let tasks = std::mem::take(&mut self.blocked_senders.lock().unwrap().tasks);
+
+        for task in tasks {
+            task.wake();
+        }
+    }
+}
+
+impl Drop for Actor
+{
+    /// Senders still waiting on the actor find out that it is gone
+    fn drop(&mut self)
+    {
+        self.status.blocked_senders.lock().unwrap().receiver_gone = true;
+        self.status.wake_senders();
+    }
+}
+
+impl ActorTx
+{
+    /// Whether the mailbox has room for another message. Every sender
+    /// holds the message allocator lock while it sends, and the receiver
+    /// only takes messages out, so this stays true until the lock is let
+    /// go of, and a send made before then doesn't block.
+    pub fn has_room(&self, msg_alloc: &Alloc) -> bool
+    {
+        self.status.num_queued.load(Ordering::Acquire) < MSG_QUEUE_SIZE &&
+        msg_alloc.bytes_used() <= MSG_BACKLOG_LIMIT
+    }
+
+    /// Queue a message, and wake the receiver up if it is parked
+    fn send(&self, msg: Message) -> Result<(), ()>
+    {
+        self.send_or_return(msg).map_err(|_| ())
+    }
+
+    /// Queue a message, or give it back if the receiver is gone
+    fn send_or_return(&self, msg: Message) -> Result<(), Message>
+    {
+        // Counted before the message can be received and uncounted
+        self.status.num_queued.fetch_add(1, Ordering::Relaxed);
+
+        let res = self.sender.send(msg).map_err(|err| err.0);
+
+        if res.is_err() {
+            self.status.num_queued.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        if let Some(task) = &self.task {
+            task.wake();
+        }
+
+        res
+    }
+
+    /// Queue a message already in the receiver's message allocator,
+    /// without blocking if the queue is full
+    pub fn try_send_nocopy(&self, msg: Value, size: usize) -> Result<(), mpsc::TrySendError<()>>
+    {
+        self.status.num_queued.fetch_add(1, Ordering::Relaxed);
+
+        let msg = Message { sender: 0, msg, size, moved: None };
+        let res = self.sender.try_send(msg).map_err(|err| match err {
+            mpsc::TrySendError::Full(_) => mpsc::TrySendError::Full(()),
+            mpsc::TrySendError::Disconnected(_) => mpsc::TrySendError::Disconnected(()),
+        });
+
+        if res.is_err() {
+            self.status.num_queued.fetch_sub(1, Ordering::Relaxed);
+        } else if let Some(task) = &self.task {
+            task.wake();
+        }
+
+        res
+    }
+}
+
+/// Send an ActorError message for a failed actor. The message is built
+/// directly in the receiver's message allocator, since the heap of the
+/// actor that failed may have no room left for it.
+fn send_failure(actor_tx: &ActorTx, failure: &ActorFailure)
+{
+    let alloc_rc = match actor_tx.msg_alloc.upgrade() {
+        Some(rc) => rc,
+        None => return, // Receiver is terminated
+    };
+    let mut msg_alloc = alloc_rc.lock().unwrap();
+
+    let bytes_before = msg_alloc.bytes_used();
+    let msg = failure.to_value(&mut msg_alloc);
+    let size = msg_alloc.bytes_used() - bytes_before;
+
+    // Queue the message while holding the allocator lock, as Actor::send does
+    let _ = actor_tx.send(Message { sender: failure.actor_id, msg, size, moved: None });
 }
 
 pub struct VM
@@ -2310,12 +3951,34 @@ pub struct VM
     // Next actor id to assign
     next_actor_id: u64,
 
-    // Map from actor ids to thread join handles
-    threads: HashMap<u64, thread::JoinHandle<Value>>,
+    // Map from actor ids to the scheduler tasks running them
+    tasks: HashMap<u64, Arc<Task>>,
+
+    // Scheduler running every actor other than the main one
+    sched: Arc<Scheduler>,
 
     // Map from actor ids to message queue endpoints
     actor_txs: HashMap<u64, ActorTx>,
 
+    // Names actors registered themselves under, so that other actors
+    // can look them up without being passed their ids
+    actor_names: HashMap<String, u64>,
+
+    // Errors that ended actors which have not been joined yet
+    failures: HashMap<u64, ActorFailure>,
+
+    // Map from actor ids to the ids of the actors monitoring them
+    monitors: HashMap<u64, Vec<u64>>,
+
+    // Timers delivering messages to actors
+    pub timers: Arc<TimerService>,
+
+    // Permissions granted to the program
+    permissions: Permissions,
+
+    // Limits every actor runs under
+    limits: ActorLimits,
+
     // Reference to self
     // Needed to instantiate actors
     vm: Option<Arc<Mutex<VM>>>,
@@ -2334,8 +3997,15 @@ impl VM
         let vm = Self {
             prog,
             next_actor_id: 0,
-            threads: HashMap::default(),
+            tasks: HashMap::default(),
+            sched: Arc::new(Scheduler::new(Scheduler::default_threads())),
             actor_txs: HashMap::default(),
+            actor_names: HashMap::default(),
+            failures: HashMap::default(),
+            monitors: HashMap::default(),
+            timers: Arc::default(),
+            permissions: Permissions::default(),
+            limits: ActorLimits::default(),
             vm: None
         };
 
@@ -2349,17 +4019,21 @@ impl VM
     }
 
     // Create a new actor
-    pub fn new_actor(parent: &mut Actor, fun: Value, args: Vec<Value>) -> u64
+    // The actor runs under the limits of the VM, restricted further by
+    // those given here
+    pub fn new_actor(parent: &mut Actor, fun: Value, args: Vec<Value>, limits: ActorLimits) -> u64
     {
         // Assign an actor id
         let mut vm_ref = parent.vm.lock().unwrap();
         let actor_id = vm_ref.next_actor_id;
         let parent_id = parent.actor_id;
+        let limits = vm_ref.limits.restrict(limits);
+        let sched = vm_ref.sched.clone();
         vm_ref.next_actor_id += 1;
         drop(vm_ref);
 
         // Create a message queue for the actor
-        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(1024);
+        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(MSG_QUEUE_SIZE);
 
         // Create an allocator to send messages to the actor
         let msg_alloc = Alloc::for_messages();
@@ -2369,11 +4043,12 @@ impl VM
         // allocator instead would cap them at its fixed size, and would
         // leave the actor referencing memory outside its own heap.
         // What we copy over cannot come out larger than the parent's
-        // heap, and the new heap is sized from the live data below, so
-        // this covers both
+        // heap and the bytes it keeps outside of it, and the new heap is
+        // sized from the live data below, so this covers both
         let mut alloc = Alloc::new();
+        let parent_bytes = parent.heap_bytes();
         let heap_bytes = std::cmp::max(
-            (parent.alloc.bytes_used() * 3) / 2,
+            (parent_bytes * 3) / 2,
             INIT_SIZE,
         );
         alloc.grow_reserve(heap_bytes);
@@ -2390,7 +4065,7 @@ impl VM
             &mut alloc,
             &mut str_table,
             &mut undo_log
-        );
+        ).into_heap();
         let fun = copier.forward(fun);
         for val in &mut globals {
             *val = copier.forward(*val);
@@ -2408,26 +4083,54 @@ impl VM
         // Wrap the message allocator in a shared mutex
         let msg_alloc = Arc::new(Mutex::new(msg_alloc));
 
+        let task = Task::new(sched, fun, args);
+
         // Info needed to send the actor a message
+        let status = Arc::new(ActorStatus::default());
         let actor_tx = ActorTx {
             sender: queue_tx,
             msg_alloc: Arc::downgrade(&msg_alloc),
+            status: status.clone(),
+            task: Some(task.clone()),
         };
 
-        // Spawn a new thread for the actor
-        let vm_mutex = parent.vm.clone();
-        let handle = thread::spawn(move || {
-            let mut actor = Actor::new(
-                actor_id,
-                Some(parent_id),
-                vm_mutex,
-                alloc,
-                msg_alloc,
-                queue_rx,
-                globals,
-            );
+        let mut actor = Actor::new(
+            actor_id,
+            Some(parent_id),
+            parent.vm.clone(),
+            alloc,
+            msg_alloc,
+            queue_rx,
+            globals,
+        );
+        actor.status = status;
+        actor.set_max_heap(limits.max_heap);
+        actor.max_stack_depth = limits.max_stack_depth.unwrap_or(DEFAULT_MAX_STACK_DEPTH);
+        actor.task = Some(task.clone());
+
+        // Make the actor reachable before it starts running, so that it
+        // can be sent messages and joined right away
+        let mut vm_ref = parent.vm.lock().unwrap();
+        vm_ref.actor_txs.insert(actor_id, actor_tx);
+        vm_ref.tasks.insert(actor_id, task.clone());
+        drop(vm_ref);
+
+        task.start(Box::new(actor));
 
-            let ret_val = actor.call(fun, &args);
+        actor_id
+    }
+
+    // Run a spawned actor, starting with the function it was given, until
+    // it is done or until it is suspended. Returns None if it was suspended.
+    pub fn run_actor(actor: &mut Actor, entry: Option<(Value, Vec<Value>)>) -> Option<Value>
+    {
+        // Errors end the actor by unwinding. Catching them here lets
+        // the actors joining and monitoring it learn what happened.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let ret_val = match entry {
+                Some((fun, args)) => actor.start(fun, &args)?,
+                None => actor.resume()?,
+            };
 
             // TODO: a possible solution here would be to copy heap return
             // values into our own message allocator, which will continue to
@@ -2442,56 +4145,216 @@ impl VM
                     "actor cannot return heap-allocated value of type {:?}, \
                     only primitive values can be returned",
                     ret_val.type_of()
-                ));
+                ), None);
             }
 
-            ret_val
-        });
+            Some(ret_val)
+        }));
 
-        // Store the join handles and queue endpoints on the VM
-        let mut vm_ref = parent.vm.lock().unwrap();
-        vm_ref.threads.insert(actor_id, handle);
-        vm_ref.actor_txs.insert(actor_id, actor_tx);
+        match result {
+            Ok(None) => None,
+            Ok(Some(ret_val)) => {
+                VM::actor_done(&actor.vm, actor.actor_id, None);
+                Some(ret_val)
+            }
+            Err(payload) => {
+                let failure = ActorFailure::from_panic(actor.actor_id, payload);
+                VM::actor_done(&actor.vm, actor.actor_id, Some(failure));
+                Some(Value::NIL)
+            }
+        }
+    }
+
+    // Record that an actor is done running, and tell the actors
+    // monitoring it if it failed
+    fn actor_done(vm: &Arc<Mutex<VM>>, actor_id: u64, failure: Option<ActorFailure>)
+    {
+        // An error can end execution while the VM is locked, which
+        // leaves it poisoned
+        vm.clear_poison();
+
+        let mut vm_ref = vm.lock().unwrap();
+        let watchers = vm_ref.monitors.remove(&actor_id).unwrap_or_default();
+
+        // Release the names the actor registered
+        vm_ref.actor_names.retain(|_, id| *id != actor_id);
+
+        let failure = match failure {
+            Some(failure) => failure,
+            None => return,
+        };
+
+        let watcher_txs: Vec<ActorTx> = watchers.iter().filter_map(|id| vm_ref.actor_txs.get(id).cloned()).collect();
+        vm_ref.failures.insert(actor_id, failure.clone());
         drop(vm_ref);
 
-        actor_id
+        for actor_tx in &watcher_txs {
+            send_failure(actor_tx, &failure);
+        }
     }
 
-    // Wait for an actor to produce a result and return it.
-    pub fn join_actor(vm: &Arc<Mutex<VM>>, tid: u64) -> Value
+    // Have an actor be sent an ActorError message if another one fails.
+    // If it has already failed, the message is sent right away.
+    pub fn monitor_actor(vm: &Arc<Mutex<VM>>, watcher_id: u64, actor_id: u64) -> Result<(), String>
     {
-        // Get the join handle, then release the VM lock
-        let mut vm = vm.lock().unwrap();
-        let handle = vm.threads.remove(&tid).unwrap();
-        vm.actor_txs.remove(&tid).unwrap();
-        drop(vm);
+        let mut vm_ref = vm.lock().unwrap();
+
+        if let Some(failure) = vm_ref.failures.get(&actor_id).cloned() {
+            let actor_tx = vm_ref.actor_txs[&watcher_id].clone();
+            drop(vm_ref);
+            send_failure(&actor_tx, &failure);
+            return Ok(());
+        }
+
+        if !vm_ref.tasks.contains_key(&actor_id) {
+            return Err(format!("no running actor with id {}", actor_id));
+        }
+
+        vm_ref.monitors.entry(actor_id).or_default().push(watcher_id);
+        Ok(())
+    }
+
+    // Register a name for an actor, which it keeps until it is done
+    // running or the name is unregistered. An actor can have several
+    // names, but a name can only belong to one actor at a time.
+    pub fn register_actor(vm: &Arc<Mutex<VM>>, actor_id: u64, name: &str) -> Result<(), String>
+    {
+        let mut vm_ref = vm.lock().unwrap();
+
+        match vm_ref.actor_names.get(name) {
+            Some(&id) if id != actor_id => {
+                Err(format!("actor name '{}' is already registered by actor {}", name, id))
+            }
+            _ => {
+                vm_ref.actor_names.insert(name.to_string(), actor_id);
+                Ok(())
+            }
+        }
+    }
+
+    // Get the id of the actor registered under a name, if any
+    pub fn lookup_actor(vm: &Arc<Mutex<VM>>, name: &str) -> Option<u64>
+    {
+        vm.lock().unwrap().actor_names.get(name).copied()
+    }
+
+    // Release a registered name. Returns false if it wasn't registered.
+    pub fn unregister_actor(vm: &Arc<Mutex<VM>>, name: &str) -> bool
+    {
+        vm.lock().unwrap().actor_names.remove(name).is_some()
+    }
+
+    // Have an actor end the next time it makes a call or loops around.
+    // Returns false if there is no such actor running.
+    pub fn kill_actor(vm: &Arc<Mutex<VM>>, actor_id: u64) -> Result<bool, String>
+    {
+        if actor_id == 0 {
+            return Err("the main actor can't be killed".into());
+        }
+
+        let vm_ref = vm.lock().unwrap();
+
+        if !vm_ref.is_alive(actor_id) {
+            return Ok(false);
+        }
+
+        // Wake the actor up if it is parked, so that it notices
+        let actor_tx = &vm_ref.actor_txs[&actor_id];
+        actor_tx.status.killed.store(true, Ordering::Relaxed);
+        if let Some(task) = &actor_tx.task {
+            task.wake();
+        }
+
+        Ok(true)
+    }
+
+    // Check whether an actor is still running
+    fn is_alive(&self, actor_id: u64) -> bool
+    {
+        if !self.actor_txs.contains_key(&actor_id) {
+            return false;
+        }
+
+        match self.tasks.get(&actor_id) {
+            Some(task) => !task.is_done(),
+            None => true,
+        }
+    }
+
+    pub fn actor_alive(vm: &Arc<Mutex<VM>>, actor_id: u64) -> bool
+    {
+        vm.lock().unwrap().is_alive(actor_id)
+    }
+
+    // Ids of the actors still running, in increasing order
+    pub fn actor_list(vm: &Arc<Mutex<VM>>) -> Vec<u64>
+    {
+        let vm_ref = vm.lock().unwrap();
+        let mut ids: Vec<u64> = vm_ref.actor_txs.keys().copied().filter(|id| vm_ref.is_alive(*id)).collect();
+        ids.sort();
+        ids
+    }
+
+    // Wait for an actor to produce a result and return it, or the
+    // error that ended it. A scheduled actor is parked while it waits,
+    // in which case nil stands in for the result. Fails if there is
+    // no actor to join, e.g. because it was already joined.
+    pub fn join_actor(actor: &mut Actor, tid: u64) -> Result<Result<Value, ActorFailure>, String>
+    {
+        if tid == actor.actor_id {
+            return Err("an actor cannot join itself".into());
+        }
+
+        // The actor may be unknown, or already joined
+        let task = match actor.vm.lock().unwrap().tasks.get(&tid) {
+            Some(task) => task.clone(),
+            None => return Err(format!("no actor with id {} to join", tid)),
+        };
+        actor.wait = None;
 
         // Note: there is no need to copy data when joining,
         // because the actor sending the data is done running
-        match handle.join() {
-            Ok(val) => val,
+        let ret_val = if actor.may_park {
+            match task.join_or_wake(actor.task.as_ref().unwrap()) {
+                Some(ret_val) => ret_val,
+                None => {
+                    actor.park(None);
+                    return Ok(Ok(Value::NIL));
+                }
+            }
+        } else {
+            task.wait_done()
+        };
 
-            // The actor reported its own error before dying, so there is
-            // nothing useful to add here
-            Err(_) => panic!("actor with id {} terminated with an error", tid)
+        // The actor can be sent messages until it is done running
+        let mut vm_ref = actor.vm.lock().unwrap();
+        vm_ref.tasks.remove(&tid);
+        vm_ref.actor_txs.remove(&tid);
+
+        match vm_ref.failures.remove(&tid) {
+            Some(failure) => Ok(Err(failure)),
+            None => Ok(Ok(ret_val)),
         }
     }
 
-    // Call a function in the main actor
-    pub fn call(vm: &mut Arc<Mutex<VM>>, fun_id: FunId, args: Vec<Value>) -> Value
+    // Create the main actor, which runs the program
+    pub fn main_actor(vm: &Arc<Mutex<VM>>) -> Actor
     {
         let vm_mutex = vm.clone();
 
         // Create a message queue for the actor
-        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(1024);
+        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(MSG_QUEUE_SIZE);
 
         // Create an allocator to send messages to the actor
         let msg_alloc = Arc::new(Mutex::new(Alloc::for_messages()));
 
         // Info needed to send the actor a message
+        let status = Arc::new(ActorStatus::default());
         let actor_tx = ActorTx {
             sender: queue_tx,
             msg_alloc: Arc::downgrade(&msg_alloc),
+            status: status.clone(),
+            task: None,
         };
 
         // Assign an actor id
@@ -2506,6 +4369,7 @@ impl VM
 
         // Initialize the global slots
         let globals = vec![Value::UNDEF; vm_ref.prog.num_globals as usize];
+        let limits = vm_ref.limits;
 
         drop(vm_ref);
 
@@ -2518,28 +4382,74 @@ impl VM
             queue_rx,
             globals,
         );
+        actor.status = status;
+        actor.set_max_heap(limits.max_heap);
+        actor.max_stack_depth = limits.max_stack_depth.unwrap_or(DEFAULT_MAX_STACK_DEPTH);
+
+        actor
+    }
 
+    // Call a function in the main actor
+    pub fn call(vm: &mut Arc<Mutex<VM>>, fun_id: FunId, args: Vec<Value>) -> Value
+    {
+        let mut actor = VM::main_actor(vm);
         actor.call(Value::fun(fun_id), &args)
     }
 
+    pub fn set_permissions(&mut self, permissions: Permissions)
+    {
+        self.permissions = permissions;
+    }
+
+    pub fn set_limits(&mut self, limits: ActorLimits)
+    {
+        self.limits = limits;
+    }
+
+    /// Set how many worker threads actors are run on. This has to be
+    /// done before any actor is spawned.
+    pub fn set_threads(&mut self, num_threads: usize)
+    {
+        assert!(self.tasks.is_empty());
+        self.sched = Arc::new(Scheduler::new(num_threads));
+    }
+
+    pub fn prog(&self) -> &Program
+    {
+        &self.prog
+    }
+
+    /// Give the REPL access to the program, which each input extends
+    pub fn prog_mut(&mut self) -> &mut Program
+    {
+        &mut self.prog
+    }
+
     // Compile every function in a program without running it, which is
-    // what --no-exec does to check that code generation works
-    pub fn compile_all(prog: Program)
+    // what --no-exec does to check that code generation works. Returns
+    // the actor holding the compiled code.
+    pub fn compile_all(prog: Program) -> Actor
     {
-        let fun_ids: Vec<FunId> = prog.funs.keys().copied().collect();
+        // Compile in order of function ids, so that the
+        // code comes out the same from one run to the next
+        let mut fun_ids: Vec<FunId> = prog.funs.keys().copied().collect();
+        fun_ids.sort_by_key(|id| usize::from(*id));
         let vm = VM::new(prog);
         let vm_mutex = vm.clone();
 
         // Create a message queue for the actor
-        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(1024);
+        let (queue_tx, queue_rx) = mpsc::sync_channel::<Message>(MSG_QUEUE_SIZE);
 
         // Create an allocator to send messages to the actor
         let msg_alloc = Arc::new(Mutex::new(Alloc::for_messages()));
 
         // Info needed to send the actor a message
+        let status = Arc::new(ActorStatus::default());
         let actor_tx = ActorTx {
             sender: queue_tx,
             msg_alloc: Arc::downgrade(&msg_alloc),
+            status: status.clone(),
+            task: None,
         };
 
         // Assign an actor id
@@ -2563,17 +4473,27 @@ impl VM
             queue_rx,
             globals,
         );
+        actor.status = status;
 
         for fun_id in fun_ids {
             actor.get_compiled_fun(&mut Value::fun(fun_id));
         }
+
+        actor
     }
 
     /// Send a message to an actor without copying it to its message allocator
     pub fn send_nocopy(&self, actor_id: u64, msg: Value, size: usize) -> Result<(), ()>
     {
         let actor_tx = self.actor_txs.get(&actor_id).ok_or(())?;
-        actor_tx.sender.send(Message { sender: 0, msg, size }).map_err(|_| ())
+        actor_tx.send(Message { sender: 0, msg, size, moved: None })
+    }
+
+    /// Get the handle used to send messages to an actor, so that
+    /// they can be sent without holding the VM lock
+    pub fn get_actor_tx(&self, actor_id: u64) -> Option<ActorTx>
+    {
+        self.actor_txs.get(&actor_id).cloned()
     }
 }
 
@@ -2590,6 +4510,11 @@ mod tests
         prog.resolve_syms().unwrap();
         let main_fn = prog.main_fn;
         let mut vm = VM::new(prog);
+
+        // These tests are about the language, not about what it may access
+        let mut permissions = Permissions::default();
+        permissions.set_allow_all();
+        vm.lock().unwrap().set_permissions(permissions);
         VM::call(&mut vm, main_fn, vec![])
     }
 
@@ -2931,10 +4856,11 @@ mod tests
     }
 
     #[test]
-    #[should_panic]
     fn int_overflow()
     {
-        eval("return 4611686018427387904 * 4;");
+        eval_eq("return 4611686018427387904 * 4 == 9223372036854775807 + 9223372036854775807 + 2;", Value::TRUE);
+        eval_eq("let x = 9223372036854775807 + 1; return x - 1 == 9223372036854775807;", Value::TRUE);
+        eval_eq("return (9223372036854775807 * 9223372036854775807).to_s() == '85070591730234615847396907784232501249';", Value::TRUE);
     }
 
     #[test]
@@ -3052,4 +4978,151 @@ mod tests
         eval_eq("return [] instanceof Array;", Value::TRUE);
         eval_eq("return {} instanceof Dict;", Value::TRUE);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn try_catch()
+    {
+        eval_eq("try { return 1; } catch (e) { return 2; }", Value::fixnum(1));
+        eval_eq("try { throw 3; } catch (e) { return e; }", Value::fixnum(3));
+        eval_eq("let var x = 0; try { x = 1; } catch (e) { x = 2; } return x;", Value::fixnum(1));
+        eval_eq("try { throw nil; return 1; } catch (e) { return 2; }", Value::fixnum(2));
+
+        // Nested try blocks
+        eval_eq("try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { return e; }", Value::fixnum(2));
+        eval_eq("try { try { let x = 1; } catch (e) {} throw 5; } catch (e) { return e; }", Value::fixnum(5));
+
+        // Throwing through stack frames
+        eval_eq("fun f(n) { if (n == 0) throw 7; return f(n - 1); } try { f(10); } catch (e) { return e; }", Value::fixnum(7));
+        eval_eq("fun f() { try { throw 1; } catch (e) { return e + 1; } } return f() + f();", Value::fixnum(4));
+        eval_eq("fun f() { let x = 1; try { return x; } catch (e) {} } try { f(); throw 2; } catch (e) { return e; }", Value::fixnum(2));
+
+        // Break and continue out of a try block
+        eval_eq("let var n = 0; for (let var i = 0; i < 10; ++i) { try { if (i == 5) break; n = n + 1; } catch (e) {} } try { throw n; } catch (e) { return e; }", Value::fixnum(5));
+        eval_eq("let var n = 0; for (let var i = 0; i < 10; ++i) { try { if (i % 2 == 0) continue; n = n + 1; } catch (e) {} } try { throw n; } catch (e) { return e; }", Value::fixnum(5));
+    }
+
+    #[test]
+    fn catch_runtime_errors()
+    {
+        // Errors raised by instructions
+        eval_eq("try { let x = 1 _/ 0; } catch (e) { return e instanceof Error; }", Value::TRUE);
+        eval_eq("try { let x = 5 _/ 0; } catch (e) { return e.message == 'division by zero in div_int'; }", Value::TRUE);
+        eval_eq("try { let v = {}.x; } catch (e) { return e instanceof Error; }", Value::TRUE);
+        eval_eq("try { let v = [1, 2][5]; } catch (e) { return e instanceof Error; }", Value::TRUE);
+        eval_eq("fun f(a) { return a; } try { f(1, 2); } catch (e) { return 1; }", Value::fixnum(1));
+
+        // Errors returned by host functions
+        eval_eq("try { 'abc'.char_at(5); } catch (e) { return e instanceof Error; }", Value::TRUE);
+
+        // The error carries the position of the faulting instruction
+        eval_eq("try {\n  let x = 1 _/ 0;\n} catch (e) { return e.pos == '@2:11'; }", Value::TRUE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn uncaught_throw()
+    {
+        eval("fun f() { throw 1; } try {} catch (e) {} f();");
+    }
+
+    #[test]
+    #[should_panic]
+    fn rethrow_error()
+    {
+        eval("try { let x = 1 _/ 0; } catch (e) { throw e; }");
+    }
+
+    #[test]
+    fn for_in()
+    {
+        eval_eq("let var s = 0; for (let x in [1, 2, 3]) s = s + x; return s;", Value::fixnum(6));
+        eval_eq("let var s = 0; for (let i, x in [1, 2, 3]) s = s + i * x; return s;", Value::fixnum(8));
+        eval_eq("let var s = 0; for (let x in []) s = s + 1; return s;", Value::fixnum(0));
+        eval_eq("let var s = 0; for (let k, v in {a: 1, b: 2}) s = s + v; return s;", Value::fixnum(3));
+        eval_eq("let var s = ''; for (let k in {a: 1}) s = s + k; return s == 'a';", Value::TRUE);
+        eval_eq("let var n = 0; for (let c in 'h\u{e9}llo') n = n + 1; return n;", Value::fixnum(5));
+        eval_eq("let var s = 0; for (let i in $range(0, 10, 3)) s = s + i; return s;", Value::fixnum(18));
+        eval_eq("let var s = 0; for (let i in $range(5, 0, -1)) s = s + i; return s;", Value::fixnum(15));
+        eval_eq("let var s = 0; for (let i in $range(0, 0, 1)) s = s + 1; return s;", Value::fixnum(0));
+
+        // Break, continue and return
+        eval_eq("let var s = 0; for (let x in [1, 2, 3, 4]) { if (x == 3) break; s = s + x; } return s;", Value::fixnum(3));
+        eval_eq("let var s = 0; for (let x in [1, 2, 3, 4]) { if (x == 3) continue; s = s + x; } return s;", Value::fixnum(7));
+        eval_eq("fun f() { for (let x in [1, 2, 3]) { if (x == 2) return x; } return 0; } return f();", Value::fixnum(2));
+
+        // Nested loops
+        eval_eq("let var s = 0; for (let x in [1, 2]) for (let y in [10, 20]) s = s + x * y; return s;", Value::fixnum(90));
+
+        // Closures capture the loop variable of each iteration
+        eval_eq("fun f() { let fs = []; for (let x in [1, 2, 3]) fs.push(|| x); return fs[0]() + fs[2](); } return f();", Value::fixnum(4));
+
+        // Iteration protocol for objects
+        eval_eq(
+            "class C { init(s) { s.n = 0; } next(s) { if (s.n == 3) return nil; s.n = s.n + 1; return s.n; } }
+            let var t = 0; for (let x in C()) t = t + x; return t;",
+            Value::fixnum(6)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn for_in_not_iterable()
+    {
+        eval("for (let x in 5) {}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn for_in_object_two_vars()
+    {
+        eval("class C { next(s) { return nil; } } for (let k, v in C()) {}");
+    }
+
+    #[test]
+    fn json()
+    {
+        eval_eq("return $json_parse('{\"a\": [1, 2.5, true, null]}').a[1];", flonum(2.5));
+        eval_eq("return $json_stringify($json_parse(' [1, {\"b\": \"c\"}] '), nil) == '[1,{\"b\":\"c\"}]';", Value::TRUE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn json_parse_malformed()
+    {
+        eval("$json_parse('[1, 2');");
+    }
+
+    #[test]
+    #[should_panic]
+    fn json_stringify_closure()
+    {
+        eval("let x = 1; $json_stringify(|| x, nil);");
+    }
+
+    #[test]
+    fn repl_inputs()
+    {
+        use crate::parser::parse_repl_input;
+        use crate::symbols::ReplEnv;
+
+        let vm = VM::new(Program::new());
+        let mut actor = VM::main_actor(&vm);
+        let mut env = ReplEnv::new();
+
+        let mut eval_input = |src: &str| {
+            let mut vm_ref = vm.lock().unwrap();
+            let prog = vm_ref.prog_mut();
+            let unit = parse_repl_input(src, prog).unwrap();
+            prog.resolve_repl_input(&mut env, &unit).unwrap();
+            drop(vm_ref);
+            actor.call_repl_unit(unit.unit_fn)
+        };
+
+        assert_eq!(eval_input("let var x = 3;"), Value::NIL);
+        assert_eq!(eval_input("fun f(a) { return a * x; }"), Value::NIL);
+        assert_eq!(eval_input("f(2);"), Value::from(6));
+        assert_eq!(eval_input("x = 5;"), Value::from(5));
+        assert_eq!(eval_input("class C { init(self) { self.v = f(1); } }"), Value::NIL);
+        assert_eq!(eval_input("C().v;"), Value::from(5));
+    }
+}
@@ -14,6 +14,7 @@ use crate::value::*;
 use crate::bytearray::ByteArray;
 use crate::str::Str;
 use crate::ast::UIEVENT_ID;
+use crate::permissions::Permission;
 use crate::*;
 
 // Global SDL state
@@ -84,6 +85,8 @@ pub fn window_create(
     _flags: Value
 ) -> Result<Value, String>
 {
+    actor.check_permission(Permission::Window)?;
+
     if actor.actor_id != 0 {
         panic!("window functions should only be called from the main actor");
     }